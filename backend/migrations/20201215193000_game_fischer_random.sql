@@ -0,0 +1,3 @@
+-- Games can start from a Fischer random position. This stores its index,
+-- classic games keep NULL.
+ALTER TABLE `game` ADD COLUMN `fischer_random` INTEGER;
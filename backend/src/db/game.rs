@@ -1,9 +1,10 @@
 use crate::db::Connection;
-use crate::{sync_match::SyncronizedMatch, ServerError};
+use crate::sync_match::{StampedAction, SyncronizedMatch};
+use crate::ServerError;
 
 /// Stores the game in the database as a new entry and updates the id
 pub async fn insert(game: &mut SyncronizedMatch, conn: &mut Connection) -> Result<(), ServerError> {
-    let action_history = serde_json::to_string(&game.actions)?;
+    let action_history = StampedAction::encode_history(&game.actions)?;
 
     let timer = if let Some(ref timer) = game.timer {
         Some(serde_json::to_string(timer)?)
@@ -11,10 +12,13 @@ pub async fn insert(game: &mut SyncronizedMatch, conn: &mut Connection) -> Resul
         None
     };
 
+    let fischer_random = game.fischer_random.map(i64::from);
+
     let id = sqlx::query!(
-        "insert into game (action_history, timer) values (?, ?)",
+        "insert into game (action_history, timer, fischer_random) values (?, ?, ?)",
         action_history,
-        timer
+        timer,
+        fischer_random
     )
     .execute(conn)
     .await?
@@ -29,7 +33,7 @@ pub async fn insert(game: &mut SyncronizedMatch, conn: &mut Connection) -> Resul
 pub async fn update(game: &SyncronizedMatch, conn: &mut Connection) -> Result<(), ServerError> {
     let id: i64 = game.key.parse()?;
 
-    let action_history = serde_json::to_string(&game.actions)?;
+    let action_history = StampedAction::encode_history(&game.actions)?;
 
     let timer = if let Some(ref timer) = game.timer {
         Some(serde_json::to_string(timer)?)
@@ -57,7 +61,7 @@ pub async fn select(
 ) -> Result<Option<SyncronizedMatch>, ServerError> {
     let raw_game = sqlx::query_as!(
         RawGame,
-        "select id, action_history, timer from game where id = ?",
+        "select id, action_history, timer, fischer_random from game where id = ?",
         id
     )
     .fetch_optional(conn)
@@ -73,7 +77,7 @@ pub async fn select(
 pub async fn latest(conn: &mut Connection) -> Result<Vec<SyncronizedMatch>, ServerError> {
     let raw_games = sqlx::query_as!(
         RawGame,
-        r"select id, action_history, timer from game
+        r"select id, action_history, timer, fischer_random from game
         order by created desc
         limit 5"
     )
@@ -90,10 +94,12 @@ pub async fn latest(conn: &mut Connection) -> Result<Vec<SyncronizedMatch>, Serv
 
 // Database representation of a sync_match::SyncronizedMatch
 // We don't fully normalize the data, instead we just dump JSON into the db.
+// The action history uses the compact format of `pacosako::codec`.
 struct RawGame {
     id: i64,
     action_history: String,
     timer: Option<String>,
+    fischer_random: Option<i64>,
 }
 
 impl RawGame {
@@ -104,10 +110,13 @@ impl RawGame {
             None
         };
 
-        Ok(SyncronizedMatch {
+        let mut game = SyncronizedMatch {
             key: format!("{}", self.id),
-            actions: serde_json::from_str(&self.action_history)?,
+            actions: Vec::new(),
             timer,
-        })
+            fischer_random: self.fischer_random.map(|index| index as u16),
+        };
+        game.actions = StampedAction::decode_history(&self.action_history, &game.start()?)?;
+        Ok(game)
     }
 }
@@ -85,6 +85,17 @@ async fn ai_worker() -> Result<NamedFile, ServerError> {
     static_file("../target/ai_worker.js").await
 }
 
+/// The rules library compiled to webassembly, used by the AI worker.
+#[get("/pacosako.js")]
+async fn wasm_js() -> Result<NamedFile, ServerError> {
+    static_file("../target/pacosako.js").await
+}
+
+#[get("/pacosako_bg.wasm")]
+async fn wasm_binary() -> Result<NamedFile, ServerError> {
+    static_file("../target/pacosako_bg.wasm").await
+}
+
 #[get("/static/examples.txt")]
 async fn examples() -> Result<NamedFile, ServerError> {
     static_file("../target/examples.js").await
@@ -114,6 +125,12 @@ pub enum ServerError {
     IoError(#[from] std::io::Error),
     #[error("Error parsing Integer")]
     ParseIntError(#[from] std::num::ParseIntError),
+    #[error("Error writing the game record")]
+    RecordError(#[from] pacosako::record::RecordError),
+    #[error("Error decoding the action history")]
+    CodecError(#[from] pacosako::codec::CodecError),
+    #[error("A blocking task panicked or was cancelled")]
+    JoinError(#[from] rocket::tokio::task::JoinError),
 }
 
 impl<'r> rocket::response::Responder<'r, 'static> for ServerError {
@@ -282,6 +299,9 @@ fn logout(jar: &CookieJar<'_>) -> Flash<Redirect> {
 #[derive(Serialize, Deserialize)]
 struct PositionData {
     notation: String,
+    /// Side to move, castling and so on. Older positions only have the notation.
+    #[serde(flatten)]
+    state: pacosako::EditorState,
 }
 
 #[get("/random")]
@@ -293,6 +313,7 @@ fn random_position() -> Json<PositionData> {
 
     Json(PositionData {
         notation: notation.0,
+        state: pacosako::EditorState::default(),
     })
 }
 
@@ -300,6 +321,7 @@ fn random_position() -> Json<PositionData> {
 struct AnalysisReport {
     text_summary: String,
     search_result: SakoSearchResult,
+    evaluation: pacosako::evaluation::Evaluation,
 }
 
 #[post("/analyse", data = "<position>")]
@@ -315,10 +337,20 @@ fn analyse_position(
     let board: Result<DenseBoard, ()> =
         (&pacosako::ExchangeNotation(position_data.notation)).try_into();
     if let Ok(board) = board {
-        let sequences = pacosako::find_sako_sequences(&((&board).into()))?;
+        let editor_board = pacosako::EditorBoard::from(&board).with_state(position_data.state);
+        // This fails with `PacoError::InvalidPosition` for impossible positions.
+        let sequences = pacosako::find_sako_sequences(&editor_board)?;
+        let board = editor_board.with_active_player(
+            editor_board
+                .state()
+                .current_player
+                .unwrap_or(pacosako::PlayerColor::White),
+        )?;
+        let evaluation = pacosako::evaluation::evaluate(&board)?;
         Ok(Json(AnalysisReport {
             text_summary: format!("{:?}", sequences),
             search_result: sequences,
+            evaluation,
         }))
     } else {
         Err(ServerError::DeserializationFailed)
@@ -341,7 +373,7 @@ async fn create_game(
 
     info!("Creating a new game on client request.");
     let mut conn = pool.conn().await?;
-    let mut game = SyncronizedMatch::new_with_key("0", game_parameters.0);
+    let mut game = SyncronizedMatch::new_with_key("0", game_parameters.0)?;
     db::game::insert(&mut game, &mut conn).await?;
 
     info!("Game created with id {}.", game.key);
@@ -365,6 +397,19 @@ async fn get_game(
     }
 }
 
+/// Exports the given game in the game record format of `pacosako::record`.
+#[get("/game/<key>/record")]
+async fn get_game_record(key: String, pool: State<'_, Pool>) -> Result<String, ServerError> {
+    let key: i64 = key.parse()?;
+    let mut conn = pool.conn().await?;
+
+    if let Some(game) = db::game::select(key, &mut conn).await? {
+        Ok(pacosako::record::write_game(&game.game_record()?)?)
+    } else {
+        Err(ServerError::NotFound)
+    }
+}
+
 #[post("/ai/game/<key>", data = "<action>")]
 async fn post_action_to_game(
     key: String,
@@ -385,6 +430,47 @@ async fn post_action_to_game(
     }
 }
 
+/// Lets the built in engine play the turn of the player that controls the
+/// given game. The actions are executed like actions posted to `/ai/game/<key>`.
+#[post("/ai/game/<key>/play")]
+async fn ai_play_turn(
+    key: String,
+    pool: State<'_, Pool>,
+    send_to_websocket: State<'_, async_channel::Sender<ws::RocketToWsMsg>>,
+) -> Result<(), ServerError> {
+    let mut conn = pool.conn().await?;
+    let game = db::game::select(key.parse()?, &mut conn)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+
+    // The search runs on the blocking thread pool so it does not hold up the
+    // async workers. The limits keep the request short.
+    let board = game.project()?;
+    let result = rocket::tokio::task::spawn_blocking(move || {
+        let mut engine = pacosako::ai::AlphaBeta::new(pacosako::ai::SearchLimits {
+            max_depth: 4,
+            time_limit: Some(std::time::Duration::from_secs(1)),
+            node_limit: Some(1_000_000),
+        });
+        engine.search(&board)
+    })
+    .await??;
+
+    if let Some(result) = result {
+        for action in result.best_move {
+            let message = RocketToWsMsg::AiAction {
+                key: key.clone(),
+                action,
+            };
+            if let Err(e) = send_to_websocket.send(message).await {
+                error!("Error sending from rocket to the websocket server: {:?}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[get("/game/recent")]
 async fn recently_created_games(
     pool: State<'_, Pool>,
@@ -530,7 +616,9 @@ fn rocket() -> _ {
                 examples,
                 place_piece,
                 main_js,
-                ai_worker
+                ai_worker,
+                wasm_js,
+                wasm_binary
             ],
         )
         .mount(
@@ -544,11 +632,13 @@ fn rocket() -> _ {
                 position_get_list,
                 position_get,
                 post_action_to_game,
+                ai_play_turn,
                 random_position,
                 analyse_position,
                 create_game,
                 branch_game,
                 get_game,
+                get_game_record,
                 websocket_port,
                 recently_created_games,
             ],
@@ -1,6 +1,11 @@
 use crate::db;
 use crate::timer::{Timer, TimerConfig, TimerState};
-use chrono::{DateTime, Utc};
+use crate::ServerError;
+use chrono::{DateTime, TimeZone, Utc};
+use pacosako::codec::History;
+use pacosako::fischer_random::FischerRandom;
+use pacosako::game_history::GameHistory;
+use pacosako::record::{GameRecord, GameResult};
 use pacosako::{PacoAction, PacoBoard, PacoError};
 use serde::{Deserialize, Serialize};
 use serde_json::de::from_str;
@@ -13,6 +18,9 @@ use std::convert::TryFrom;
 #[derive(Deserialize, Clone)]
 pub struct MatchParameters {
     timer: Option<TimerConfig>,
+    /// Starts the game from a Fischer random position instead of the classic one.
+    #[serde(default)]
+    fischer_random: Option<FischerRandom>,
 }
 
 /// A paco sako action together with a timestamp that remembers when it was done.
@@ -24,6 +32,44 @@ pub struct StampedAction {
     timestamp: DateTime<Utc>,
 }
 
+impl StampedAction {
+    /// Packs the actions into the compact base64 format of `pacosako::codec`.
+    pub fn encode_history(actions: &[StampedAction]) -> Result<String, ServerError> {
+        let stamped = actions
+            .iter()
+            .map(|a| (a.action, a.timestamp.timestamp_millis()))
+            .collect();
+        Ok(History::with_timestamps(stamped).to_base64()?)
+    }
+
+    /// Unpacks actions written by `encode_history` and checks that they can be
+    /// played from the start board. Older games store their actions as JSON.
+    pub fn decode_history(
+        text: &str,
+        start: &pacosako::DenseBoard,
+    ) -> Result<Vec<StampedAction>, ServerError> {
+        if text.starts_with('[') {
+            return Ok(serde_json::from_str(text)?);
+        }
+        let history = History::from_base64(text, start)?;
+        let timestamps = history
+            .timestamps()
+            .ok_or(ServerError::DeserializationFailed)?;
+        history
+            .actions()
+            .iter()
+            .zip(timestamps)
+            .map(|(&action, &millis)| {
+                let timestamp = Utc
+                    .timestamp_millis_opt(millis)
+                    .single()
+                    .ok_or(ServerError::DeserializationFailed)?;
+                Ok(StampedAction { action, timestamp })
+            })
+            .collect()
+    }
+}
+
 /// A match is a recording of actions taken in it together with a unique
 /// identifier that can be used to connect to the game.
 /// It also takes care of tracking the timing and ensures actions are legal.
@@ -32,6 +78,8 @@ pub struct SyncronizedMatch {
     pub key: String,
     pub actions: Vec<StampedAction>,
     pub timer: Option<Timer>,
+    /// Index of the Fischer random starting position, None for a classic game.
+    pub fischer_random: Option<u16>,
 }
 
 /// Message that may be send by the client to the server.
@@ -86,6 +134,28 @@ pub struct CurrentMatchState {
     key: String,
     actions: Vec<StampedAction>,
     legal_actions: Vec<PacoAction>,
+    /// The legal actions without those that lead into a dead end. Clients can
+    /// offer these instead, so players don't lift pieces that can't move.
+    productive_actions: Vec<PacoAction>,
+    pub controlling_player: pacosako::PlayerColor,
+    pub timer: Option<Timer>,
+    pub victory_state: pacosako::VictoryState,
+    /// Index of the Fischer random starting position, None for a classic game.
+    pub fischer_random: Option<u16>,
+    /// The board before the first action in the fen format of
+    /// `pacosako::fen`. Clients replay the actions from here.
+    pub start: String,
+    /// What the last action did on the board. This is only filled in when the
+    /// state is the answer to an action, clients use it for animations and
+    /// sounds.
+    pub events: Vec<pacosako::PacoEvent>,
+}
+
+/// The part of the match state that changes when time passes. Unlike
+/// `CurrentMatchState` this is cheap to create, because it doesn't need the
+/// legal actions.
+#[derive(Clone, Debug)]
+pub struct TimerProgress {
     pub controlling_player: pacosako::PlayerColor,
     pub timer: Option<Timer>,
     pub victory_state: pacosako::VictoryState,
@@ -99,18 +169,23 @@ impl CurrentMatchState {
         board: &pacosako::DenseBoard,
     ) -> Result<Self, PacoError> {
         let victory_state = Self::victory_state(&board, &sync_match.timer);
+        let (legal_actions, productive_actions) = if victory_state.is_over() {
+            (vec![], vec![])
+        } else {
+            (board.actions()?, board.productive_actions()?)
+        };
 
         Ok(CurrentMatchState {
             key: sync_match.key.clone(),
             actions: sync_match.actions.clone(),
-            legal_actions: if victory_state.is_over() {
-                vec![]
-            } else {
-                board.actions()?
-            },
+            legal_actions,
+            productive_actions,
             controlling_player: board.controlling_player(),
             timer: sync_match.timer.clone(),
             victory_state: victory_state,
+            fischer_random: sync_match.fischer_random,
+            start: pacosako::fen::write_fen(&sync_match.start()?),
+            events: vec![],
         })
     }
 
@@ -129,21 +204,45 @@ impl CurrentMatchState {
 
 /// This implementation contains most of the "Business Logic" of the match.
 impl SyncronizedMatch {
-    pub fn new_with_key(key: &str, params: MatchParameters) -> Self {
-        SyncronizedMatch {
+    pub fn new_with_key(key: &str, params: MatchParameters) -> Result<Self, PacoError> {
+        let sync_match = SyncronizedMatch {
             key: key.to_owned(),
             actions: Vec::default(),
             timer: params.timer.map(|t| t.into()),
+            fischer_random: params.fischer_random.map(FischerRandom::index),
+        };
+        // Fails early for a starting position that can not be played.
+        let problems = sync_match.start()?.validate();
+        if !problems.is_empty() {
+            return Err(PacoError::InvalidPosition(problems));
+        }
+        Ok(sync_match)
+    }
+
+    /// The board before the first action.
+    pub fn start(&self) -> Result<pacosako::DenseBoard, PacoError> {
+        match self.fischer_random {
+            Some(index) => pacosako::fischer_random::start_position(index),
+            None => Ok(pacosako::DenseBoard::new()),
         }
     }
 
-    /// Reconstruct the board state
-    fn project(&self) -> Result<pacosako::DenseBoard, PacoError> {
+    /// The actions of the match with the board at the start of each turn.
+    pub fn history(&self) -> Result<GameHistory, PacoError> {
         // Here we don't need to validate the move, this was done before they
         // have been added to the action list.
-        let mut board = pacosako::DenseBoard::new();
+        let actions: Vec<PacoAction> = self.actions.iter().map(|a| a.action).collect();
+        GameHistory::from_trusted_actions(self.start()?, &actions)
+    }
+
+    /// Reconstruct the board state. This replays the actions without taking
+    /// snapshots, it runs for every message and only needs the last board.
+    pub fn project(&self) -> Result<pacosako::DenseBoard, PacoError> {
+        // Here we don't need to validate the move, this was done before they
+        // have been added to the action list.
+        let mut board = self.start()?;
         for action in &self.actions {
-            board.execute_trusted(action.action.clone())?;
+            board.execute_trusted(action.action)?;
         }
         Ok(board)
     }
@@ -164,7 +263,7 @@ impl SyncronizedMatch {
             }
         }
 
-        board.execute(new_action)?;
+        let events = board.execute_with_events(new_action)?;
         self.actions.push(StampedAction {
             action: new_action,
             timestamp: Utc::now(),
@@ -184,7 +283,9 @@ impl SyncronizedMatch {
             }
         }
 
-        CurrentMatchState::try_new(self, &board)
+        let mut state = CurrentMatchState::try_new(self, &board)?;
+        state.events = events;
+        Ok(state)
     }
 
     /// Gets the current state and the currently available legal actions.
@@ -193,26 +294,13 @@ impl SyncronizedMatch {
     }
 
     /// Rolls back the game state to the start of the turn of the current player.
+    /// This will never change the active player. Rolling back on a settled
+    /// board state does nothing.
     pub fn rollback(&mut self) -> Result<CurrentMatchState, PacoError> {
-        Self::rollback_trusted_action_stack(&mut self.actions)?;
-        self.current_state()
-    }
-
-    /// Takes a board state that is provided in terms of an action history and
-    /// rolls back an in-progress move. This will never change the active player.
-    /// Rolling back on a settled board state does nothing.
-    /// The action stack is assumed to only contain legal moves and the moves are
-    /// not validated.
-    fn rollback_trusted_action_stack(actions: &mut Vec<StampedAction>) -> Result<(), PacoError> {
-        let last_checkpoint_index =
-            pacosako::find_last_checkpoint_index(actions.iter().map(|a| &a.action))?;
-
-        // Remove all moves to get back to last_checkpoint_index
-        while actions.len() > last_checkpoint_index {
-            actions.pop();
-        }
-
-        Ok(())
+        let mut history = self.history()?;
+        history.rollback();
+        self.actions.truncate(history.len());
+        CurrentMatchState::try_new(self, history.board())
     }
 
     /// Updates the timer
@@ -226,13 +314,60 @@ impl SyncronizedMatch {
         }
     }
 
+    /// Exports the match as a game record. The time each turn took is taken
+    /// from the timestamps of the actions.
+    pub fn game_record(&self) -> Result<GameRecord, PacoError> {
+        let actions: Vec<PacoAction> = self.actions.iter().map(|a| a.action).collect();
+        let mut record = GameRecord::from_actions(self.start()?, &actions)?;
+
+        record.set_header("Event", format!("Game {}", self.key));
+        if let Some(first) = self.actions.first() {
+            record.set_header("Date", first.timestamp.format("%Y.%m.%d").to_string());
+        }
+        if let Some(timer) = &self.timer {
+            let config = timer.get_config();
+            // The tag can only describe time controls that are the same for both players.
+            if config.time_budget_white == config.time_budget_black {
+                let increment = config.increment.map_or(0, |i| i.num_seconds());
+                record.set_header(
+                    "TimeControl",
+                    format!("{}+{}", config.time_budget_white.num_seconds(), increment),
+                );
+            }
+        }
+
+        let mut action_count = 0;
+        let mut previous_end: Option<DateTime<Utc>> = None;
+        for turn in &mut record.turns {
+            action_count += turn.actions.len();
+            let end = self.actions[action_count - 1].timestamp;
+            if let Some(previous_end) = previous_end {
+                turn.elapsed = (end - previous_end).to_std().ok();
+            }
+            previous_end = Some(end);
+        }
+
+        // A timeout is only known to the timer and not to the board.
+        let board = self.project()?;
+        record.result =
+            GameResult::from_victory_state(CurrentMatchState::victory_state(&board, &self.timer));
+
+        Ok(record)
+    }
+
     /// Is triggered when there may have been significant timer progress.
-    pub fn timer_progress(&mut self) -> Result<CurrentMatchState, PacoError> {
+    /// This doesn't list the legal actions, use `current_state` for a state
+    /// that is sent to the clients.
+    pub fn timer_progress(&mut self) -> Result<TimerProgress, PacoError> {
         let board = self.project()?;
 
         self.update_timer(board.controlling_player());
 
-        CurrentMatchState::try_new(self, &board)
+        Ok(TimerProgress {
+            controlling_player: board.controlling_player(),
+            timer: self.timer.clone(),
+            victory_state: CurrentMatchState::victory_state(&board, &self.timer),
+        })
     }
 }
 
@@ -241,10 +376,17 @@ mod test {
     use super::*;
     use pacosako::types::BoardPosition;
 
+    fn classic() -> MatchParameters {
+        MatchParameters {
+            timer: None,
+            fischer_random: None,
+        }
+    }
+
     /// Does a move and mostly just checks that it does not crash.
     #[test]
     fn test_legal_moves_are_ok() {
-        let mut game = SyncronizedMatch::new_with_key("Game1", MatchParameters { timer: None });
+        let mut game = SyncronizedMatch::new_with_key("Game1", classic()).unwrap();
 
         game.do_action(PacoAction::Lift(BoardPosition(10))).unwrap();
         let current_state = game
@@ -260,8 +402,139 @@ mod test {
         assert_eq!(no_stamps, no_stamps_2);
         assert_eq!(current_state.legal_actions, current_state_2.legal_actions);
 
+        // Only the answer to the action reports what it did.
+        assert_eq!(
+            current_state.events,
+            vec![pacosako::PacoEvent::Moved {
+                from: BoardPosition(10),
+                to: BoardPosition(18),
+                piece: pacosako::PieceType::Pawn,
+                partner: None
+            }]
+        );
+        assert!(current_state_2.events.is_empty());
+
         // there are two moves in the state and 16 possible actions.
         assert_eq!(current_state.actions.len(), 2);
         assert_eq!(current_state.legal_actions.len(), 16);
+        // Rooks, bishops, the queen and the king are still blocked.
+        assert_eq!(current_state.productive_actions.len(), 10);
+    }
+
+    /// The action history survives the database format, old JSON histories
+    /// can still be read.
+    #[test]
+    fn test_encode_history() {
+        let mut game = SyncronizedMatch::new_with_key("Game1", classic()).unwrap();
+        game.do_action(PacoAction::Lift(BoardPosition(10))).unwrap();
+        game.do_action(PacoAction::Place(BoardPosition(18)))
+            .unwrap();
+        let start = game.start().unwrap();
+
+        let encoded = StampedAction::encode_history(&game.actions).unwrap();
+        let decoded = StampedAction::decode_history(&encoded, &start).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (a, b) in game.actions.iter().zip(&decoded) {
+            assert_eq!(a.action, b.action);
+            assert_eq!(
+                a.timestamp.timestamp_millis(),
+                b.timestamp.timestamp_millis()
+            );
+        }
+
+        let json = serde_json::to_string(&game.actions).unwrap();
+        let decoded = StampedAction::decode_history(&json, &start).unwrap();
+        assert_eq!(decoded.len(), 2);
+
+        // Placing before lifting is not a valid history.
+        assert!(StampedAction::decode_history("AQFTAA", &start).is_err());
+    }
+
+    /// Repeating a position three times ends the game in a draw, which is
+    /// treated just like a victory: There are no more legal actions.
+    #[test]
+    fn test_draw_by_repetition_ends_game() {
+        let mut game = SyncronizedMatch::new_with_key("Game1", classic()).unwrap();
+
+        // Knights on b1 and b8 move back and forth, this repeats the start.
+        let knight_moves = [(1, 18), (57, 42), (18, 1), (42, 57)];
+        let mut current_state = game.current_state().unwrap();
+        for _ in 0..2 {
+            for &(from, to) in &knight_moves {
+                game.do_action(PacoAction::Lift(BoardPosition(from)))
+                    .unwrap();
+                current_state = game
+                    .do_action(PacoAction::Place(BoardPosition(to)))
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(
+            current_state.victory_state,
+            pacosako::VictoryState::Draw(pacosako::DrawReason::Repetition)
+        );
+        assert!(current_state.legal_actions.is_empty());
+        assert!(current_state.productive_actions.is_empty());
+        assert!(game.do_action(PacoAction::Lift(BoardPosition(1))).is_err());
+    }
+
+    /// The exported record contains all turns and can be read back.
+    #[test]
+    fn test_game_record() {
+        let mut game = SyncronizedMatch::new_with_key("Game1", classic()).unwrap();
+
+        // A complete turn of white followed by a lifted black pawn.
+        for &action in &[
+            PacoAction::Lift(BoardPosition(12)),
+            PacoAction::Place(BoardPosition(28)),
+            PacoAction::Lift(BoardPosition(51)),
+        ] {
+            game.do_action(action).unwrap();
+        }
+        // Projecting skips the snapshots, but ends on the same board.
+        assert_eq!(game.project().unwrap(), *game.history().unwrap().board());
+
+        let record = game.game_record().unwrap();
+        assert_eq!(record.header("Event"), Some("Game Game1"));
+        assert_eq!(record.turns.len(), 2);
+        assert_eq!(record.turns[0].elapsed, None);
+        assert!(record.turns[1].elapsed.is_some());
+        assert_eq!(record.result, GameResult::Unknown);
+
+        let text = pacosako::record::write_game(&record).unwrap();
+        let read = pacosako::record::read_games(&text, pacosako::record::ReadMode::Strict).unwrap();
+        assert_eq!(read[0].actions(), record.actions());
+    }
+
+    /// A Fischer random game replays from its own starting position.
+    #[test]
+    fn test_fischer_random() {
+        let params = MatchParameters {
+            timer: None,
+            fischer_random: Some(FischerRandom::Index(0)),
+        };
+        let mut game = SyncronizedMatch::new_with_key("Game1", params).unwrap();
+
+        // The back row is BBQNNRKR, the knight on d1 jumps to c3.
+        game.do_action(PacoAction::Lift(BoardPosition(3))).unwrap();
+        game.do_action(PacoAction::Place(BoardPosition(18)))
+            .unwrap();
+        game.do_action(PacoAction::Lift(BoardPosition(59))).unwrap();
+        let current_state = game.rollback().unwrap();
+        assert_eq!(current_state.actions.len(), 2);
+        assert_eq!(current_state.fischer_random, Some(0));
+        // Clients replay the actions from this board.
+        assert!(current_state
+            .start
+            .starts_with("bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w "));
+
+        let record = game.game_record().unwrap();
+        assert_eq!(record.start, game.start().unwrap());
+
+        let params = MatchParameters {
+            timer: None,
+            fischer_random: Some(FischerRandom::Index(960)),
+        };
+        assert!(SyncronizedMatch::new_with_key("Game1", params).is_err());
     }
 }
@@ -145,6 +145,10 @@ impl Timer {
         self.timer_state
     }
 
+    pub fn get_config(&self) -> &TimerConfig {
+        &self.config
+    }
+
     /// Returns the time at which the timer would run out if the given player
     /// retains controll until then.
     pub fn timeout(&self, player: PlayerColor) -> DateTime<Utc> {
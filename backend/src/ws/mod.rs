@@ -4,7 +4,7 @@ pub mod timeout_connector;
 
 use crate::{
     db,
-    sync_match::{CurrentMatchState, SyncronizedMatch},
+    sync_match::{CurrentMatchState, SyncronizedMatch, TimerProgress},
     ServerError,
 };
 use anyhow::bail;
@@ -241,23 +241,23 @@ async fn handle_message(
 
             let mut game = fetch_game(&key, conn).await?;
 
-            let state = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
+            progress_the_timer(&mut game, to_timeout, key.clone()).await?;
 
             store_game(&game, conn).await?;
             if let Some(room) = server_state.rooms.get_mut(&game.key) {
-                broadcast_state(room, &state, ws).await;
+                broadcast_state(room, &game.current_state()?, ws).await;
             }
             return Ok(());
         }
         LogicMsg::AiAction { key, action } => {
             let mut game = fetch_game(&key, conn).await?;
 
-            let state = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
+            let progress = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
 
-            if state.victory_state.is_over() {
+            if progress.victory_state.is_over() {
                 store_game(&game, conn).await?;
                 if let Some(room) = server_state.rooms.get_mut(&key) {
-                    broadcast_state(room, &state, ws).await;
+                    broadcast_state(room, &game.current_state()?, ws).await;
                 }
             }
 
@@ -276,19 +276,19 @@ async fn progress_the_timer(
     game: &mut SyncronizedMatch,
     to_timeout: Sender<(String, DateTime<Utc>)>,
     key: String,
-) -> Result<CurrentMatchState, anyhow::Error> {
+) -> Result<TimerProgress, anyhow::Error> {
     match game.timer_progress() {
-        Ok(mut state) => {
-            if state.victory_state.is_over() {
-                return Ok(state);
-            } else if let Some(timer) = &mut state.timer {
-                let next_reminder = timer.timeout(state.controlling_player);
+        Ok(mut progress) => {
+            if progress.victory_state.is_over() {
+                return Ok(progress);
+            } else if let Some(timer) = &mut progress.timer {
+                let next_reminder = timer.timeout(progress.controlling_player);
                 to_timeout
                     .send((key, next_reminder))
                     .await
                     .expect("Timeout connector quit unexpectedly.");
             }
-            return Ok(state);
+            return Ok(progress);
         }
         Err(e) => {
             bail!("Error when progressing the timer: {}", e);
@@ -347,11 +347,11 @@ async fn handle_client_message(
                 return send_error(format!("Game {} not found", key), &sender, ws).await;
             };
 
-            let state = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
+            let progress = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
 
-            if state.victory_state.is_over() {
+            if progress.victory_state.is_over() {
                 store_game(&game, conn).await?;
-                broadcast_state(room, &state, ws).await;
+                broadcast_state(room, &game.current_state()?, ws).await;
                 return Ok(());
             }
 
@@ -364,11 +364,11 @@ async fn handle_client_message(
 
             let mut game = fetch_game(&room.key, conn).await?;
 
-            let state = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
+            let progress = progress_the_timer(&mut game, to_timeout, key.clone()).await?;
 
-            if state.victory_state.is_over() {
+            if progress.victory_state.is_over() {
                 store_game(&game, conn).await?;
-                broadcast_state(room, &state, ws).await;
+                broadcast_state(room, &game.current_state()?, ws).await;
                 return Ok(());
             }
 
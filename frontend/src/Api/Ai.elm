@@ -46,9 +46,18 @@ aiState msg =
 -- port restartAiWorker : () -> Cmd msg
 
 
-requestMoveFromAi : Cmd msg
-requestMoveFromAi =
-    Ports.requestMoveFromAi Encode.null
+{-| Asks the AI worker for a turn after the given actions, starting from the
+position given as a fen string. The game may not start from the initial
+position, for example in Fischer random games.
+-}
+requestMoveFromAi : String -> List Sako.Action -> Cmd msg
+requestMoveFromAi start actions =
+    Ports.requestMoveFromAi
+        (Encode.object
+            [ ( "start", Encode.string start )
+            , ( "actions", Encode.list Sako.encodeAction actions )
+            ]
+        )
 
 
 subscribeMoveFromAi : msg -> (Sako.Action -> msg) -> Sub msg
@@ -18,7 +18,7 @@ module Api.Backend exposing
 the server api.
 -}
 
-import Api.Decoders exposing (CurrentMatchState, decodeMatchState)
+import Api.Decoders exposing (CurrentMatchState, decodeMatchState, decodeStart)
 import Http exposing (Error)
 import Iso8601
 import Json.Decode as Decode exposing (Decoder)
@@ -372,15 +372,17 @@ type alias Replay =
     { actions : List ( Sako.Action, Posix )
     , timer : Maybe Timer.Timer
     , victoryState : Sako.VictoryState
+    , start : Sako.Position
     }
 
 
 decodeReplay : Decoder Replay
 decodeReplay =
-    Decode.map3 Replay
+    Decode.map4 Replay
         (Decode.field "actions" (Decode.list decodeStampedAction))
         (Decode.field "timer" (Decode.maybe Timer.decodeTimer))
         (Decode.field "victory_state" Sako.decodeVictoryState)
+        decodeStart
 
 
 decodeStampedAction : Decoder ( Sako.Action, Posix )
@@ -1,5 +1,6 @@
-module Api.Decoders exposing (CurrentMatchState, decodeMatchState)
+module Api.Decoders exposing (CurrentMatchState, decodeMatchState, decodeStart)
 
+import Fen
 import Json.Decode as Decode exposing (Decoder)
 import Sako
 import Timer
@@ -12,6 +13,8 @@ type alias CurrentMatchState =
     , controllingPlayer : Sako.Color
     , timer : Maybe Timer.Timer
     , gameState : Sako.VictoryState
+    , start : Sako.Position
+    , startFen : String
     }
 
 
@@ -20,14 +23,16 @@ endpoints. This is the file for those.
 -}
 decodeMatchState : Decoder CurrentMatchState
 decodeMatchState =
-    Decode.map6
-        (\key actionHistory legalActions controllingPlayer timer gameState ->
+    Decode.map8
+        (\key actionHistory legalActions controllingPlayer timer gameState start startFen ->
             { key = key
             , actionHistory = actionHistory
             , legalActions = legalActions
             , controllingPlayer = controllingPlayer
             , timer = timer
             , gameState = gameState
+            , start = start
+            , startFen = startFen
             }
         )
         (Decode.field "key" Decode.string)
@@ -36,3 +41,22 @@ decodeMatchState =
         (Decode.field "controlling_player" Sako.decodeColor)
         (Decode.field "timer" (Decode.maybe Timer.decodeTimer))
         (Decode.field "victory_state" Sako.decodeVictoryState)
+        decodeStart
+        (Decode.field "start" Decode.string)
+
+
+{-| The position before the first action. Fischer random games don't start
+from the initial position, so the actions are replayed from here.
+-}
+decodeStart : Decoder Sako.Position
+decodeStart =
+    Decode.field "start" Decode.string
+        |> Decode.andThen
+            (\fen ->
+                case Fen.parseFen fen of
+                    Just position ->
+                        Decode.succeed position
+
+                    Nothing ->
+                        Decode.fail ("Not a valid start position: " ++ fen)
+            )
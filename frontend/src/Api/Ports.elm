@@ -7,7 +7,9 @@ port module Api.Ports exposing
     , requestSvgNodeContent
     , responseSvgNodeContent
     , subscribeMoveFromAi
+    , positionProblems
     , triggerPngDownload
+    , validatePosition
     , websocketReceive
     , websocketSend
     , websocketStatus
@@ -97,6 +99,25 @@ port restartAiWorker : () -> Cmd msg
 port requestMoveFromAi : Value -> Cmd msg
 
 
-{-| After calling `requestMoveFromAi` this response should be received once.
+{-| After calling `requestMoveFromAi` this response is received once for each
+action of the turn the AI chose.
 -}
 port subscribeMoveFromAi : (Value -> msg) -> Sub msg
+
+
+
+--------------------------------------------------------------------------------
+-- Position validation ports ---------------------------------------------------
+--------------------------------------------------------------------------------
+
+
+{-| Checks if a board can occur in a game. This runs `validate_json` of the
+Rust library in the browser.
+-}
+port validatePosition : Value -> Cmd msg
+
+
+{-| After calling `validatePosition` this response is received with the list
+of problems.
+-}
+port positionProblems : (Value -> msg) -> Sub msg
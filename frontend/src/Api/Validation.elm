@@ -0,0 +1,105 @@
+module Api.Validation exposing (subscribePositionProblems, validatePosition)
+
+{-| Checks positions from the editor with the rules of the Rust library. The
+problems are the `PositionProblem` values of `validate_json`, written out the
+same way as in Rust.
+-}
+
+import Api.Ports as Ports
+import Json.Decode as Decode exposing (Decoder)
+import Json.Encode as Encode exposing (Value)
+import Sako
+
+
+{-| Asks the Rust library for everything that prevents the position from
+occurring in a game. The answer arrives via `subscribePositionProblems`.
+-}
+validatePosition : Sako.Position -> Cmd msg
+validatePosition position =
+    Ports.validatePosition (encodeEditorBoard position)
+
+
+subscribePositionProblems : (List String -> msg) -> Sub msg
+subscribePositionProblems msg =
+    Ports.positionProblems
+        (\value ->
+            Decode.decodeValue decodeProblems value
+                |> Result.withDefault [ "The position could not be checked." ]
+                |> msg
+        )
+
+
+{-| The `EditorBoard` of the Rust library. Without a player to move, both
+players are checked.
+-}
+encodeEditorBoard : Sako.Position -> Value
+encodeEditorBoard position =
+    Encode.object
+        [ ( "pieces", Encode.list encodeRestingPiece position.pieces ) ]
+
+
+encodeRestingPiece : Sako.Piece -> Value
+encodeRestingPiece piece =
+    Encode.object
+        [ ( "piece_type", Encode.string (Sako.toStringType piece.pieceType) )
+        , ( "color", Sako.encodeColor piece.color )
+        , ( "position", Encode.int (Sako.tileFlat piece.position) )
+        ]
+
+
+{-| The library answers with a list of problems, or with an error if it could
+not read the board.
+-}
+decodeProblems : Decoder (List String)
+decodeProblems =
+    Decode.oneOf
+        [ Decode.list decodeProblem
+        , Decode.succeed [ "The position could not be checked." ]
+        ]
+
+
+decodeProblem : Decoder String
+decodeProblem =
+    Decode.oneOf
+        [ Decode.field "MissingKing"
+            (Decode.map (\color -> color ++ " has no king.") Decode.string)
+        , Decode.field "DuplicateKing"
+            (Decode.map2 (\color count -> color ++ " has " ++ String.fromInt count ++ " kings.")
+                (Decode.index 0 Decode.string)
+                (Decode.index 1 Decode.int)
+            )
+        , Decode.field "KingInUnion"
+            (colorAndTile (\color tile -> "The " ++ color ++ " king on " ++ tile ++ " is in a union."))
+        , Decode.field "PawnOnHomeRow"
+            (colorAndTile (\color tile -> "The single " ++ color ++ " pawn on " ++ tile ++ " can never have reached its own home row."))
+        , Decode.field "UnpromotedPawn"
+            (colorAndTile (\color tile -> "The " ++ color ++ " pawn on " ++ tile ++ " should have been promoted."))
+        , Decode.field "CastlingRights"
+            (Decode.map (\color -> color ++ " may castle, but the king or the rook left its square.") Decode.string)
+        , Decode.field "EnPassant"
+            (Decode.map (\tile -> tile ++ " is not a possible en passant square.") decodeTileName)
+        , Decode.field "OpponentInSako"
+            (Decode.map (\color -> color ++ " is not to move, but already in ŝako.") Decode.string)
+        , Decode.string
+            |> Decode.andThen
+                (\problem ->
+                    case problem of
+                        "HandWithoutPlayer" ->
+                            Decode.succeed "There are pieces in the hand, but no player to move who holds them."
+
+                        _ ->
+                            Decode.fail ("Not a known position problem: " ++ problem)
+                )
+        ]
+
+
+colorAndTile : (String -> String -> String) -> Decoder String
+colorAndTile describe =
+    Decode.map2 describe
+        (Decode.index 0 Decode.string)
+        (Decode.index 1 decodeTileName)
+
+
+decodeTileName : Decoder String
+decodeTileName =
+    Decode.map (Sako.tileFromFlatCoordinate >> Sako.tileToIdentifier) Decode.int
@@ -3,6 +3,7 @@ module Pages.Editor exposing (Model, Msg, Params, page)
 import Animation exposing (Timeline)
 import Api.Backend exposing (Replay)
 import Api.Ports
+import Api.Validation
 import CastingDeco
 import Colors
 import Components exposing (btn, viewButton, withMsg, withSmallIcon, withStyle)
@@ -72,6 +73,7 @@ type alias Model =
     , userPaste : String
     , pasteParsed : PositionParseResult
     , analysis : Maybe AnalysisReport
+    , problems : List String
     , smartTool : SmartToolModel
     , showExportOptions : Bool
     , castingDeco : CastingDeco.Model
@@ -170,6 +172,7 @@ initialEditor rawUrl shared query =
     , userPaste = ""
     , pasteParsed = NoInput
     , analysis = Nothing
+    , problems = []
     , smartTool = initSmartTool
     , showExportOptions = Basics.False
     , castingDeco = CastingDeco.initModel
@@ -194,7 +197,7 @@ loadInitialData model =
             )
 
         QueryFen { fen } ->
-            ( setTimelineToSingleton fen model, Cmd.none )
+            ( setTimelineToSingleton fen model, Api.Validation.validatePosition fen )
 
         _ ->
             ( model, Cmd.none )
@@ -225,6 +228,7 @@ type Msg
     | GotRandomPosition Sako.Position
     | RequestAnalysePosition Sako.Position
     | GotAnalysePosition AnalysisReport
+    | GotPositionProblems (List String)
     | ToolAddPiece Sako.Color Sako.Type
     | SetExportOptionsVisible Bool
     | SetInputMode (Maybe CastingDeco.InputMode)
@@ -255,6 +259,7 @@ subscriptions model =
         [ Custom.Events.onKeyUp keybindings
         , Api.Ports.responseSvgNodeContent SvgReadyForDownload
         , Animation.subscription model.timeline AnimationTick
+        , Api.Validation.subscribePositionProblems GotPositionProblems
         ]
 
 
@@ -285,8 +290,23 @@ updateTimeline now model =
     { model | timeline = Animation.tick now model.timeline }
 
 
+{-| Checks the position again whenever it changes.
+-}
 update : Msg -> Model -> ( Model, Cmd Msg )
 update msg model =
+    let
+        ( newModel, cmd ) =
+            updateEditor msg model
+    in
+    if P.getC newModel.game /= P.getC model.game then
+        ( newModel, Cmd.batch [ cmd, Api.Validation.validatePosition (P.getC newModel.game) ] )
+
+    else
+        ( newModel, cmd )
+
+
+updateEditor : Msg -> Model -> ( Model, Cmd Msg )
+updateEditor msg model =
     case msg of
         EditorMsgNoOp ->
             ( model, Cmd.none )
@@ -423,6 +443,9 @@ update msg model =
         GotAnalysePosition analysis ->
             ( { model | analysis = Just analysis }, Cmd.none )
 
+        GotPositionProblems problems ->
+            ( { model | problems = problems }, Cmd.none )
+
         ToolAddPiece color pieceType ->
             ( updateSmartToolAdd (P.getC model.game) color pieceType
                 |> liftToolUpdate model
@@ -477,7 +500,7 @@ replayLoaded actionCount replay model =
             List.take actionCount replay.actions |> List.map (\( a, _ ) -> a)
 
         maybeBoard =
-            Sako.doActionsList actions Sako.initialPosition
+            Sako.doActionsList actions replay.start
     in
     case maybeBoard of
         Just board ->
@@ -1298,6 +1321,7 @@ sidebar model =
          , addPieceButtons Sako.Black "Black:" model.smartTool
          , colorSchemeConfig model
          , CastingDeco.configView model.lang castingDecoMessages model.inputMode model.castingDeco
+         , positionProblems model
          , analysisResult model
          ]
             ++ exportOptions
@@ -1542,6 +1566,18 @@ parsedMarkdownPaste model =
                 }
 
 
+{-| Lists why the position can not occur in a game.
+-}
+positionProblems : Model -> Element msg
+positionProblems model =
+    if List.isEmpty model.problems then
+        Element.none
+
+    else
+        column [ spacing 5, Font.color (Element.rgb 0.6 0 0) ]
+            (List.map (\problem -> Element.paragraph [] [ Element.text problem ]) model.problems)
+
+
 analysisResult : Model -> Element msg
 analysisResult model =
     case model.analysis of
@@ -17,6 +17,7 @@ import Element.Background as Background
 import Element.Border as Border
 import Element.Font as Font
 import Element.Input as Input
+import Fen
 import FontAwesome.Regular as Regular
 import FontAwesome.Solid as Solid
 import I18n.Strings as I18n exposing (I18nToken(..), Language(..), t)
@@ -94,6 +95,8 @@ init shared { params, query } =
             , controllingPlayer = Sako.White
             , timer = Nothing
             , gameState = Sako.Running
+            , start = Sako.initialPosition
+            , startFen = Fen.writeFen Sako.initialPosition
             }
       , timeline = Animation.init (PositionView.renderStatic WhiteBottom Sako.initialPosition)
       , focus = Nothing
@@ -200,7 +203,9 @@ update msg model =
             updateActionInputStep action model
 
         RequestAiMove ->
-            ( model, Api.Ai.requestMoveFromAi )
+            ( model
+            , Api.Ai.requestMoveFromAi model.currentState.startFen model.currentState.actionHistory
+            )
 
         AiCrashed ->
             ( model, Ports.logToConsole "Ai Crashed" )
@@ -468,7 +473,7 @@ updateCurrentMatchState data model =
                         |> Maybe.withDefault model.board
 
                 Nothing ->
-                    Sako.doActionsList data.actionHistory Sako.initialPosition
+                    Sako.doActionsList data.actionHistory data.start
                         |> Maybe.withDefault Sako.emptyPosition
 
         newState =
@@ -494,11 +499,15 @@ updateMatchConnectionSuccess data model =
 
 {-| Given an old and a new match state, this returns the actions that need to
 be taken to transform the old state into the new state. Returns Nothing if the
-new state does not extend the old state.
+new state does not extend the old state or starts from a different position.
 -}
 matchStatesDiff : CurrentMatchState -> CurrentMatchState -> Maybe (List Sako.Action)
 matchStatesDiff old new =
-    historyDiff old.actionHistory new.actionHistory
+    if old.start == new.start then
+        historyDiff old.actionHistory new.actionHistory
+
+    else
+        Nothing
 
 
 historyDiff : List a -> List a -> Maybe (List a)
@@ -978,6 +987,11 @@ maybeVictoryStateInfo model victoryState =
                 , Element.el [ Font.size 20, centerX ] (Element.text (t model.lang i18nTimeout))
                 ]
 
+        Sako.Draw _ ->
+            bigRoundedVictoryStateLabel (Element.rgb255 200 200 200)
+                [ Element.el [ Font.size 30, centerX ] (Element.text (t model.lang i18nDraw))
+                ]
+
 
 {-| Links to the replay, but only after the game is finished.
 -}
@@ -1147,6 +1161,15 @@ i18nTimeout =
         }
 
 
+i18nDraw : I18nToken String
+i18nDraw =
+    I18nToken
+        { english = "Draw"
+        , dutch = "Remise"
+        , esperanto = "Egalludo"
+        }
+
+
 i18nWatchReplay : I18nToken String
 i18nWatchReplay =
     I18nToken
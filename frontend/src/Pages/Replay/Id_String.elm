@@ -465,7 +465,7 @@ currentBoard model replay =
                 |> List.map (\( action, _ ) -> action)
     in
     if List.length actions == model.actionCount then
-        Sako.doActionsList actions Sako.initialPosition
+        Sako.doActionsList actions replay.start
             |> Maybe.map (ReplayOk actions)
             |> Maybe.withDefault ReplayError
 
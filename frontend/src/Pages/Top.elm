@@ -542,7 +542,7 @@ recentGamesListSuccessOne : Language -> CurrentMatchState -> Element msg
 recentGamesListSuccessOne lang matchState =
     let
         position =
-            Sako.initialPosition
+            matchState.start
                 |> Sako.doActionsList matchState.actionHistory
                 |> Maybe.map (PositionView.renderStatic WhiteBottom)
                 |> Maybe.map (PositionView.viewStatic PositionView.staticViewConfig)
@@ -1,6 +1,7 @@
 module Sako exposing
     ( Action(..)
     , Color(..)
+    , DrawReason(..)
     , Piece
     , Position
     , Tile(..)
@@ -15,6 +16,7 @@ module Sako exposing
     , doActionsList
     , emptyPosition
     , encodeAction
+    , encodeColor
     , encodePosition
     , enumeratePieceIdentity
     , exportExchangeNotation
@@ -27,6 +29,7 @@ module Sako exposing
     , isPromoting
     , liftedAtTile
     , tileFlat
+    , tileFromFlatCoordinate
     , tileToIdentifier
     , toStringType
     )
@@ -325,6 +328,13 @@ type VictoryState
     = Running
     | PacoVictory Color
     | TimeoutVictory Color
+    | Draw DrawReason
+
+
+type DrawReason
+    = Repetition
+    | NoProgress
+    | AllPaired
 
 
 decodeVictoryState : Decoder VictoryState
@@ -343,9 +353,31 @@ decodeVictoryState =
             (Decode.field "TimeoutVictory" decodeColor)
         , Decode.map PacoVictory
             (Decode.field "PacoVictory" decodeColor)
+        , Decode.map Draw
+            (Decode.field "Draw" decodeDrawReason)
         ]
 
 
+decodeDrawReason : Decoder DrawReason
+decodeDrawReason =
+    Decode.string
+        |> Decode.andThen
+            (\str ->
+                case str of
+                    "Repetition" ->
+                        Decode.succeed Repetition
+
+                    "NoProgress" ->
+                        Decode.succeed NoProgress
+
+                    "AllPaired" ->
+                        Decode.succeed AllPaired
+
+                    _ ->
+                        Decode.fail ("Unknown draw reason: " ++ str)
+            )
+
+
 {-| If there is currently a lifted piece (or two), then this returns the tile.
 -}
 liftedAtTile : Position -> Maybe Tile
@@ -1,14 +1,42 @@
-/** 
+/**
  * Use this class as `let myWorker = new Worker('ai_worker.js');` you can then
  * load an AI into it and ask it to perform moves. You will also be able to kill
  * it again.
+ *
+ * The worker expects an `AiSearchRequest` as its message, that is an object
+ * with the `actions` of the game so far and optionally the `start` fen and the
+ * search `limits`. It answers with one message per action of the turn it
+ * chose, or with a single `{ error: ... }` message if the search failed.
  */
 
 /** Helps with typescript type checking. */
 declare function postMessage(params: any);
+declare function importScripts(...urls: string[]);
+declare const wasm_bindgen: any;
 
-onmessage = function (data: any) {
+// The library is built with `wasm-pack build --target no-modules` which
+// defines the global `wasm_bindgen`.
+importScripts('/pacosako.js');
+let wasmReady: Promise<any> = wasm_bindgen('/pacosako_bg.wasm');
+
+/**
+ * The web worker has no clock in wasm, so we limit the search by nodes instead
+ * of time. This takes a few seconds in the browser.
+ */
+const defaultLimits = { max_depth: 4, node_limit: 200000 };
+
+onmessage = function (event: MessageEvent<any>) {
     console.log('The Ai was asked to perform a move.');
-    let arbitraryAction = { "Lift": 15 };
-    postMessage(arbitraryAction);
-}
\ No newline at end of file
+    let request = event.data;
+    if (!request.limits) {
+        request.limits = defaultLimits;
+    }
+    wasmReady.then(() => {
+        let response = JSON.parse(wasm_bindgen.ai_search_json(JSON.stringify(request)));
+        if (Array.isArray(response)) {
+            response.forEach(action => postMessage(action));
+        } else {
+            postMessage({ error: response });
+        }
+    });
+}
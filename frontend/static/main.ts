@@ -4,6 +4,7 @@
 /// This could be more precise listing also the ports that we have for better
 /// controll.
 declare var Elm: any;
+declare const wasm_bindgen: any;
 
 // Set up a new mutation observer, that will fire custom events for all
 // svg elements when there is a click event or a motion event.
@@ -411,4 +412,22 @@ aiWorker.onmessage = commit_actions;
 
 if (app.ports.requestMoveFromAi) {
     app.ports.requestMoveFromAi.subscribe(decide_move)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Ports for position validation ///////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////
+
+// The library is built with `wasm-pack build --target no-modules` and included
+// by index.html, which defines the global `wasm_bindgen`. Validation is quick,
+// so it runs here and not in the AI worker.
+let wasmReady: Promise<any> = wasm_bindgen('/pacosako_bg.wasm');
+
+if (app.ports.validatePosition) {
+    app.ports.validatePosition.subscribe(board => {
+        wasmReady.then(() => {
+            let problems = JSON.parse(wasm_bindgen.validate_json(JSON.stringify(board)));
+            app.ports.positionProblems.send(problems);
+        });
+    });
 }
\ No newline at end of file
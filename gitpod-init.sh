@@ -11,6 +11,11 @@ cd frontend
 elm-spa build
 elm make src/Main.elm --output=../target/elm.js
 cd ..
+# Rules library as webassembly for the AI worker.
+echo Prebuild of the webassembly library
+cd lib
+wasm-pack build --target no-modules --out-dir ../target --out-name pacosako
+cd ..
 # Supporting typescript code.
 echo Prebuild of required typescript code
 tsc
@@ -0,0 +1,424 @@
+//! A negamax search with alpha-beta pruning to let the computer play.
+//!
+//! The search works on complete turns as returned by `legal_moves`, so the
+//! result is a whole turn that can be sent to the server action by action.
+//! It uses iterative deepening: The search starts with a depth of one turn
+//! and goes one turn deeper until a limit is reached. The principal variation
+//! of the last iteration is searched first in the next one.
+//!
+//! Time limits need a clock, which the standard library does not provide on
+//! wasm. There you need to limit the search by depth and nodes instead.
+
+use crate::evaluation::evaluate;
+use crate::fen;
+use crate::{
+    legal_moves, DeadEnds, DenseBoard, PacoAction, PacoBoard, PacoError, PlayerColor, VictoryState,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use wasm_bindgen::prelude::*;
+
+/// Anything that can choose a turn for the player that controls a board.
+/// This is the common interface of all computer players.
+pub trait Engine {
+    /// Returns the complete turn the engine wants to play or None, if there
+    /// is no legal turn.
+    fn choose_turn(&mut self, board: &DenseBoard) -> Result<Option<Vec<PacoAction>>, PacoError>;
+}
+
+/// Score of a won game. Wins that take less turns score a bit higher.
+pub const WIN_SCORE: i32 = 1_000_000;
+
+/// Decides how long the search may run. The search stops as soon as one of the
+/// limits is reached and returns the best turn it found up to that point.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct SearchLimits {
+    /// Maximum depth in complete turns.
+    pub max_depth: usize,
+    #[serde(default)]
+    pub time_limit: Option<Duration>,
+    #[serde(default)]
+    pub node_limit: Option<u64>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            max_depth: 3,
+            time_limit: None,
+            node_limit: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SearchResult {
+    /// The turn the engine wants to play.
+    pub best_move: Vec<PacoAction>,
+    /// Score from the perspective of the player that controls the board.
+    pub score: i32,
+    /// The expected continuation, starting with the best move.
+    pub principal_variation: Vec<Vec<PacoAction>>,
+    /// The depth of the deepest iteration that contributed to the result.
+    pub depth: usize,
+    /// Number of positions that were looked at.
+    pub nodes: u64,
+}
+
+/// Negamax search with alpha-beta pruning and iterative deepening.
+pub struct AlphaBeta {
+    pub limits: SearchLimits,
+    nodes: u64,
+    deadline: Option<Instant>,
+}
+
+impl AlphaBeta {
+    pub fn new(limits: SearchLimits) -> Self {
+        AlphaBeta {
+            limits,
+            nodes: 0,
+            deadline: None,
+        }
+    }
+
+    /// Finds the best turn for the player that controls the board.
+    /// Returns None, if there is no legal turn.
+    pub fn search(&mut self, board: &DenseBoard) -> Result<Option<SearchResult>, PacoError> {
+        self.nodes = 0;
+        // Only ask for the time when it is needed, as this panics on wasm.
+        self.deadline = self.limits.time_limit.map(|limit| Instant::now() + limit);
+
+        let player = board.controlling_player();
+        let root_moves = legal_moves(board, DeadEnds::Exclude)?;
+        if root_moves.is_empty() {
+            return Ok(None);
+        }
+
+        let mut result = SearchResult {
+            best_move: root_moves[0].actions.clone(),
+            score: 0,
+            principal_variation: vec![root_moves[0].actions.clone()],
+            depth: 0,
+            nodes: 0,
+        };
+
+        for depth in 1..=self.limits.max_depth.max(1) {
+            let mut pv = Vec::new();
+            let (score, complete) = self.negamax(
+                board,
+                player,
+                depth,
+                0,
+                -WIN_SCORE - 1,
+                WIN_SCORE + 1,
+                &result.principal_variation,
+                &mut pv,
+            )?;
+            // An interrupted iteration can still be used if it found a
+            // move, because the previous best move was searched first.
+            if !pv.is_empty() {
+                result.best_move = pv[0].clone();
+                result.score = score;
+                result.principal_variation = pv;
+                result.depth = depth;
+            }
+            if !complete || score.abs() >= WIN_SCORE - depth as i32 {
+                break;
+            }
+        }
+
+        result.nodes = self.nodes;
+        Ok(Some(result))
+    }
+
+    /// Returns true, when the search has to stop.
+    fn out_of_budget(&self) -> bool {
+        if let Some(node_limit) = self.limits.node_limit {
+            if self.nodes >= node_limit {
+                return true;
+            }
+        }
+        match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Returns the score from the perspective of `player` together with a flag
+    /// that is false when the search was interrupted. The principal variation
+    /// found is written into `pv`.
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        board: &DenseBoard,
+        player: PlayerColor,
+        depth: usize,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+        pv_hint: &[Vec<PacoAction>],
+        pv: &mut Vec<Vec<PacoAction>>,
+    ) -> Result<(i32, bool), PacoError> {
+        self.nodes += 1;
+
+        match board.victory_state() {
+            VictoryState::Running => {}
+            VictoryState::PacoVictory(winner) | VictoryState::TimeoutVictory(winner) => {
+                let score = WIN_SCORE - ply;
+                return Ok((if winner == player { score } else { -score }, true));
+            }
+            VictoryState::Draw(_) => return Ok((0, true)),
+        }
+        if ply > 0 && self.out_of_budget() {
+            return Ok((0, false));
+        }
+        if depth == 0 {
+            return Ok((evaluate(board)?.relative_to(player), true));
+        }
+
+        let mut moves = legal_moves(board, DeadEnds::Exclude)?;
+        if moves.is_empty() {
+            return Ok((0, true));
+        }
+        // Search the expected best move first, then moves that win right away.
+        moves.sort_by_key(|m| {
+            (
+                pv_hint.first() != Some(&m.actions),
+                !m.board.victory_state().is_over(),
+            )
+        });
+
+        for m in moves {
+            let child_hint = match pv_hint.split_first() {
+                Some((first, rest)) if *first == m.actions => rest,
+                _ => &[],
+            };
+            let mut child_pv = Vec::new();
+            let (child_score, complete) = self.negamax(
+                &m.board,
+                player.other(),
+                depth - 1,
+                ply + 1,
+                -beta,
+                -alpha,
+                child_hint,
+                &mut child_pv,
+            )?;
+            if !complete {
+                return Ok((alpha, false));
+            }
+            let score = -child_score;
+            if score > alpha || pv.is_empty() {
+                alpha = alpha.max(score);
+                pv.clear();
+                pv.push(m.actions);
+                pv.extend(child_pv);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        Ok((alpha, true))
+    }
+}
+
+impl Engine for AlphaBeta {
+    fn choose_turn(&mut self, board: &DenseBoard) -> Result<Option<Vec<PacoAction>>, PacoError> {
+        Ok(self.search(board)?.map(|result| result.best_move))
+    }
+}
+
+/// Input of `ai_search_json`.
+#[derive(Deserialize)]
+struct AiSearchRequest {
+    /// The board before the first action in fen notation, for Fischer random
+    /// games and positions from the editor. Without it, the game starts from
+    /// the classic position.
+    #[serde(default)]
+    start: Option<String>,
+    /// The actions of the game so far.
+    actions: Vec<PacoAction>,
+    limits: SearchLimits,
+}
+
+impl AiSearchRequest {
+    fn start(&self) -> Result<DenseBoard, PacoError> {
+        match &self.start {
+            Some(fen) => {
+                let board = fen::parse_fen(fen).map_err(|_| PacoError::InputJsonMalformed)?;
+                let problems = board.validate();
+                if problems.is_empty() {
+                    Ok(board)
+                } else {
+                    Err(PacoError::InvalidPosition(problems))
+                }
+            }
+            None => Ok(DenseBoard::new()),
+        }
+    }
+}
+
+/// Entry point for the AI web worker. Takes a JSON encoded `AiSearchRequest`
+/// and returns the best turn as a JSON list of actions or an error.
+#[wasm_bindgen]
+pub fn ai_search_json(request: &str) -> String {
+    let result = serde_json::from_str(request)
+        .map_err(|_| PacoError::InputJsonMalformed)
+        .and_then(|request: AiSearchRequest| {
+            let mut board = request.start()?;
+            for action in request.actions {
+                board.execute(action)?;
+            }
+            AlphaBeta::new(request.limits).search(&board)
+        });
+
+    match result {
+        Ok(Some(result)) => serde_json::to_string(&result.best_move).unwrap(),
+        Ok(None) => serde_json::to_string(&Vec::<PacoAction>::new()).unwrap(),
+        Err(error) => serde_json::to_string(&error).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Square;
+    use crate::{execute_sequence, BoardPosition};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn limits(max_depth: usize) -> SearchLimits {
+        SearchLimits {
+            max_depth,
+            ..SearchLimits::default()
+        }
+    }
+
+    #[test]
+    fn finds_immediate_win() {
+        use crate::PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert(pos("d5"), Square::black(King));
+        squares.insert(pos("d1"), Square::white(Rook));
+        squares.insert(pos("h7"), Square::black(Pawn));
+        let board = DenseBoard::from_squares(squares);
+
+        let result = AlphaBeta::new(limits(3)).search(&board).unwrap().unwrap();
+        assert_eq!(
+            result.best_move,
+            vec![PacoAction::Lift(pos("d1")), PacoAction::Place(pos("d5"))]
+        );
+        assert_eq!(result.score, WIN_SCORE - 1);
+        assert_eq!(result.depth, 1);
+    }
+
+    /// Black must move the king away or it will be united with the rook.
+    #[test]
+    fn avoids_immediate_loss() {
+        use crate::PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert(pos("d5"), Square::black(King));
+        squares.insert(pos("d1"), Square::white(Rook));
+        let mut board = DenseBoard::from_squares(squares);
+        board.current_player = PlayerColor::Black;
+
+        let result = AlphaBeta::new(limits(2)).search(&board).unwrap().unwrap();
+        assert!(result.score > -WIN_SCORE / 2);
+        let after = execute_sequence(&board, result.best_move, PlayerColor::Black).unwrap();
+        let replies = legal_moves(&after, DeadEnds::Exclude).unwrap();
+        assert!(replies.iter().all(|m| !m.board.victory_state().is_over()));
+    }
+
+    #[test]
+    fn principal_variation_is_legal() {
+        let board = DenseBoard::new();
+        let result = AlphaBeta::new(limits(2)).search(&board).unwrap().unwrap();
+
+        assert_eq!(result.depth, 2);
+        assert_eq!(result.principal_variation.len(), 2);
+        assert_eq!(result.principal_variation[0], result.best_move);
+        let mut after = board;
+        for turn in result.principal_variation {
+            let player = after.controlling_player();
+            after = execute_sequence(&after, turn, player).unwrap();
+        }
+    }
+
+    #[test]
+    fn node_limit() {
+        let board = DenseBoard::new();
+        let mut engine = AlphaBeta::new(SearchLimits {
+            max_depth: 10,
+            time_limit: None,
+            node_limit: Some(100),
+        });
+        let result = engine.search(&board).unwrap().unwrap();
+
+        assert!(result.nodes <= 101);
+        assert!(result.depth >= 1);
+        execute_sequence(&board, result.best_move, PlayerColor::White).unwrap();
+    }
+
+    #[test]
+    fn time_limit() {
+        let board = DenseBoard::new();
+        let mut engine = AlphaBeta::new(SearchLimits {
+            max_depth: 100,
+            time_limit: Some(Duration::from_millis(200)),
+            node_limit: None,
+        });
+        let start = Instant::now();
+        let result = engine.search(&board).unwrap().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        execute_sequence(&board, result.best_move, PlayerColor::White).unwrap();
+    }
+
+    #[test]
+    fn json_entry_point() {
+        let response = ai_search_json(
+            r#"{"actions": [{"Lift": 12}, {"Place": 28}], "limits": {"max_depth": 1}}"#,
+        );
+        let turn: Vec<PacoAction> = serde_json::from_str(&response).unwrap();
+        assert_eq!(turn.len(), 2);
+        assert_eq!(ai_search_json("not json"), "\"InputJsonMalformed\"");
+    }
+
+    #[test]
+    fn json_entry_point_with_start() {
+        // Neither square holds a piece in the classic position.
+        let start = "8/7p/8/7k/8/2R5/1K6/8 w 0 ---- - - - - -";
+        let response = ai_search_json(&format!(
+            r#"{{"start": "{}", "actions": [], "limits": {{"max_depth": 1}}}}"#,
+            start
+        ));
+        let turn: Vec<PacoAction> = serde_json::from_str(&response).unwrap();
+        assert!(matches!(turn[0], PacoAction::Lift(p) if p == pos("b2") || p == pos("c3")));
+        let board = fen::parse_fen(start).unwrap();
+        execute_sequence(&board, turn, PlayerColor::White).unwrap();
+
+        let response = ai_search_json(
+            r#"{"start": "8/8/8/8/8/8/8/K7 w 0 ---- - - - - -",
+                "actions": [], "limits": {"max_depth": 1}}"#,
+        );
+        assert_eq!(
+            response,
+            serde_json::to_string(&PacoError::InvalidPosition(vec![
+                crate::PositionProblem::MissingKing(PlayerColor::Black)
+            ]))
+            .unwrap()
+        );
+        let response =
+            ai_search_json(r#"{"start": "8/8", "actions": [], "limits": {"max_depth": 1}}"#);
+        assert_eq!(response, "\"InputJsonMalformed\"");
+    }
+}
@@ -0,0 +1,313 @@
+//! Exports the chain reaction graph of a position for teaching and analysis.
+//!
+//! Nodes are the states of the board during a turn, described by what the
+//! player holds in their hand. Edges are the actions leading from one state
+//! to the next. The graph starts at the given board and ends where the turn
+//! is over, which is highlighted, especially when the king was united with.
+//! The graph can be written as Graphviz DOT or serialized as JSON.
+
+use crate::{explore_moves, DenseBoard, Hand, PacoAction, PacoBoard, PacoError, VictoryState};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum NodeKind {
+    /// The board the graph starts from.
+    Start,
+    /// A state in the middle of the turn.
+    Chain,
+    /// The turn is over.
+    Settled,
+    /// The turn is over and the opponent king is in a union.
+    KingUnion,
+    /// The turn can not be finished from here.
+    DeadEnd,
+    /// The depth limit was reached before the state was explored.
+    Unexplored,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChainNode {
+    pub id: usize,
+    pub kind: NodeKind,
+    /// What the player holds in this state.
+    pub hand: Hand,
+    /// Number of actions from the start.
+    pub depth: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChainEdge {
+    pub from: usize,
+    pub to: usize,
+    pub action: PacoAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChainGraph {
+    pub nodes: Vec<ChainNode>,
+    pub edges: Vec<ChainEdge>,
+}
+
+/// Builds the graph of all states reachable from `board` within the rest of
+/// the current turn. The turn is explored the same way as for `legal_moves`,
+/// states further than `max_depth` actions away from the start are left out.
+/// With a `max_depth` of zero the graph only holds the start.
+pub fn chain_graph(board: &DenseBoard, max_depth: usize) -> Result<ChainGraph, PacoError> {
+    let player = board.controlling_player();
+    let mut graph = ChainGraph {
+        nodes: vec![ChainNode {
+            id: 0,
+            kind: NodeKind::Start,
+            hand: board.lifted_piece.clone(),
+            depth: 0,
+        }],
+        edges: vec![],
+    };
+    if board.victory_state().is_over() || max_depth == 0 {
+        return Ok(graph);
+    }
+
+    let explored = explore_moves(board.clone(), |b| {
+        b.controlling_player() != player || b.victory_state().is_over()
+    })?;
+
+    // The `found_via` map points from each state to its parents, the graph
+    // needs the actions leaving each state. The start has no parent.
+    let mut children: HashMap<Option<&DenseBoard>, Vec<(PacoAction, &DenseBoard)>> = HashMap::new();
+    for (state, parents) in &explored.found_via {
+        for (action, parent) in parents {
+            children
+                .entry(parent.as_ref())
+                .or_default()
+                .push((*action, state));
+        }
+    }
+
+    let mut ids: HashMap<&DenseBoard, usize> = HashMap::new();
+    ids.insert(board, 0);
+    let mut todo_list: VecDeque<(Option<&DenseBoard>, usize)> = VecDeque::new();
+    todo_list.push_back((None, 0));
+
+    while let Some((state, id)) = todo_list.pop_front() {
+        let mut edges = children.remove(&state).unwrap_or_default();
+        if edges.is_empty() {
+            if id != 0 {
+                graph.nodes[id].kind = NodeKind::DeadEnd;
+            }
+            continue;
+        }
+        let depth = graph.nodes[id].depth;
+        if depth >= max_depth {
+            graph.nodes[id].kind = NodeKind::Unexplored;
+            continue;
+        }
+
+        // Keep the order of `actions` so the node ids don't depend on hashing.
+        let actions = state.unwrap_or(board).actions()?;
+        edges.sort_by_key(|(action, _)| actions.iter().position(|a| a == action));
+
+        for (action, b) in edges {
+            let to = match ids.get(b) {
+                Some(&to) => to,
+                None => {
+                    let to = graph.nodes.len();
+                    let kind = match b.victory_state() {
+                        VictoryState::PacoVictory(winner) if winner == player => {
+                            NodeKind::KingUnion
+                        }
+                        _ if b.controlling_player() != player || b.victory_state().is_over() => {
+                            NodeKind::Settled
+                        }
+                        _ => NodeKind::Chain,
+                    };
+                    graph.nodes.push(ChainNode {
+                        id: to,
+                        kind,
+                        hand: b.lifted_piece.clone(),
+                        depth: depth + 1,
+                    });
+                    if kind == NodeKind::Chain {
+                        todo_list.push_back((Some(b), to));
+                    }
+                    ids.insert(b, to);
+                    to
+                }
+            };
+            graph.edges.push(ChainEdge {
+                from: id,
+                to,
+                action,
+            });
+        }
+    }
+
+    Ok(graph)
+}
+
+impl ChainGraph {
+    /// Writes the graph in the Graphviz DOT language.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph chains {\n");
+        for node in &self.nodes {
+            let style = match node.kind {
+                NodeKind::Start => "shape=box",
+                NodeKind::Chain => "shape=ellipse",
+                NodeKind::Settled => "shape=box, style=filled, fillcolor=lightgrey",
+                NodeKind::KingUnion => "shape=doubleoctagon, style=filled, fillcolor=gold",
+                NodeKind::DeadEnd => "shape=box, style=dashed",
+                NodeKind::Unexplored => "shape=ellipse, style=dotted",
+            };
+            writeln!(
+                dot,
+                "  n{} [label=\"{}\", {}];",
+                node.id,
+                node_label(node),
+                style
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "  n{} -> n{} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                action_label(edge.action)
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn node_label(node: &ChainNode) -> String {
+    match node.kind {
+        NodeKind::Start => return "Start".to_string(),
+        NodeKind::KingUnion => return "Ŝako".to_string(),
+        NodeKind::Settled => return "Settled".to_string(),
+        _ => {}
+    }
+    match &node.hand {
+        Hand::Empty => "Empty hand".to_string(),
+        Hand::Single { piece, position } => format!("{} {}", piece.letter(), position),
+        Hand::Pair {
+            piece,
+            partner,
+            position,
+        } => format!(
+            "{}{} {}",
+            piece.letter(),
+            partner.letter().to_ascii_lowercase(),
+            position
+        ),
+    }
+}
+
+fn action_label(action: PacoAction) -> String {
+    match action {
+        PacoAction::Lift(position) => format!("lift {}", position),
+        PacoAction::Place(position) => format!("place {}", position),
+        PacoAction::Promote(piece) => format!("promote {}", piece.letter()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::BoardPosition;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    /// The rook can take over the union on d4 and the bishop reaches the king.
+    const CHAIN: &str = "8/8/5k2/8/R2e4/8/8/7K w 0 ---- - - - - -";
+
+    #[test]
+    fn chain_to_the_king() {
+        let board = parse_fen(CHAIN).unwrap();
+        let graph = chain_graph(&board, 10).unwrap();
+
+        let king_unions: Vec<&ChainNode> = graph
+            .nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::KingUnion)
+            .collect();
+        assert_eq!(king_unions.len(), 1);
+        assert_eq!(king_unions[0].depth, 3);
+
+        // Follow the edges back to the start.
+        let mut path = vec![];
+        let mut id = king_unions[0].id;
+        while id != 0 {
+            let edge = graph.edges.iter().find(|e| e.to == id).unwrap();
+            path.push(edge.action);
+            id = edge.from;
+        }
+        path.reverse();
+        assert_eq!(
+            path,
+            vec![
+                PacoAction::Lift(pos("a4")),
+                PacoAction::Place(pos("d4")),
+                PacoAction::Place(pos("f6"))
+            ]
+        );
+        assert!(graph.nodes.iter().all(|n| n.kind != NodeKind::Unexplored));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let board = parse_fen(CHAIN).unwrap();
+        let graph = chain_graph(&board, 1).unwrap();
+        assert!(graph.nodes.iter().all(|n| n.depth <= 1));
+        assert!(graph.nodes.iter().any(|n| n.kind == NodeKind::Unexplored));
+        assert!(graph.edges.iter().all(|e| e.from == 0));
+    }
+
+    #[test]
+    fn depth_zero_is_only_the_start() {
+        let board = parse_fen(CHAIN).unwrap();
+        let graph = chain_graph(&board, 0).unwrap();
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!(graph.nodes[0].kind, NodeKind::Start);
+        assert!(graph.edges.is_empty());
+    }
+
+    /// The graph has the same complete moves as `legal_moves`.
+    #[test]
+    fn settled_nodes_match_legal_moves() {
+        let board = parse_fen(CHAIN).unwrap();
+        let graph = chain_graph(&board, 10).unwrap();
+        let moves = crate::legal_moves(&board, crate::DeadEnds::Exclude).unwrap();
+        let settled = graph
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Settled | NodeKind::KingUnion))
+            .count();
+        assert_eq!(settled, moves.len());
+        assert_eq!(graph, chain_graph(&board, 10).unwrap());
+    }
+
+    #[test]
+    fn dot_and_json() {
+        let board = parse_fen(CHAIN).unwrap();
+        let graph = chain_graph(&board, 10).unwrap();
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph chains {\n"));
+        assert!(dot.contains("[label=\"R a4\", shape=ellipse]"));
+        assert!(dot.contains("[label=\"Ŝako\", shape=doubleoctagon"));
+        assert!(dot.contains("[label=\"place d4\"]"));
+        assert_eq!(dot.matches(" -> ").count(), graph.edges.len());
+
+        let json: serde_json::Value = serde_json::to_value(&graph).unwrap();
+        assert_eq!(json["nodes"][0]["kind"], "Start");
+        assert_eq!(json["edges"].as_array().unwrap().len(), graph.edges.len());
+    }
+}
@@ -0,0 +1,412 @@
+//! Compact binary encoding of actions and action histories, for storage,
+//! short links to shared games and small websocket messages.
+//!
+//! Each action is a single byte, like in the `export` module, but promotions
+//! keep their piece:
+//!
+//! | Byte       | Action                                             |
+//! |------------|----------------------------------------------------|
+//! | `1..=64`   | `Lift` on square `byte - 1`                        |
+//! | `65..=128` | `Place` on square `byte - 65`                      |
+//! | `129..=132`| `Promote` to a rook, knight, bishop or queen       |
+//!
+//! A history starts with the format version and a flags byte. Bit 0 of the
+//! flags says that every action is followed by its timestamp. Timestamps are
+//! milliseconds since the unix epoch, stored as the difference to the previous
+//! timestamp in a zigzag encoded LEB128 varint. The first one is relative to
+//! the epoch. For text, the bytes are written as URL safe base64 without
+//! padding.
+
+use crate::{BoardPosition, DenseBoard, PacoAction, PacoBoard, PacoError, PieceType};
+
+/// The format version written by `History::encode`.
+pub const VERSION: u8 = 1;
+
+const FLAG_TIMESTAMPS: u8 = 1;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    #[error("The history is empty, it needs at least a version and flags.")]
+    MissingHeader,
+    #[error("Version {0} of the history format is not supported.")]
+    UnknownVersion(u8),
+    #[error("The flags {0:#04x} are not supported.")]
+    UnknownFlags(u8),
+    #[error("Byte {0} does not encode an action.")]
+    UnknownAction(u8),
+    #[error("{0:?} can not be encoded, pawns and kings are no promotion targets.")]
+    UnencodableAction(PacoAction),
+    #[error("The timestamp of action {0} is missing or too large.")]
+    BrokenTimestamp(usize),
+    #[error("Action {index} can not be executed: {error}")]
+    IllegalAction { index: usize, error: PacoError },
+    #[error("'{0}' is not a base64 character.")]
+    Base64Character(char),
+    #[error("The base64 text has an impossible length.")]
+    Base64Length,
+}
+
+pub fn encode_action(action: PacoAction) -> Result<u8, CodecError> {
+    use PieceType::*;
+    match action {
+        PacoAction::Lift(position) => Ok(1 + position.0),
+        PacoAction::Place(position) => Ok(65 + position.0),
+        PacoAction::Promote(Rook) => Ok(129),
+        PacoAction::Promote(Knight) => Ok(130),
+        PacoAction::Promote(Bishop) => Ok(131),
+        PacoAction::Promote(Queen) => Ok(132),
+        PacoAction::Promote(Pawn) | PacoAction::Promote(King) => {
+            Err(CodecError::UnencodableAction(action))
+        }
+    }
+}
+
+pub fn decode_action(byte: u8) -> Result<PacoAction, CodecError> {
+    use PieceType::*;
+    match byte {
+        1..=64 => Ok(PacoAction::Lift(BoardPosition(byte - 1))),
+        65..=128 => Ok(PacoAction::Place(BoardPosition(byte - 65))),
+        129 => Ok(PacoAction::Promote(Rook)),
+        130 => Ok(PacoAction::Promote(Knight)),
+        131 => Ok(PacoAction::Promote(Bishop)),
+        132 => Ok(PacoAction::Promote(Queen)),
+        _ => Err(CodecError::UnknownAction(byte)),
+    }
+}
+
+/// The actions of a game, optionally with the time each one was done.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct History {
+    actions: Vec<PacoAction>,
+    timestamps: Option<Vec<i64>>,
+}
+
+impl History {
+    pub fn new(actions: Vec<PacoAction>) -> Self {
+        History {
+            actions,
+            timestamps: None,
+        }
+    }
+
+    /// Timestamps are milliseconds since the unix epoch.
+    pub fn with_timestamps(stamped: Vec<(PacoAction, i64)>) -> Self {
+        let (actions, timestamps) = stamped.into_iter().unzip();
+        History {
+            actions,
+            timestamps: Some(timestamps),
+        }
+    }
+
+    pub fn actions(&self) -> &[PacoAction] {
+        &self.actions
+    }
+
+    pub fn timestamps(&self) -> Option<&[i64]> {
+        self.timestamps.as_deref()
+    }
+
+    /// Fails for promotions to a pawn or a king, which no game contains.
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::with_capacity(2 + self.actions.len() * 3);
+        bytes.push(VERSION);
+        match &self.timestamps {
+            None => {
+                bytes.push(0);
+                for &action in &self.actions {
+                    bytes.push(encode_action(action)?);
+                }
+            }
+            Some(timestamps) => {
+                bytes.push(FLAG_TIMESTAMPS);
+                let mut previous = 0i64;
+                for (&action, &timestamp) in self.actions.iter().zip(timestamps) {
+                    bytes.push(encode_action(action)?);
+                    write_varint(&mut bytes, zigzag(timestamp.wrapping_sub(previous)));
+                    previous = timestamp;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a history and checks that all actions can be executed one after
+    /// the other, starting with the given board.
+    pub fn decode(bytes: &[u8], start: &DenseBoard) -> Result<Self, CodecError> {
+        let (version, flags) = match bytes {
+            [version, flags, ..] => (*version, *flags),
+            _ => return Err(CodecError::MissingHeader),
+        };
+        if version != VERSION {
+            return Err(CodecError::UnknownVersion(version));
+        }
+        if flags & !FLAG_TIMESTAMPS != 0 {
+            return Err(CodecError::UnknownFlags(flags));
+        }
+
+        let mut board = start.clone();
+        let mut actions = Vec::new();
+        let mut timestamps = if flags & FLAG_TIMESTAMPS != 0 {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        let mut rest = &bytes[2..];
+        while let Some((&byte, tail)) = rest.split_first() {
+            let index = actions.len();
+            let action = decode_action(byte)?;
+            board
+                .execute(action)
+                .map_err(|error| CodecError::IllegalAction { index, error })?;
+            actions.push(action);
+            rest = tail;
+
+            if let Some(timestamps) = &mut timestamps {
+                let (delta, tail) = read_varint(rest).ok_or(CodecError::BrokenTimestamp(index))?;
+                let previous = timestamps.last().copied().unwrap_or(0i64);
+                timestamps.push(previous.wrapping_add(unzigzag(delta)));
+                rest = tail;
+            }
+        }
+
+        Ok(History {
+            actions,
+            timestamps,
+        })
+    }
+
+    pub fn to_base64(&self) -> Result<String, CodecError> {
+        Ok(encode_base64(&self.encode()?))
+    }
+
+    pub fn from_base64(text: &str, start: &DenseBoard) -> Result<Self, CodecError> {
+        History::decode(&decode_base64(text)?, start)
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Returns the value and the remaining bytes, or None if the varint is cut
+/// off or does not fit into 64 bits.
+fn read_varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        let bits = u64::from(byte & 0x7f);
+        if i == 9 && bits > 1 {
+            return None;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+/// URL safe base64 without padding.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 4 / 3 + 2);
+    for chunk in bytes.chunks(3) {
+        let mut buffer = [0u8; 3];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let group = u32::from(buffer[0]) << 16 | u32::from(buffer[1]) << 8 | u32::from(buffer[2]);
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0x3f;
+            text.push(BASE64_ALPHABET[index as usize] as char);
+        }
+    }
+    text
+}
+
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, CodecError> {
+    let values = text
+        .chars()
+        .map(
+            |c| match BASE64_ALPHABET.iter().position(|&a| a as char == c) {
+                Some(value) => Ok(value as u32),
+                None => Err(CodecError::Base64Character(c)),
+            },
+        )
+        .collect::<Result<Vec<u32>, CodecError>>()?;
+
+    let mut bytes = Vec::with_capacity(values.len() * 3 / 4);
+    for chunk in values.chunks(4) {
+        if chunk.len() == 1 {
+            return Err(CodecError::Base64Length);
+        }
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |group, (i, value)| group | value << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    #[test]
+    fn all_actions() {
+        for byte in 0..=255u8 {
+            match decode_action(byte) {
+                Ok(action) => assert_eq!(encode_action(action), Ok(byte)),
+                Err(error) => {
+                    assert!(byte == 0 || byte > 132);
+                    assert_eq!(error, CodecError::UnknownAction(byte));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn promotion_keeps_the_piece() {
+        let start = parse_fen("4k3/3P4/8/8/8/8/8/4K3 w 0 ---- - - - - -").unwrap();
+        let actions = vec![
+            PacoAction::Lift(pos("d7")),
+            PacoAction::Place(pos("d8")),
+            PacoAction::Promote(PieceType::Knight),
+        ];
+        let history = History::new(actions.clone());
+        assert_eq!(history.encode(), Ok(vec![1, 0, 52, 124, 130]));
+        assert_eq!(
+            History::decode(&history.encode().unwrap(), &start),
+            Ok(history)
+        );
+    }
+
+    #[test]
+    fn promotion_to_pawn_or_king_is_rejected() {
+        for piece in [PieceType::Pawn, PieceType::King].iter() {
+            let action = PacoAction::Promote(*piece);
+            assert_eq!(
+                encode_action(action),
+                Err(CodecError::UnencodableAction(action))
+            );
+            assert_eq!(
+                History::new(vec![action]).encode(),
+                Err(CodecError::UnencodableAction(action))
+            );
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let start = DenseBoard::new();
+        let history = History::with_timestamps(vec![
+            (PacoAction::Lift(pos("e2")), 1_607_000_000_000),
+            (PacoAction::Place(pos("e4")), 1_607_000_001_500),
+            (PacoAction::Lift(pos("e7")), 1_607_000_001_400),
+        ]);
+        let bytes = history.encode().unwrap();
+        assert_eq!(bytes[1], FLAG_TIMESTAMPS);
+        // After the first timestamp, the deltas fit into two bytes.
+        assert_eq!(bytes.len(), 2 + 1 + 6 + 1 + 2 + 1 + 2);
+        let decoded = History::decode(&bytes, &start).unwrap();
+        assert_eq!(decoded, history);
+        assert_eq!(
+            decoded.timestamps(),
+            Some(&[1_607_000_000_000, 1_607_000_001_500, 1_607_000_001_400][..])
+        );
+    }
+
+    #[test]
+    fn decoder_checks_the_history() {
+        let start = DenseBoard::new();
+        assert_eq!(History::decode(&[], &start), Err(CodecError::MissingHeader));
+        assert_eq!(
+            History::decode(&[2, 0], &start),
+            Err(CodecError::UnknownVersion(2))
+        );
+        assert_eq!(
+            History::decode(&[1, 2], &start),
+            Err(CodecError::UnknownFlags(2))
+        );
+        assert_eq!(
+            History::decode(&[1, 0, 200], &start),
+            Err(CodecError::UnknownAction(200))
+        );
+        // Lifting from e4 in the starting position.
+        assert_eq!(
+            History::decode(&[1, 0, 29], &start),
+            Err(CodecError::IllegalAction {
+                index: 0,
+                error: PacoError::ActionNotLegal
+            })
+        );
+        assert_eq!(
+            History::decode(&[1, 1, 13, 0x80], &start),
+            Err(CodecError::BrokenTimestamp(0))
+        );
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg");
+        assert_eq!(encode_base64(b"fo"), "Zm8");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(&[0xfb, 0xff]), "-_8");
+        assert_eq!(decode_base64("Zm9vYg"), Ok(b"foob".to_vec()));
+        assert_eq!(decode_base64("Zm9vY"), Err(CodecError::Base64Length));
+        assert_eq!(
+            decode_base64("Zm9v="),
+            Err(CodecError::Base64Character('='))
+        );
+    }
+
+    /// Random games survive the round trip through base64, with and without
+    /// timestamps.
+    #[quickcheck]
+    fn round_trip_random_game(seed: u64) -> bool {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let start: DenseBoard = rng.gen();
+        let mut board = start.clone();
+        let mut stamped = Vec::new();
+        let mut time: i64 = rng.gen_range(0, 1 << 42);
+        for _ in 0..100 {
+            let actions = board.actions().unwrap();
+            let action = match actions.choose(&mut rng) {
+                Some(&action) => action,
+                None => break,
+            };
+            board.execute_trusted(action).unwrap();
+            time += rng.gen_range(-1000, 100_000);
+            stamped.push((action, time));
+        }
+
+        let with_time = History::with_timestamps(stamped);
+        let without_time = History::new(with_time.actions().to_vec());
+        [with_time, without_time].iter().all(|history| {
+            let text = history.to_base64().unwrap();
+            History::from_base64(&text, &start).as_ref() == Ok(history)
+        })
+    }
+}
@@ -0,0 +1,243 @@
+//! Static evaluation of a position. The score is split into terms, so the
+//! engines can use the total while the analysis can show where it comes from.
+//!
+//! All scores are given from the point of view of white: Positive numbers
+//! favour white, negative numbers favour black. Use `relative_to` to get the
+//! total for one player. The unit is roughly a hundredth of a pawn.
+
+use crate::{determine_all_threats, DenseBoard, PacoBoard, PacoError, PieceType, PlayerColor};
+use serde::{Deserialize, Serialize};
+
+/// Penalty when the king is threatened directly, that is, when the opponent
+/// can form a union with it next turn.
+const KING_THREATENED: i32 = 1000;
+/// Penalty for each threatened square next to the king.
+const KING_NEIGHBOUR_THREATENED: i32 = 20;
+/// Bonus for each square a side threatens.
+const MOBILITY: i32 = 2;
+/// Bonus for each castling right that is still available.
+const CASTLING_RIGHT: i32 = 15;
+
+/// The result of `evaluate` with one entry for each term.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evaluation {
+    /// Sum of all the terms below.
+    pub total: i32,
+    /// Value of all pieces on the board. This only changes by promotion.
+    pub material: i32,
+    /// A union binds one piece of each side. Binding an opponent piece that
+    /// is worth more than your own piece in the union is good for you.
+    pub unions: i32,
+    /// How much the black king is exposed to threats minus the same for the
+    /// white king.
+    pub king_exposure: i32,
+    /// Squares threatened by white minus squares threatened by black.
+    pub mobility: i32,
+    /// How far the pawns have walked towards promotion.
+    pub pawn_advancement: i32,
+    /// Castling rights that are still available.
+    pub castling: i32,
+}
+
+impl Evaluation {
+    /// The total score from the point of view of the given player.
+    pub fn relative_to(&self, player: PlayerColor) -> i32 {
+        match player {
+            PlayerColor::White => self.total,
+            PlayerColor::Black => -self.total,
+        }
+    }
+}
+
+/// Value of a single piece. The king has no value, because uniting with it
+/// ends the game. This is handled by the king exposure instead.
+pub fn piece_value(piece: PieceType) -> i32 {
+    match piece {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 300,
+        PieceType::Bishop => 300,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+/// Evaluates the position without looking ahead. The threats are computed
+/// for both players, so the turn must be finished.
+pub fn evaluate(board: &DenseBoard) -> Result<Evaluation, PacoError> {
+    if !board.is_settled() || board.promotion.is_some() {
+        return Err(PacoError::UnfinishedTurn);
+    }
+    let mut evaluation = Evaluation::default();
+
+    for &(color, sign) in &[(PlayerColor::White, 1), (PlayerColor::Black, -1)] {
+        let own = board.pieces_of_color(color);
+        let opponent = board.pieces_of_color(color.other());
+
+        for (position, piece) in own.iter().enumerate() {
+            let piece = match piece {
+                Some(piece) => *piece,
+                None => continue,
+            };
+            evaluation.material += sign * piece_value(piece);
+            if piece == PieceType::Pawn {
+                let rank = position as i32 / 8;
+                let advancement = match color {
+                    PlayerColor::White => rank - 1,
+                    PlayerColor::Black => 6 - rank,
+                };
+                evaluation.pawn_advancement += sign * 2 * advancement * advancement;
+            }
+        }
+
+        let mut attacker = board.clone();
+        attacker.current_player = color;
+        let threats = determine_all_threats(&attacker)?;
+        evaluation.mobility += sign * MOBILITY * threats.iter().filter(|t| t.0).count() as i32;
+
+        if let Some(king) = opponent.iter().position(|&p| p == Some(PieceType::King)) {
+            if threats[king].0 {
+                evaluation.king_exposure += sign * KING_THREATENED;
+            }
+            let neighbours = king_neighbours(king).filter(|&n| threats[n].0).count() as i32;
+            evaluation.king_exposure += sign * KING_NEIGHBOUR_THREATENED * neighbours;
+        }
+    }
+
+    for (white, black) in board.white.iter().zip(&board.black) {
+        if let (Some(white), Some(black)) = (white, black) {
+            evaluation.unions += (piece_value(*black) - piece_value(*white)) / 2;
+        }
+    }
+
+    let castling = board.castling;
+    let rights = |queen_side: bool, king_side: bool| queen_side as i32 + king_side as i32;
+    evaluation.castling = CASTLING_RIGHT
+        * (rights(castling.white_queen_side, castling.white_king_side)
+            - rights(castling.black_queen_side, castling.black_king_side));
+
+    evaluation.total = evaluation.material
+        + evaluation.unions
+        + evaluation.king_exposure
+        + evaluation.mobility
+        + evaluation.pawn_advancement
+        + evaluation.castling;
+    Ok(evaluation)
+}
+
+/// Indices of all squares that touch the given square.
+fn king_neighbours(position: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((position % 8) as i32, (position / 8) as i32);
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(nx, ny)| (nx, ny) != (x, y) && (0..8).contains(&nx) && (0..8).contains(&ny))
+        .map(|(nx, ny)| (nx + 8 * ny) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::testing::RandomBoard;
+    use crate::{BoardPosition, Hand};
+
+    #[test]
+    fn initial_position_is_balanced() {
+        let evaluation = evaluate(&DenseBoard::new()).unwrap();
+        assert_eq!(evaluation.total, 0);
+        assert_eq!(evaluation.material, 0);
+        assert_eq!(evaluation.castling, 0);
+    }
+
+    #[test]
+    fn threatened_king_is_exposed() {
+        // The white rook threatens the black king.
+        let board = parse_fen("4k3/8/8/8/8/8/8/K3R3 w 0 ahah - - - - -").unwrap();
+        let evaluation = evaluate(&board).unwrap();
+        assert!(evaluation.king_exposure >= KING_THREATENED);
+        assert!(evaluation.relative_to(PlayerColor::White) > 0);
+        assert!(evaluation.relative_to(PlayerColor::Black) < 0);
+    }
+
+    #[test]
+    fn unions_and_advancement() {
+        // A white pawn holds the black queen in a union, while a black pawn
+        // is about to promote.
+        let board = parse_fen("4k3/8/8/3F4/8/8/p7/4K3 w 0 ahah - - - - -").unwrap();
+        let evaluation = evaluate(&board).unwrap();
+        assert_eq!(evaluation.unions, (900 - 100) / 2);
+        assert!(evaluation.pawn_advancement < 0);
+    }
+
+    #[test]
+    fn unfinished_turn() {
+        let mut board = DenseBoard::new();
+        board
+            .execute(crate::PacoAction::Lift(BoardPosition::new(4, 1)))
+            .unwrap();
+        assert_eq!(evaluate(&board), Err(PacoError::UnfinishedTurn));
+
+        let board = parse_fen("3Pk3/8/8/8/8/8/8/K7 w 0 ---- - - - d8 -").unwrap();
+        assert_eq!(evaluate(&board), Err(PacoError::UnfinishedTurn));
+    }
+
+    #[test]
+    fn king_neighbours_stay_on_the_board() {
+        assert_eq!(king_neighbours(0).count(), 3);
+        assert_eq!(king_neighbours(7).count(), 3);
+        assert_eq!(king_neighbours(4).count(), 5);
+        assert_eq!(king_neighbours(27).count(), 8);
+    }
+
+    /// Swaps the colours and flips the board vertically, together with the
+    /// castling rights, the en passant square, the hand and the promotion.
+    fn mirror(board: &DenseBoard) -> DenseBoard {
+        let flip = |position: BoardPosition| BoardPosition(position.0 ^ 56);
+        let mut result = board.clone();
+        for position in 0..64 {
+            result.white[position ^ 56] = board.black[position];
+            result.black[position ^ 56] = board.white[position];
+        }
+        result.current_player = board.current_player.other();
+        result.lifted_piece = match board.lifted_piece {
+            Hand::Empty => Hand::Empty,
+            Hand::Single { piece, position } => Hand::Single {
+                piece,
+                position: flip(position),
+            },
+            Hand::Pair {
+                piece,
+                partner,
+                position,
+            } => Hand::Pair {
+                piece,
+                partner,
+                position: flip(position),
+            },
+        };
+        result.en_passant = board
+            .en_passant
+            .map(|(position, player)| (flip(position), player.other()));
+        result.promotion = board.promotion.map(flip);
+        let castling = board.castling;
+        result.castling.white_queen_side = castling.black_queen_side;
+        result.castling.white_king_side = castling.black_king_side;
+        result.castling.black_queen_side = castling.white_queen_side;
+        result.castling.black_king_side = castling.white_king_side;
+        result.castling.white_files = castling.black_files;
+        result.castling.black_files = castling.white_files;
+        result
+    }
+
+    /// A mirrored position is just as good for the other side.
+    #[quickcheck]
+    fn evaluation_is_symmetric(board: RandomBoard) -> bool {
+        match (evaluate(&board.0), evaluate(&mirror(&board.0))) {
+            (Ok(evaluation), Ok(mirrored)) => {
+                evaluation.total == -mirrored.total && evaluation.unions == -mirrored.unions
+            }
+            (Err(PacoError::UnfinishedTurn), Err(PacoError::UnfinishedTurn)) => true,
+            _ => false,
+        }
+    }
+}
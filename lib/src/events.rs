@@ -0,0 +1,255 @@
+//! Events that describe what an action did on the board. Clients use them to
+//! animate chains and play sounds without comparing boards.
+//!
+//! Each action reports at least one event. A `Place` that forms a union en
+//! passant reports `EnPassant` followed by `UnionFormed`, a `Place` that wins
+//! the game ends with `KingUnited` and `GameOver`.
+
+use crate::{BoardPosition, PieceType, PlayerColor, VictoryState};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PacoEvent {
+    /// A piece was lifted, together with the opponent piece when it was in a
+    /// union.
+    Lifted {
+        position: BoardPosition,
+        piece: PieceType,
+        partner: Option<PieceType>,
+    },
+    /// A single piece or a union moved to an empty square.
+    Moved {
+        from: BoardPosition,
+        to: BoardPosition,
+        piece: PieceType,
+        partner: Option<PieceType>,
+    },
+    /// A single piece was placed on a single opponent piece.
+    UnionFormed {
+        position: BoardPosition,
+        piece: PieceType,
+        partner: PieceType,
+    },
+    /// A piece took over a union and set the own piece there free. The freed
+    /// piece is now in the hand.
+    ChainContinued {
+        position: BoardPosition,
+        placed: PieceType,
+        lifted: PieceType,
+    },
+    /// A pawn united en passant and pulled back the opponent pawn.
+    EnPassant {
+        position: BoardPosition,
+        pawn_from: BoardPosition,
+    },
+    Castled {
+        king_from: BoardPosition,
+        king_to: BoardPosition,
+        rook_from: BoardPosition,
+        rook_to: BoardPosition,
+    },
+    /// A pawn reached the opponent home row and waits for its promotion.
+    PromotionScheduled { position: BoardPosition },
+    Promoted {
+        position: BoardPosition,
+        piece: PieceType,
+    },
+    /// A piece united with the king of the opponent.
+    KingUnited {
+        position: BoardPosition,
+        winner: PlayerColor,
+    },
+    /// The action ended the game.
+    GameOver { victory_state: VictoryState },
+}
+
+/// Records an event when the caller asked for them. Searches execute a lot of
+/// actions and don't pass a list.
+pub(crate) fn emit(events: &mut Option<&mut Vec<PacoEvent>>, event: PacoEvent) {
+    if let Some(events) = events {
+        events.push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::{DenseBoard, PacoAction, PacoBoard, PacoError};
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn events(board: &mut DenseBoard, action: PacoAction) -> Vec<PacoEvent> {
+        board.execute_with_events(action).unwrap()
+    }
+
+    #[test]
+    fn move_and_union() {
+        use PacoEvent::*;
+        let mut board = DenseBoard::new();
+        assert_eq!(
+            events(&mut board, PacoAction::Lift(pos("e2"))),
+            vec![Lifted {
+                position: pos("e2"),
+                piece: PieceType::Pawn,
+                partner: None
+            }]
+        );
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("e4"))),
+            vec![Moved {
+                from: pos("e2"),
+                to: pos("e4"),
+                piece: PieceType::Pawn,
+                partner: None
+            }]
+        );
+
+        let mut board = parse_fen("4k2r/8/8/3p4/4P3/8/8/4K2R w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("e4"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("d5"))),
+            vec![UnionFormed {
+                position: pos("d5"),
+                piece: PieceType::Pawn,
+                partner: PieceType::Pawn
+            }]
+        );
+    }
+
+    #[test]
+    fn chain_and_pair() {
+        use PacoEvent::*;
+        // A white knight in a union with a black pawn on d5.
+        let mut board = parse_fen("4k3/8/8/3d4/8/8/8/3RK3 w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("d1"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("d5"))),
+            vec![ChainContinued {
+                position: pos("d5"),
+                placed: PieceType::Rook,
+                lifted: PieceType::Knight
+            }]
+        );
+
+        let mut board = parse_fen("4k2r/8/8/3d4/8/8/8/4K3 w 0 ---- - - - - -").unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Lift(pos("d5"))),
+            vec![Lifted {
+                position: pos("d5"),
+                piece: PieceType::Knight,
+                partner: Some(PieceType::Pawn)
+            }]
+        );
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("c3"))),
+            vec![Moved {
+                from: pos("d5"),
+                to: pos("c3"),
+                piece: PieceType::Knight,
+                partner: Some(PieceType::Pawn)
+            }]
+        );
+    }
+
+    #[test]
+    fn en_passant() {
+        use PacoEvent::*;
+        let mut board = parse_fen("4k2r/8/8/8/2pP4/8/8/4K2R b 0 ---- d3 - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("c4"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("d3"))),
+            vec![
+                EnPassant {
+                    position: pos("d3"),
+                    pawn_from: pos("d4")
+                },
+                UnionFormed {
+                    position: pos("d3"),
+                    piece: PieceType::Pawn,
+                    partner: PieceType::Pawn
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn castling() {
+        let mut board = parse_fen("4k3/8/8/8/8/8/8/R3K2R w 0 ah-- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("e1"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("g1"))),
+            vec![PacoEvent::Castled {
+                king_from: pos("e1"),
+                king_to: pos("g1"),
+                rook_from: pos("h1"),
+                rook_to: pos("f1")
+            }]
+        );
+    }
+
+    #[test]
+    fn promotion() {
+        use PacoEvent::*;
+        let mut board = parse_fen("8/3P4/8/8/8/8/8/K6k w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("d7"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("d8"))),
+            vec![
+                Moved {
+                    from: pos("d7"),
+                    to: pos("d8"),
+                    piece: PieceType::Pawn,
+                    partner: None
+                },
+                PromotionScheduled {
+                    position: pos("d8")
+                }
+            ]
+        );
+        assert_eq!(
+            events(&mut board, PacoAction::Promote(PieceType::Queen)),
+            vec![Promoted {
+                position: pos("d8"),
+                piece: PieceType::Queen
+            }]
+        );
+    }
+
+    #[test]
+    fn king_united() {
+        use PacoEvent::*;
+        let mut board = parse_fen("4k3/8/8/8/8/8/8/4RK2 w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("e1"))).unwrap();
+        assert_eq!(
+            events(&mut board, PacoAction::Place(pos("e8"))),
+            vec![
+                UnionFormed {
+                    position: pos("e8"),
+                    piece: PieceType::Rook,
+                    partner: PieceType::King
+                },
+                KingUnited {
+                    position: pos("e8"),
+                    winner: PlayerColor::White
+                },
+                GameOver {
+                    victory_state: VictoryState::PacoVictory(PlayerColor::White)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn illegal_actions_report_nothing() {
+        let mut board = DenseBoard::new();
+        assert_eq!(
+            board.execute_with_events(PacoAction::Lift(pos("e7"))),
+            Err(PacoError::ActionNotLegal)
+        );
+        assert_eq!(board, DenseBoard::new());
+    }
+}
@@ -0,0 +1,636 @@
+//! This module implements an extension of X-Fen that can represent Paco Ŝako
+//! boards together with all of their state. It is compatible with the notation
+//! written by the frontend in `Fen.elm`, which in turn is mostly compatible with
+//! <https://vchess.club/#/variants/Pacosako>.
+//!
+//! Fen looks like this:
+//!
+//! ```text
+//! bqnrkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/NRBKRBNQ w 2 bedh - - - - - 0
+//! ```
+//!
+//! The space separated fields are:
+//!
+//!  1. The pieces on the board, starting with the 8th row.
+//!  2. The side to move, `w` or `b`. This is `DenseBoard::current_player`.
+//!  3. The vchess move count. The board does not count moves, so this is
+//!     ignored when reading and written as `0`, like `Fen.elm` does.
+//!  4. Castling rights as rook files for white queen side, white king side,
+//!     black queen side and black king side. A `-` marks a lost right. The
+//!     files are `ahah` for classic games and may differ in Fischer random
+//!     games, even between the players.
+//!  5. The en passant square or `-`.
+//!  6. The last union move (vchess ko rule), which we always write as `-`.
+//!  7. The lifted piece(s) together with the square they were lifted from or `-`.
+//!  8. The square of a pawn that still needs to be promoted or `-`.
+//!  9. The victory state, `-` for a running game.
+//! 10. Amount of half-moves since the last progress, see `DrawReason::NoProgress`.
+//!
+//! Fields 7 to 10 are extensions for boards with an active chain, a finished
+//! game or a draw counter. When they are missing, they are assumed to be `-`
+//! and `0`. A fen with only the pieces gets all castling rights that fit the
+//! kings and rooks on the board.
+//!
+//! Some state of the board is not part of the notation, so it is lost in a
+//! round trip: The position history used to detect repetitions, whether the
+//! turn in progress already made progress for `DrawReason::NoProgress`, and
+//! the `RuleSet`. Parsing always gives an empty history, no progress and the
+//! default rules.
+
+use crate::{
+    BoardPosition, Castling, CastlingSide, DenseBoard, DrawReason, Hand, PieceType, PlayerColor,
+    StartFiles, VictoryState,
+};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    #[error("The fen has {0} fields, but it needs 1, 6, 9 or 10.")]
+    FieldCount(usize),
+    #[error("The board needs to have 8 rows, but it has {0}.")]
+    RowCount(usize),
+    #[error("Row {0} of the board does not have 8 squares.")]
+    RowLength(usize),
+    #[error("'{0}' does not describe the pieces on a square.")]
+    UnknownPiece(char),
+    #[error("'{0}' is not a player, use 'w' or 'b'.")]
+    UnknownPlayer(String),
+    #[error("'{0}' is not a move count.")]
+    MoveCount(String),
+    #[error("'{0}' is not a number of half-moves.")]
+    HalfMoveCounter(String),
+    #[error("'{0}' does not describe castling rights.")]
+    Castling(String),
+    #[error("'{0}' is not a square.")]
+    Square(String),
+    #[error("'{0}' is not an en passant square.")]
+    EnPassant(String),
+    #[error("'{0}' does not describe lifted pieces of the side to move.")]
+    Hand(String),
+    #[error("'{0}' is not a victory state.")]
+    VictoryState(String),
+}
+
+/// The pieces on a single square, (white, black).
+type PiecesOnSquare = (Option<PieceType>, Option<PieceType>);
+
+/// Pair letters of the union notation. The lower case letter stands for a
+/// union of a black piece of the first type and a white piece of the second
+/// type. The upper case letter swaps the colors.
+const PAIR_LETTERS: [(char, PieceType, PieceType); 21] = {
+    use PieceType::*;
+    [
+        ('a', Pawn, Pawn),
+        ('c', Pawn, Rook),
+        ('d', Pawn, Knight),
+        ('e', Pawn, Bishop),
+        ('f', Pawn, Queen),
+        ('g', Pawn, King),
+        ('h', Rook, Rook),
+        ('i', Rook, Knight),
+        ('j', Rook, Bishop),
+        ('l', Rook, Queen),
+        ('m', Rook, King),
+        ('o', Knight, Knight),
+        ('s', Knight, Bishop),
+        ('t', Knight, Queen),
+        ('u', Knight, King),
+        ('v', Bishop, Bishop),
+        ('w', Bishop, Queen),
+        ('x', Bishop, King),
+        ('y', Queen, Queen),
+        ('z', Queen, King),
+        ('_', King, King),
+    ]
+};
+
+/// Writes the fen notation for the given Paco Ŝako board.
+pub fn write_fen(board: &DenseBoard) -> String {
+    let mut result = String::with_capacity(100);
+
+    for y in (0..8).rev() {
+        let mut empty_squares = 0;
+        for x in 0..8 {
+            let position = BoardPosition::new(x, y).0 as usize;
+            if let Some(c) = encode_square((board.white[position], board.black[position])) {
+                if empty_squares > 0 {
+                    write!(result, "{}", empty_squares).unwrap();
+                    empty_squares = 0;
+                }
+                result.push(c);
+            } else {
+                empty_squares += 1;
+            }
+        }
+        if empty_squares > 0 {
+            write!(result, "{}", empty_squares).unwrap();
+        }
+        if y != 0 {
+            result.push('/');
+        }
+    }
+
+    write!(
+        result,
+        " {} 0 {} {} - {} {} {} {}",
+        player_char(board.current_player),
+        write_castling(&board.castling),
+        write_optional_position(board.en_passant.map(|(p, _)| p)),
+        write_hand(&board.lifted_piece, board.current_player),
+        write_optional_position(board.promotion),
+        write_victory_state(board.victory_state),
+        board.draw_state.no_progress_half_moves,
+    )
+    .unwrap();
+
+    result
+}
+
+/// Reads a string in fen notation and converts it into a Paco Ŝako board.
+/// Reading the output of `write_fen` gives you back the same board, apart
+/// from the state that the notation does not contain, see the module
+/// documentation.
+pub fn parse_fen(input: &str) -> Result<DenseBoard, FenError> {
+    let fields: Vec<&str> = input.split_whitespace().collect();
+    if ![1, 6, 9, 10].contains(&fields.len()) {
+        return Err(FenError::FieldCount(fields.len()));
+    }
+
+    let mut board = DenseBoard::empty();
+    parse_pieces(fields[0], &mut board)?;
+
+    if fields.len() == 1 {
+        board.forfeit_impossible_castling();
+        return Ok(board);
+    }
+
+    board.current_player = parse_player(fields[1])?;
+    // The vchess move count, the board does not track it.
+    fields[2]
+        .parse::<u32>()
+        .map_err(|_| FenError::MoveCount(fields[2].to_owned()))?;
+    board.castling = parse_castling(fields[3])?;
+    board.en_passant = parse_en_passant(fields[4])?;
+    // fields[5] is the vchess union move, which we don't use.
+
+    if fields.len() >= 9 {
+        board.lifted_piece = parse_hand(fields[6], board.current_player)?;
+        board.promotion = parse_optional_position(fields[7])?;
+        board.victory_state = parse_victory_state(fields[8])?;
+    }
+
+    if fields.len() == 10 {
+        board.draw_state.no_progress_half_moves = fields[9]
+            .parse()
+            .map_err(|_| FenError::HalfMoveCounter(fields[9].to_owned()))?;
+    }
+
+    find_king_file(&mut board);
+
+    Ok(board)
+}
+
+fn parse_pieces(input: &str, board: &mut DenseBoard) -> Result<(), FenError> {
+    let rows: Vec<&str> = input.split('/').collect();
+    if rows.len() != 8 {
+        return Err(FenError::RowCount(rows.len()));
+    }
+
+    for (row_index, row) in rows.iter().enumerate() {
+        let y = 7 - row_index as u8;
+        let mut x: u8 = 0;
+        for c in row.chars() {
+            if x >= 8 {
+                return Err(FenError::RowLength(y as usize + 1));
+            }
+            if let Some(empty_squares) = c.to_digit(10) {
+                x += empty_squares as u8;
+            } else {
+                let (white, black) = decode_square(c)?;
+                let position = BoardPosition::new(x, y).0 as usize;
+                board.white[position] = white;
+                board.black[position] = black;
+                x += 1;
+            }
+        }
+        if x != 8 {
+            return Err(FenError::RowLength(y as usize + 1));
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes a character and returns which piece(s) this represents.
+fn decode_square(c: char) -> Result<PiecesOnSquare, FenError> {
+    let lower = c.to_ascii_lowercase();
+    let (white, black) = if let Some(piece) = PieceType::from_letter(c.to_ascii_uppercase()) {
+        (None, Some(piece))
+    } else if let Some((_, black, white)) = PAIR_LETTERS.iter().find(|p| p.0 == lower) {
+        (Some(*white), Some(*black))
+    } else {
+        return Err(FenError::UnknownPiece(c));
+    };
+
+    // Upper case letters swap the colors.
+    if c.is_ascii_uppercase() {
+        Ok((black, white))
+    } else {
+        Ok((white, black))
+    }
+}
+
+/// Basically inverts `decode_square`, returns None for an empty square.
+/// Note that a symmetric pair is written with a capital letter by convention.
+fn encode_square(square: PiecesOnSquare) -> Option<char> {
+    match square {
+        (None, None) => None,
+        (Some(white), None) => Some(white.letter()),
+        (None, Some(black)) => Some(black.letter().to_ascii_lowercase()),
+        (Some(white), Some(black)) => {
+            if let Some(pair) = PAIR_LETTERS.iter().find(|p| p.1 == black && p.2 == white) {
+                if black == white {
+                    Some(pair.0.to_ascii_uppercase())
+                } else {
+                    Some(pair.0)
+                }
+            } else {
+                let pair = PAIR_LETTERS.iter().find(|p| p.1 == white && p.2 == black)?;
+                Some(pair.0.to_ascii_uppercase())
+            }
+        }
+    }
+}
+
+fn player_char(player: PlayerColor) -> char {
+    match player {
+        PlayerColor::White => 'w',
+        PlayerColor::Black => 'b',
+    }
+}
+
+fn parse_player(input: &str) -> Result<PlayerColor, FenError> {
+    match input {
+        "w" => Ok(PlayerColor::White),
+        "b" => Ok(PlayerColor::Black),
+        _ => Err(FenError::UnknownPlayer(input.to_owned())),
+    }
+}
+
+pub(crate) fn write_castling(castling: &Castling) -> String {
+    let flag = |allowed: bool, file: u8| {
+        if allowed {
+            (b'a' + file) as char
+        } else {
+            '-'
+        }
+    };
+    let (white, black) = (castling.white_files, castling.black_files);
+    [
+        flag(castling.white_queen_side, white.queen_rook),
+        flag(castling.white_king_side, white.king_rook),
+        flag(castling.black_queen_side, black.queen_rook),
+        flag(castling.black_king_side, black.king_rook),
+    ]
+    .iter()
+    .collect()
+}
+
+/// Reads the castling rights. The files of the kings are set by
+/// `find_king_file`.
+pub(crate) fn parse_castling(input: &str) -> Result<Castling, FenError> {
+    let error = || FenError::Castling(input.to_owned());
+    let mut castling = Castling::new();
+    if input == "-" {
+        castling.white_queen_side = false;
+        castling.white_king_side = false;
+        castling.black_queen_side = false;
+        castling.black_king_side = false;
+        return Ok(castling);
+    }
+
+    let flags: Vec<char> = input.chars().collect();
+    if flags.len() != 4 {
+        return Err(error());
+    }
+    let file = |c: char| match c {
+        '-' => Ok(None),
+        'a'..='h' => Ok(Some(c as u8 - b'a')),
+        _ => Err(error()),
+    };
+    // Each player has their own rook files, a lost right keeps the classic one.
+    let start_files = |queen: Option<u8>, king: Option<u8>| {
+        let classic = StartFiles::classic();
+        let files = StartFiles {
+            king: classic.king,
+            queen_rook: queen.unwrap_or(classic.queen_rook),
+            king_rook: king.unwrap_or(classic.king_rook),
+        };
+        if files.queen_rook < files.king_rook {
+            Ok(files)
+        } else {
+            Err(error())
+        }
+    };
+    let (white_queen, white_king) = (file(flags[0])?, file(flags[1])?);
+    let (black_queen, black_king) = (file(flags[2])?, file(flags[3])?);
+    castling.white_files = start_files(white_queen, white_king)?;
+    castling.black_files = start_files(black_queen, black_king)?;
+    castling.white_queen_side = white_queen.is_some();
+    castling.white_king_side = white_king.is_some();
+    castling.black_queen_side = black_queen.is_some();
+    castling.black_king_side = black_king.is_some();
+    Ok(castling)
+}
+
+/// The fen only has the files of the rooks. The king of a player that can
+/// still castle stands between them on the home row.
+pub(crate) fn find_king_file(board: &mut DenseBoard) {
+    for &player in &[PlayerColor::White, PlayerColor::Black] {
+        let castling = board.castling;
+        if !castling.has_right(player, CastlingSide::Queen)
+            && !castling.has_right(player, CastlingSide::King)
+        {
+            continue;
+        }
+        let files = castling.files(player);
+        let row = if player == PlayerColor::White { 0 } else { 7 };
+        let pieces = board.pieces_of_color(player);
+        let king = (files.queen_rook + 1..files.king_rook)
+            .find(|&x| pieces[BoardPosition::new(x, row).0 as usize] == Some(PieceType::King));
+        if let Some(x) = king {
+            board.castling.files_mut(player).king = x;
+        }
+    }
+}
+
+fn write_optional_position(position: Option<BoardPosition>) -> String {
+    position.map_or_else(|| "-".to_owned(), |p| p.to_string())
+}
+
+fn parse_optional_position(input: &str) -> Result<Option<BoardPosition>, FenError> {
+    if input == "-" {
+        Ok(None)
+    } else {
+        BoardPosition::try_from(input)
+            .map(Some)
+            .map_err(|_| FenError::Square(input.to_owned()))
+    }
+}
+
+/// The en passant square is behind the pawn that moved two steps. This means
+/// we can read off the player that moved the pawn from the row.
+fn parse_en_passant(input: &str) -> Result<Option<(BoardPosition, PlayerColor)>, FenError> {
+    match parse_optional_position(input)? {
+        None => Ok(None),
+        Some(position) if position.y() == 2 => Ok(Some((position, PlayerColor::White))),
+        Some(position) if position.y() == 5 => Ok(Some((position, PlayerColor::Black))),
+        Some(_) => Err(FenError::EnPassant(input.to_owned())),
+    }
+}
+
+fn write_hand(hand: &Hand, current_player: PlayerColor) -> String {
+    let (square, position) = match *hand {
+        Hand::Empty => return "-".to_owned(),
+        Hand::Single { piece, position } => match current_player {
+            PlayerColor::White => ((Some(piece), None), position),
+            PlayerColor::Black => ((None, Some(piece)), position),
+        },
+        Hand::Pair {
+            piece,
+            partner,
+            position,
+        } => match current_player {
+            PlayerColor::White => ((Some(piece), Some(partner)), position),
+            PlayerColor::Black => ((Some(partner), Some(piece)), position),
+        },
+    };
+    // A hand is never empty at this point, so there always is a character.
+    format!("{}{}", encode_square(square).unwrap_or('-'), position)
+}
+
+fn parse_hand(input: &str, current_player: PlayerColor) -> Result<Hand, FenError> {
+    let error = || FenError::Hand(input.to_owned());
+    if input == "-" {
+        return Ok(Hand::Empty);
+    }
+
+    let mut chars = input.chars();
+    let (white, black) = decode_square(chars.next().ok_or_else(error)?)?;
+    let position = BoardPosition::try_from(chars.as_str()).map_err(|_| error())?;
+
+    let (piece, partner) = match current_player {
+        PlayerColor::White => (white, black),
+        PlayerColor::Black => (black, white),
+    };
+
+    match (piece, partner) {
+        (Some(piece), None) => Ok(Hand::Single { piece, position }),
+        (Some(piece), Some(partner)) => Ok(Hand::Pair {
+            piece,
+            partner,
+            position,
+        }),
+        _ => Err(error()),
+    }
+}
+
+fn write_victory_state(victory_state: VictoryState) -> String {
+    use VictoryState::*;
+    match victory_state {
+        Running => "-".to_owned(),
+        PacoVictory(player) => format!("paco-{}", player_char(player)),
+        TimeoutVictory(player) => format!("timeout-{}", player_char(player)),
+        Draw(DrawReason::Repetition) => "draw-repetition".to_owned(),
+        Draw(DrawReason::NoProgress) => "draw-no-progress".to_owned(),
+        Draw(DrawReason::AllPaired) => "draw-all-paired".to_owned(),
+    }
+}
+
+fn parse_victory_state(input: &str) -> Result<VictoryState, FenError> {
+    use VictoryState::*;
+    let error = || FenError::VictoryState(input.to_owned());
+    match input {
+        "-" => Ok(Running),
+        "draw-repetition" => Ok(Draw(DrawReason::Repetition)),
+        "draw-no-progress" => Ok(Draw(DrawReason::NoProgress)),
+        "draw-all-paired" => Ok(Draw(DrawReason::AllPaired)),
+        _ => {
+            if let Some(player) = input.strip_prefix("paco-") {
+                Ok(PacoVictory(parse_player(player).map_err(|_| error())?))
+            } else if let Some(player) = input.strip_prefix("timeout-") {
+                Ok(TimeoutVictory(parse_player(player).map_err(|_| error())?))
+            } else {
+                Err(error())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RandomBoard;
+    use crate::{PacoAction, PacoBoard, Progress, RuleSet};
+
+    /// The initial position as it would be written by `Fen.elm`.
+    const INITIAL_POSITION: &str =
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 ahah - - - - - 0";
+
+    #[test]
+    fn initial_position() {
+        assert_eq!(write_fen(&DenseBoard::new()), INITIAL_POSITION);
+        assert_eq!(parse_fen(INITIAL_POSITION).unwrap(), DenseBoard::new());
+    }
+
+    /// The example of `Fen.elm`. It has a move count and the players have
+    /// their rooks on different files.
+    const FRONTEND_EXAMPLE: &str = "bqnrkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/NRBKRBNQ w 2 bedh - -";
+
+    /// The frontend writes six fields and only reads the first one.
+    #[test]
+    fn frontend_compatibility() {
+        let board = parse_fen(FRONTEND_EXAMPLE).unwrap();
+        assert_eq!(
+            write_fen(&board),
+            "bqnrkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/NRBKRBNQ w 0 bedh - - - - - 0"
+        );
+        assert_eq!(board.draw_state.no_progress_half_moves, 0);
+        assert_eq!(
+            board.castling.king_square(PlayerColor::White),
+            BoardPosition::new(3, 0)
+        );
+        assert_eq!(
+            board.castling.king_square(PlayerColor::Black),
+            BoardPosition::new(4, 7)
+        );
+        assert!(board.validate().is_empty());
+
+        // This is what `Fen.elm` writes for the initial position.
+        let written = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 ahah - -";
+        assert_eq!(parse_fen(written).unwrap(), DenseBoard::new());
+
+        // Without the castling field, only rights that fit the pieces remain.
+        let only_pieces = parse_fen("bqnrkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/NRBKRBNQ").unwrap();
+        assert_eq!(write_castling(&only_pieces.castling), "---h");
+    }
+
+    #[test]
+    fn pairs() {
+        use PieceType::*;
+        let board = parse_fen("8/8/8/8/8/8/8/AcCuU_2 w 0 ---- - -").unwrap();
+        let expected = [
+            (Some(Pawn), Some(Pawn)),
+            (Some(Rook), Some(Pawn)),
+            (Some(Pawn), Some(Rook)),
+            (Some(King), Some(Knight)),
+            (Some(Knight), Some(King)),
+            (Some(King), Some(King)),
+            (None, None),
+            (None, None),
+        ];
+        for (x, pieces) in expected.iter().enumerate() {
+            assert_eq!(board.get_at(BoardPosition(x as u8)), *pieces);
+        }
+        assert_eq!(
+            write_fen(&board),
+            "8/8/8/8/8/8/8/AcCuU_2 w 0 ---- - - - - - 0"
+        );
+    }
+
+    /// Keeps castling, en passant, the hand and a pending promotion.
+    #[test]
+    fn game_state() {
+        use PacoAction::*;
+        let mut board = DenseBoard::new();
+        for action in [
+            Lift(BoardPosition(12)),
+            Place(BoardPosition(28)),
+            Lift(BoardPosition(51)),
+            Place(BoardPosition(35)),
+            Lift(BoardPosition(28)),
+            Place(BoardPosition(35)),
+            Lift(BoardPosition(63)),
+        ]
+        .iter()
+        {
+            board.execute(*action).unwrap();
+        }
+
+        let fen = write_fen(&board);
+        assert_eq!(
+            fen,
+            "rnbqkbn1/ppp1pppp/8/3A4/8/8/PPPP1PPP/RNBQKBNR b 0 aha- - - rh8 - - 0"
+        );
+        assert_eq!(write_fen(&parse_fen(&fen).unwrap()), fen);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_fen("8/8 w"), Err(FenError::FieldCount(2)));
+        assert_eq!(
+            parse_fen("8/8/8/8/8/8/8/8 w x ahah - -"),
+            Err(FenError::MoveCount("x".to_owned()))
+        );
+        assert_eq!(
+            parse_fen("8/8/8/8/8/8/8/8 w 0 ahah - - - - - x"),
+            Err(FenError::HalfMoveCounter("x".to_owned()))
+        );
+        assert_eq!(parse_fen("8/8/8/8"), Err(FenError::RowCount(4)));
+        assert_eq!(parse_fen("8/8/8/8/8/8/8/9"), Err(FenError::RowLength(1)));
+        assert_eq!(
+            parse_fen("8/8/8/8/8/8/8/7$"),
+            Err(FenError::UnknownPiece('$'))
+        );
+        assert_eq!(
+            parse_fen("8/8/8/8/8/8/8/8 w 0 ahah e4 -"),
+            Err(FenError::EnPassant("e4".to_owned()))
+        );
+        assert_eq!(
+            parse_fen("8/8/8/8/8/8/8/8 w 0 haha - -"),
+            Err(FenError::Castling("haha".to_owned()))
+        );
+    }
+
+    /// The state of the board that is part of the notation.
+    fn written_state(board: &DenseBoard) -> impl PartialEq + std::fmt::Debug {
+        (
+            (board.white.clone(), board.black.clone()),
+            board.current_player,
+            board.draw_state.no_progress_half_moves,
+            board.castling,
+            board.en_passant,
+            board.lifted_piece.clone(),
+            board.promotion,
+            board.victory_state,
+        )
+    }
+
+    #[quickcheck]
+    fn fen_roundtrip(board: RandomBoard) -> bool {
+        let fen = write_fen(&board.0);
+        let parsed = parse_fen(&fen).unwrap();
+        written_state(&parsed) == written_state(&board.0) && write_fen(&parsed) == fen
+    }
+
+    #[test]
+    fn state_that_is_not_written() {
+        // The pawn moves into the union on d4 and picks up the white knight.
+        let mut board = parse_fen("4k3/8/8/8/3d4/4P3/8/4K3 w 0 ---- - - - - -").unwrap();
+        board.set_rules(RuleSet::rules_2017());
+        board
+            .execute(PacoAction::Lift(BoardPosition::new(4, 2)))
+            .unwrap();
+        board
+            .execute(PacoAction::Place(BoardPosition::new(3, 3)))
+            .unwrap();
+        assert_eq!(board.draw_state.turn_progress, Progress::Reversible);
+
+        let parsed = parse_fen(&write_fen(&board)).unwrap();
+        assert_eq!(write_fen(&parsed), write_fen(&board));
+        assert_eq!(parsed.rules(), RuleSet::default());
+        assert!(parsed.draw_state.history.is_empty());
+        assert_eq!(parsed.draw_state.turn_progress, Progress::None);
+    }
+}
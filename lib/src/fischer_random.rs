@@ -0,0 +1,229 @@
+//! Fischer random starting positions, also known as Chess960.
+//!
+//! The pieces on the back row are shuffled, with the bishops on squares of
+//! different colors and the king somewhere between the rooks. Black mirrors
+//! the setup of white. The positions are numbered from 0 to 959 in the usual
+//! way, so the classic setup has the index 518.
+//!
+//! Castling still moves the king to the c or g file and the rook next to it,
+//! see `Castling::target` for how that is done when the king barely moves.
+
+use crate::{BoardPosition, Castling, DenseBoard, PacoError, PieceType, StartFiles};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// The number of different starting positions.
+pub const POSITION_COUNT: u16 = 960;
+
+/// The index of the classic starting position.
+pub const CLASSIC_INDEX: u16 = 518;
+
+/// Chooses a starting position, either directly or from a random seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FischerRandom {
+    Index(u16),
+    Seed(u64),
+}
+
+impl FischerRandom {
+    /// The index of the chosen starting position.
+    pub fn index(self) -> u16 {
+        match self {
+            FischerRandom::Index(index) => index,
+            FischerRandom::Seed(seed) => index_from_seed(seed),
+        }
+    }
+}
+
+/// Picks a starting position at random. The same seed always gives the same
+/// position.
+pub fn index_from_seed(seed: u64) -> u16 {
+    StdRng::seed_from_u64(seed).gen_range(0, POSITION_COUNT)
+}
+
+/// The pieces on the back row, from the a file to the h file.
+pub fn back_row(index: u16) -> Result<[PieceType; 8], PacoError> {
+    use PieceType::*;
+    if index >= POSITION_COUNT {
+        return Err(PacoError::NoSuchStartingPosition(index));
+    }
+
+    let mut row: [Option<PieceType>; 8] = [None; 8];
+    let mut n = index as usize;
+
+    // The bishops go on a light and a dark square.
+    row[2 * (n % 4) + 1] = Some(Bishop);
+    n /= 4;
+    row[2 * (n % 4)] = Some(Bishop);
+    n /= 4;
+
+    // The queen and the knights take some of the remaining squares.
+    let mut place_on_free_square = |piece: PieceType, nth: usize| {
+        let file = (0..8).filter(|&x| row[x].is_none()).nth(nth).unwrap();
+        row[file] = Some(piece);
+    };
+    place_on_free_square(Queen, n % 6);
+    n /= 6;
+    let knights = [
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (0, 4),
+        (1, 2),
+        (1, 3),
+        (1, 4),
+        (2, 3),
+        (2, 4),
+        (3, 4),
+    ];
+    let (first, second) = knights[n];
+    // Placing the first knight removes a free square before the second one.
+    place_on_free_square(Knight, first);
+    place_on_free_square(Knight, second - 1);
+
+    // Rook, king and rook fill the last three squares in this order.
+    for &piece in &[Rook, King, Rook] {
+        place_on_free_square(piece, 0);
+    }
+
+    let mut result = [Pawn; 8];
+    for (target, piece) in result.iter_mut().zip(row.iter()) {
+        *target = piece.unwrap();
+    }
+    Ok(result)
+}
+
+/// Creates the board for the starting position with the given index.
+pub fn start_position(index: u16) -> Result<DenseBoard, PacoError> {
+    let row = back_row(index)?;
+    let mut board = DenseBoard::empty();
+    for (x, &piece) in row.iter().enumerate() {
+        let x = x as u8;
+        board.white[BoardPosition::new(x, 0).0 as usize] = Some(piece);
+        board.white[BoardPosition::new(x, 1).0 as usize] = Some(PieceType::Pawn);
+        board.black[BoardPosition::new(x, 6).0 as usize] = Some(PieceType::Pawn);
+        board.black[BoardPosition::new(x, 7).0 as usize] = Some(piece);
+    }
+
+    let file_of = |piece: PieceType| row.iter().position(|&p| p == piece).unwrap() as u8;
+    board.castling = Castling::with_files(StartFiles {
+        king: file_of(PieceType::King),
+        queen_rook: file_of(PieceType::Rook),
+        king_rook: row.iter().rposition(|&p| p == PieceType::Rook).unwrap() as u8,
+    });
+    Ok(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::{parse_fen, write_fen};
+    use crate::notation::{parse_turn, write_turn};
+    use crate::{PacoAction, PacoBoard};
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn letters(index: u16) -> String {
+        back_row(index)
+            .unwrap()
+            .iter()
+            .map(|piece| piece.letter())
+            .collect()
+    }
+
+    #[test]
+    fn known_positions() {
+        assert_eq!(letters(0), "BBQNNRKR");
+        assert_eq!(letters(CLASSIC_INDEX), "RNBQKBNR");
+        assert_eq!(letters(959), "RKRNNQBB");
+        assert_eq!(start_position(CLASSIC_INDEX).unwrap(), DenseBoard::new());
+        assert_eq!(
+            back_row(POSITION_COUNT),
+            Err(PacoError::NoSuchStartingPosition(POSITION_COUNT))
+        );
+    }
+
+    #[test]
+    fn all_positions_are_valid_and_different() {
+        let mut seen = HashSet::new();
+        for index in 0..POSITION_COUNT {
+            let row = letters(index);
+            assert!(seen.insert(row.clone()), "{} appears twice", row);
+
+            let bishops: Vec<usize> = row.match_indices('B').map(|(i, _)| i).collect();
+            assert_eq!(bishops.len(), 2);
+            assert_ne!(bishops[0] % 2, bishops[1] % 2);
+            let king = row.find('K').unwrap();
+            assert!(row.find('R').unwrap() < king && king < row.rfind('R').unwrap());
+
+            // The fen keeps the castling files.
+            let board = start_position(index).unwrap();
+            assert_eq!(parse_fen(&write_fen(&board)).unwrap(), board);
+        }
+    }
+
+    #[test]
+    fn seeded() {
+        assert_eq!(index_from_seed(42), index_from_seed(42));
+        assert!(index_from_seed(42) < POSITION_COUNT);
+        assert_eq!(FischerRandom::Index(7).index(), 7);
+        assert_eq!(
+            FischerRandom::Seed(42).index(),
+            FischerRandom::Seed(42).index()
+        );
+    }
+
+    /// The king on b1 castles queen side by being placed on the rook, as c1
+    /// would be a normal move of the king.
+    #[test]
+    fn castling_with_king_next_to_destination() {
+        let board = parse_fen("4k3/8/8/8/8/8/8/RK5R w 0 ah-- - - - - -").unwrap();
+        let mut lifted = board.clone();
+        lifted.execute(PacoAction::Lift(pos("b1"))).unwrap();
+        let actions = lifted.actions().unwrap();
+        assert!(actions.contains(&PacoAction::Place(pos("a1"))));
+        assert!(actions.contains(&PacoAction::Place(pos("c1"))));
+        assert!(actions.contains(&PacoAction::Place(pos("g1"))));
+
+        let mut queen_side = lifted.clone();
+        queen_side.execute(PacoAction::Place(pos("a1"))).unwrap();
+        assert_eq!(
+            write_fen(&queen_side),
+            "4k3/8/8/8/8/8/8/2KR3R b 0 ---- - - - - - 1"
+        );
+
+        let mut king_move = lifted.clone();
+        king_move.execute(PacoAction::Place(pos("c1"))).unwrap();
+        assert_eq!(
+            write_fen(&king_move),
+            "4k3/8/8/8/8/8/8/R1K4R b 0 ---- - - - - - 1"
+        );
+
+        let mut king_side = lifted;
+        king_side.execute(PacoAction::Place(pos("g1"))).unwrap();
+        assert_eq!(
+            write_fen(&king_side),
+            "4k3/8/8/8/8/8/8/R4RK1 b 0 ---- - - - - - 1"
+        );
+
+        let queen_side = [PacoAction::Lift(pos("b1")), PacoAction::Place(pos("a1"))];
+        assert_eq!(write_turn(&board, &queen_side).unwrap(), "O-O-O");
+        assert_eq!(parse_turn(&board, "O-O-O").unwrap(), queen_side);
+    }
+
+    /// The king on g1 stays where it is and only the rook moves.
+    #[test]
+    fn castling_without_moving_the_king() {
+        let mut board = parse_fen("4k3/8/8/8/8/8/8/R5KR w 0 ah-- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("g1"))).unwrap();
+        board.execute(PacoAction::Place(pos("h1"))).unwrap();
+        assert_eq!(
+            write_fen(&board),
+            "4k3/8/8/8/8/8/8/R4RK1 b 0 ---- - - - - - 1"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! The actions of a game together with a snapshot of the board at the start of
+//! every turn. Seeking to a turn is a clone, seeking to an action only replays
+//! the actions of a single turn.
+//!
+//! A turn starts whenever the controlling player changes. This is where the
+//! replay page steps to, where rollback returns to and where games are
+//! usually branched.
+
+use crate::{DenseBoard, PacoAction, PacoBoard, PacoError};
+
+#[derive(Clone, Debug)]
+pub struct GameHistory {
+    actions: Vec<PacoAction>,
+    /// The index of the first action of each turn. The first turn starts at 0.
+    turn_starts: Vec<usize>,
+    /// The board at the start of each turn.
+    snapshots: Vec<DenseBoard>,
+    /// The board after all actions.
+    board: DenseBoard,
+}
+
+impl GameHistory {
+    /// An empty history for a game that starts with the given board.
+    pub fn new(start: DenseBoard) -> Self {
+        GameHistory {
+            actions: vec![],
+            turn_starts: vec![0],
+            snapshots: vec![start.clone()],
+            board: start,
+        }
+    }
+
+    /// Executes all actions, each one has to be legal.
+    pub fn from_actions(start: DenseBoard, actions: &[PacoAction]) -> Result<Self, PacoError> {
+        let mut history = Self::new(start);
+        for &action in actions {
+            history.push(action)?;
+        }
+        Ok(history)
+    }
+
+    /// Like `from_actions`, for actions that were already validated.
+    pub fn from_trusted_actions(
+        start: DenseBoard,
+        actions: &[PacoAction],
+    ) -> Result<Self, PacoError> {
+        let mut history = Self::new(start);
+        for &action in actions {
+            history.push_trusted(action)?;
+        }
+        Ok(history)
+    }
+
+    /// Validates and executes an action.
+    pub fn push(&mut self, action: PacoAction) -> Result<(), PacoError> {
+        if !self.board.is_legal(action)? {
+            return Err(PacoError::ActionNotLegal);
+        }
+        self.push_trusted(action)
+    }
+
+    /// Executes an action without checking if it is legal.
+    pub fn push_trusted(&mut self, action: PacoAction) -> Result<(), PacoError> {
+        let controlling_player = self.board.controlling_player();
+        self.board.execute_trusted(action)?;
+        self.actions.push(action);
+        if self.board.controlling_player() != controlling_player {
+            self.turn_starts.push(self.actions.len());
+            self.snapshots.push(self.board.clone());
+        }
+        Ok(())
+    }
+
+    /// The board before the first action.
+    pub fn start(&self) -> &DenseBoard {
+        &self.snapshots[0]
+    }
+
+    /// The board after all actions.
+    pub fn board(&self) -> &DenseBoard {
+        &self.board
+    }
+
+    pub fn actions(&self) -> &[PacoAction] {
+        &self.actions
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// The action indices where a turn starts. The first entry is always 0.
+    /// When the last action ended a turn, the last entry is the length of the
+    /// history and belongs to a turn without actions so far.
+    pub fn turn_boundaries(&self) -> &[usize] {
+        &self.turn_starts
+    }
+
+    /// The number of turns, including the turn that is in progress.
+    pub fn turn_count(&self) -> usize {
+        self.turn_starts.len()
+    }
+
+    /// The turn that contains the action with the given index.
+    pub fn turn_of(&self, action_index: usize) -> usize {
+        // The first turn always starts at 0, so the partition point is never 0.
+        self.turn_starts
+            .partition_point(|&start| start <= action_index)
+            - 1
+    }
+
+    /// The board after the first `action_index` actions.
+    pub fn board_at(&self, action_index: usize) -> Option<DenseBoard> {
+        if action_index > self.actions.len() {
+            return None;
+        }
+        let turn = self.turn_of(action_index);
+        let mut board = self.snapshots[turn].clone();
+        for &action in &self.actions[self.turn_starts[turn]..action_index] {
+            board
+                .execute_trusted(action)
+                .expect("The actions were executed on the same board before.");
+        }
+        Some(board)
+    }
+
+    /// The board at the start of the given turn.
+    pub fn board_at_turn(&self, turn: usize) -> Option<DenseBoard> {
+        self.snapshots.get(turn).cloned()
+    }
+
+    /// The number of actions to keep when the turn in progress is rolled back.
+    /// Once the game is over, nothing can be rolled back.
+    pub fn last_checkpoint(&self) -> usize {
+        if self.board.victory_state().is_over() {
+            self.actions.len()
+        } else {
+            self.turn_starts[self.turn_starts.len() - 1]
+        }
+    }
+
+    /// Takes back the actions of the turn in progress.
+    pub fn rollback(&mut self) {
+        self.truncate(self.last_checkpoint());
+    }
+
+    /// Keeps the first `len` actions. Does nothing if the history is shorter.
+    pub fn truncate(&mut self, len: usize) {
+        if let Some(board) = self.board_at(len) {
+            let turns = self.turn_of(len) + 1;
+            self.actions.truncate(len);
+            self.turn_starts.truncate(turns);
+            self.snapshots.truncate(turns);
+            self.board = board;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::identical;
+    use crate::{BoardPosition, PlayerColor};
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use std::convert::TryFrom;
+
+    fn lift(identifier: &str) -> PacoAction {
+        PacoAction::Lift(BoardPosition::try_from(identifier).unwrap())
+    }
+
+    fn place(identifier: &str) -> PacoAction {
+        PacoAction::Place(BoardPosition::try_from(identifier).unwrap())
+    }
+
+    /// Two full turns and the start of a third one.
+    fn opening() -> GameHistory {
+        let actions = [lift("e2"), place("e4"), lift("d7"), place("d5"), lift("e4")];
+        GameHistory::from_actions(DenseBoard::new(), &actions).unwrap()
+    }
+
+    #[test]
+    fn turn_boundaries() {
+        let history = opening();
+        assert_eq!(history.turn_boundaries(), &[0, 2, 4]);
+        assert_eq!(history.turn_count(), 3);
+        assert_eq!(history.turn_of(0), 0);
+        assert_eq!(history.turn_of(1), 0);
+        assert_eq!(history.turn_of(2), 1);
+        assert_eq!(history.turn_of(5), 2);
+        assert_eq!(
+            history.board_at_turn(1).unwrap().controlling_player(),
+            PlayerColor::Black
+        );
+        assert_eq!(history.board_at_turn(3), None);
+    }
+
+    #[test]
+    fn illegal_actions_are_rejected() {
+        let mut history = opening();
+        assert_eq!(history.push(place("h5")), Err(PacoError::ActionNotLegal));
+        assert_eq!(history.len(), 5);
+        assert_eq!(
+            GameHistory::from_actions(DenseBoard::new(), &[lift("e7")]).err(),
+            Some(PacoError::ActionNotLegal)
+        );
+    }
+
+    #[test]
+    fn rollback_and_truncate() {
+        let mut history = opening();
+        assert_eq!(history.last_checkpoint(), 4);
+        history.rollback();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.turn_boundaries(), &[0, 2, 4]);
+        assert_eq!(history.board(), &history.board_at_turn(2).unwrap());
+
+        history.truncate(3);
+        assert_eq!(history.turn_boundaries(), &[0, 2]);
+        assert_eq!(history.board(), &history.board_at(3).unwrap());
+        history.push(place("d6")).unwrap();
+        assert_eq!(history.turn_boundaries(), &[0, 2, 4]);
+
+        history.truncate(10);
+        assert_eq!(history.len(), 4);
+        history.truncate(0);
+        assert!(history.is_empty());
+        assert_eq!(history.board(), &DenseBoard::new());
+    }
+
+    /// Seeking gives the same board as replaying from the start, also for
+    /// finished games and turns with chains and promotions.
+    #[quickcheck]
+    fn seek_matches_replay(seed: u64) -> bool {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut board = DenseBoard::new();
+        let mut boards = vec![board.clone()];
+        let mut actions = vec![];
+        for _ in 0..rng.gen_range(0, 300) {
+            let legal = board.actions().unwrap();
+            match legal.choose(&mut rng) {
+                Some(&action) => {
+                    board.execute_trusted(action).unwrap();
+                    boards.push(board.clone());
+                    actions.push(action);
+                }
+                None => break,
+            }
+        }
+
+        let history = GameHistory::from_trusted_actions(DenseBoard::new(), &actions).unwrap();
+        let turns_match = history
+            .turn_boundaries()
+            .iter()
+            .enumerate()
+            .all(|(turn, &start)| identical(&history.board_at_turn(turn).unwrap(), &boards[start]));
+        let actions_match =
+            (0..boards.len()).all(|i| identical(&history.board_at(i).unwrap(), &boards[i]));
+        identical(history.board(), &board) && turns_match && actions_match
+    }
+}
@@ -1,11 +1,36 @@
+pub mod ai;
+pub mod chain_graph;
+pub mod codec;
+pub mod evaluation;
+pub mod events;
 pub mod export;
+pub mod fen;
+pub mod fischer_random;
+pub mod game_history;
+pub mod mcts;
+pub mod notation;
 pub mod parser;
+pub mod perft;
+pub mod puzzles;
+pub mod record;
+pub mod rules;
+pub mod sako;
+pub mod snapshot;
+pub mod solver;
+#[cfg(test)]
+mod testing;
+pub mod threats;
 pub mod types;
+pub mod undo;
+pub mod validation;
 
 use colored::*;
+use events::emit;
+pub use events::PacoEvent;
 use rand::distributions::{Distribution, Standard};
 use rand::seq::SliceRandom;
 use rand::Rng;
+pub use rules::RuleSet;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
@@ -14,7 +39,9 @@ use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 pub use types::{BoardPosition, PieceType, PlayerColor};
+pub use validation::PositionProblem;
 use wasm_bindgen::prelude::*;
 #[cfg(test)]
 extern crate quickcheck;
@@ -22,7 +49,7 @@ extern crate quickcheck;
 #[macro_use(quickcheck)]
 extern crate quickcheck_macros;
 
-#[derive(thiserror::Error, Clone, Debug, Serialize)]
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq, Serialize)]
 pub enum PacoError {
     #[error("You can not 'Lift' when the hand is full.")]
     LiftFullHand,
@@ -48,6 +75,14 @@ pub enum PacoError {
     MissingInput,
     #[error("You are trying to execute an action when it is not your turn.")]
     NotYourTurn,
+    #[error("This needs a position where the current turn is finished.")]
+    UnfinishedTurn,
+    #[error("The rules don't allow promoting to a {0:?}.")]
+    PromoteNotAllowed(PieceType),
+    #[error("There is no Fischer random starting position with index {0}.")]
+    NoSuchStartingPosition(u16),
+    #[error("The position can not occur in a game: {0:?}")]
+    InvalidPosition(Vec<PositionProblem>),
 }
 
 impl PlayerColor {
@@ -82,18 +117,16 @@ impl PieceType {
     }
 }
 
-/// Possible states a board of Paco Ŝako can be in. The pacosako library only
+/// Possible states a board of Paco Ŝako can be in. The pacosako library
 /// implements automatic transition to PacoVictory in case of a Paco Ŝako for
-/// either player.
-///
-/// Note that Drawing a game is not implemented yet. Possible draw reasons may
-/// be: Repeated position x3, No progress made for 50 moves (100 half-moves) or
-/// all pieces paired up. Maybe others?
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+/// either player and to Draw when one of the draw conditions in `DrawReason`
+/// is met at the end of a turn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum VictoryState {
     Running,
     PacoVictory(PlayerColor),
     TimeoutVictory(PlayerColor),
+    Draw(DrawReason),
 }
 
 impl VictoryState {
@@ -102,12 +135,73 @@ impl VictoryState {
             VictoryState::Running => false,
             VictoryState::PacoVictory(_) => true,
             VictoryState::TimeoutVictory(_) => true,
+            VictoryState::Draw(_) => true,
         }
     }
 }
 
-/// In a DenseBoard we reserve memory for all positions.
+/// The reason why a game of Paco Ŝako ended in a draw.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DrawReason {
+    /// The same position (including the player to move, castling and en passant
+    /// information) occurred for the third time.
+    Repetition,
+    /// No pawn was moved, no union was formed and no pawn promoted for the
+    /// configured number of half-moves.
+    NoProgress,
+    /// All pieces except for the kings are in a union. As a king can only be
+    /// united by a single piece, nobody can win anymore.
+    AllPaired,
+}
+
+/// The default amount of half-moves without progress after which the game is
+/// drawn. This is 50 moves for each player.
+pub const DEFAULT_NO_PROGRESS_LIMIT: u16 = 100;
+
+/// Records how much progress was made in the turn that is currently running.
+/// Progress is irreversible when the position before the turn can never occur
+/// again: Unions can not be dissolved and promoted pawns stay promoted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+enum Progress {
+    None,
+    Reversible,
+    Irreversible,
+}
+
+/// Keeps track of all the information required to detect draws.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct DrawState {
+    /// Hashes of all settled positions since the last irreversible progress.
+    /// The starting position is only added when the first piece is lifted.
+    history: Vec<u64>,
+    /// Amount of half-moves that were played since the last progress.
+    no_progress_half_moves: u16,
+    turn_progress: Progress,
+}
+
+impl DrawState {
+    fn new() -> Self {
+        DrawState {
+            history: Vec::new(),
+            no_progress_half_moves: 0,
+            turn_progress: Progress::None,
+        }
+    }
+
+    /// Remembers the highest kind of progress made in the current turn.
+    fn note_progress(&mut self, progress: Progress) {
+        self.turn_progress = self.turn_progress.max(progress);
+    }
+}
+
+/// In a DenseBoard we reserve memory for all positions.
+///
+/// The serde representation is versioned, see the `snapshot` module.
+///
+/// Two boards are equal when they describe the same position, the draw state
+/// is not compared. This way transpositions share table entries in searches.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "snapshot::Snapshot", try_from = "snapshot::Snapshot")]
 pub struct DenseBoard {
     white: Vec<Option<PieceType>>,
     black: Vec<Option<PieceType>>,
@@ -122,6 +216,41 @@ pub struct DenseBoard {
     /// Stores castling information
     castling: Castling,
     victory_state: VictoryState,
+    /// Position history and progress counter to detect draws.
+    draw_state: DrawState,
+    /// The rules this game is played with.
+    rules: RuleSet,
+}
+
+impl PartialEq for DenseBoard {
+    fn eq(&self, other: &Self) -> bool {
+        self.white == other.white
+            && self.black == other.black
+            && self.current_player == other.current_player
+            && self.lifted_piece == other.lifted_piece
+            && self.en_passant == other.en_passant
+            && self.promotion == other.promotion
+            && self.castling == other.castling
+            && self.victory_state == other.victory_state
+            && self.rules == other.rules
+    }
+}
+
+impl Eq for DenseBoard {}
+
+/// Must agree with `PartialEq`, so the draw state is left out here as well.
+impl Hash for DenseBoard {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.white.hash(state);
+        self.black.hash(state);
+        self.current_player.hash(state);
+        self.lifted_piece.hash(state);
+        self.en_passant.hash(state);
+        self.promotion.hash(state);
+        self.castling.hash(state);
+        self.victory_state.hash(state);
+        self.rules.hash(state);
+    }
 }
 
 /// Defines a random generator for Paco Ŝako games that are not over yet.
@@ -198,6 +327,8 @@ impl Distribution<DenseBoard> for Standard {
             }
         }
 
+        board.forfeit_impossible_castling();
+
         // Randomize current player
         board.current_player = if rng.gen() {
             PlayerColor::White
@@ -212,6 +343,27 @@ impl Distribution<DenseBoard> for Standard {
 #[derive(Serialize, Deserialize)]
 pub struct EditorBoard {
     pieces: Vec<RestingPiece>,
+    #[serde(flatten)]
+    state: EditorState,
+}
+
+/// The game state of an `EditorBoard` apart from the pieces. All fields are
+/// optional, so boards saved before they existed can still be read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorState {
+    /// The player to move. When missing, the analysis looks at both players.
+    pub current_player: Option<PlayerColor>,
+    /// Castling rights as in the fen, e.g. `ahah`. When missing, all rights
+    /// that fit the pieces on the board.
+    pub castling: Option<String>,
+    /// The square a pawn skipped when it moved two steps.
+    pub en_passant: Option<BoardPosition>,
+    /// The pieces `current_player` holds in their hand. A hand without a
+    /// `current_player` is an error.
+    pub hand: Option<Hand>,
+    /// A pawn that still needs to be promoted.
+    pub promotion: Option<BoardPosition>,
 }
 
 impl From<&DenseBoard> for EditorBoard {
@@ -237,25 +389,64 @@ impl From<&DenseBoard> for EditorBoard {
             })
         }));
 
-        EditorBoard { pieces }
+        let state = EditorState {
+            current_player: Some(dense.current_player),
+            castling: Some(fen::write_castling(&dense.castling)),
+            en_passant: dense.en_passant.map(|(position, _)| position),
+            hand: Some(dense.lifted_piece.clone()),
+            promotion: dense.promotion,
+        };
+
+        EditorBoard { pieces, state }
     }
 }
 
 impl EditorBoard {
     pub fn new(pieces: Vec<RestingPiece>) -> Self {
-        EditorBoard { pieces }
+        EditorBoard {
+            pieces,
+            state: EditorState::default(),
+        }
+    }
+
+    /// Replaces the game state and keeps the pieces.
+    pub fn with_state(self, state: EditorState) -> Self {
+        EditorBoard {
+            pieces: self.pieces,
+            state,
+        }
     }
 
-    pub fn with_active_player(&self, current_player: PlayerColor) -> DenseBoard {
+    pub fn state(&self) -> &EditorState {
+        &self.state
+    }
+
+    /// Builds the board with the given player to move. All other game state
+    /// is taken from the `EditorState`. The hand belongs to the player to move
+    /// of the editor, so it is only used when that is the given player.
+    pub fn with_active_player(&self, current_player: PlayerColor) -> Result<DenseBoard, PacoError> {
+        let hand = self.state.hand.clone().unwrap_or(Hand::Empty);
+        if hand != Hand::Empty && self.state.current_player.is_none() {
+            return Err(PacoError::InvalidPosition(vec![
+                PositionProblem::HandWithoutPlayer,
+            ]));
+        }
+
         let mut result: DenseBoard = DenseBoard {
             white: vec![None; 64],
             black: vec![None; 64],
             current_player,
-            lifted_piece: Hand::Empty,
+            lifted_piece: if self.state.current_player == Some(current_player) {
+                hand
+            } else {
+                Hand::Empty
+            },
             en_passant: None,
-            promotion: None,
+            promotion: self.state.promotion,
             castling: Castling::new(),
             victory_state: VictoryState::Running,
+            draw_state: DrawState::new(),
+            rules: RuleSet::default(),
         };
 
         // Copy piece from the `pieces` list into the dense arrays.
@@ -270,7 +461,35 @@ impl EditorBoard {
             }
         }
 
-        result
+        // The en passant square is behind the pawn, so the row tells us who moved it.
+        result.en_passant = self.state.en_passant.map(|position| {
+            let player = if position.y() < 4 {
+                PlayerColor::White
+            } else {
+                PlayerColor::Black
+            };
+            (position, player)
+        });
+
+        match &self.state.castling {
+            Some(castling) => {
+                result.castling =
+                    fen::parse_castling(castling).map_err(|_| PacoError::InputJsonMalformed)?;
+                fen::find_king_file(&mut result);
+            }
+            None => result.forfeit_impossible_castling(),
+        }
+
+        Ok(result)
+    }
+
+    /// The players that the analysis looks at: The player to move, or both
+    /// when the editor does not say who is to move.
+    fn players_to_analyse(&self) -> Vec<PlayerColor> {
+        match self.state.current_player {
+            Some(player) => vec![player],
+            None => vec![PlayerColor::White, PlayerColor::Black],
+        }
     }
 }
 
@@ -288,21 +507,153 @@ pub struct SakoSearchResult {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum CastlingSide {
+    Queen,
+    King,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct Castling {
     white_queen_side: bool,
     white_king_side: bool,
     black_queen_side: bool,
     black_king_side: bool,
+    /// Files of the king and the rooks at the start of the game. These only
+    /// differ from e, a and h in Fischer random games and in positions from a
+    /// fen, where the players may even start on different files.
+    white_files: StartFiles,
+    black_files: StartFiles,
+}
+
+/// Files of the king and the rooks of one player at the start of the game.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct StartFiles {
+    king: u8,
+    queen_rook: u8,
+    king_rook: u8,
+}
+
+impl StartFiles {
+    fn classic() -> Self {
+        StartFiles {
+            king: 4,
+            queen_rook: 0,
+            king_rook: 7,
+        }
+    }
 }
 
 impl Castling {
     /// Returns an initial Castling structure where all castling is possible
     fn new() -> Self {
+        Castling::with_files(StartFiles::classic())
+    }
+
+    /// All castling is possible, with the king and rooks of both players
+    /// starting on the given files.
+    fn with_files(files: StartFiles) -> Self {
         Castling {
             white_queen_side: true,
             white_king_side: true,
             black_queen_side: true,
             black_king_side: true,
+            white_files: files,
+            black_files: files,
+        }
+    }
+
+    fn files(&self, player: PlayerColor) -> StartFiles {
+        match player {
+            PlayerColor::White => self.white_files,
+            PlayerColor::Black => self.black_files,
+        }
+    }
+
+    fn files_mut(&mut self, player: PlayerColor) -> &mut StartFiles {
+        match player {
+            PlayerColor::White => &mut self.white_files,
+            PlayerColor::Black => &mut self.black_files,
+        }
+    }
+
+    fn has_right(&self, player: PlayerColor, side: CastlingSide) -> bool {
+        match (player, side) {
+            (PlayerColor::White, CastlingSide::Queen) => self.white_queen_side,
+            (PlayerColor::White, CastlingSide::King) => self.white_king_side,
+            (PlayerColor::Black, CastlingSide::Queen) => self.black_queen_side,
+            (PlayerColor::Black, CastlingSide::King) => self.black_king_side,
+        }
+    }
+
+    /// Removes a castling right. Files that no right refers to anymore are set
+    /// back to the classic ones, so boards that only differ in them are equal.
+    fn forfeit(&mut self, player: PlayerColor, side: CastlingSide) {
+        match (player, side) {
+            (PlayerColor::White, CastlingSide::Queen) => self.white_queen_side = false,
+            (PlayerColor::White, CastlingSide::King) => self.white_king_side = false,
+            (PlayerColor::Black, CastlingSide::Queen) => self.black_queen_side = false,
+            (PlayerColor::Black, CastlingSide::King) => self.black_king_side = false,
+        }
+        let classic = StartFiles::classic();
+        let queen_side = self.has_right(player, CastlingSide::Queen);
+        let king_side = self.has_right(player, CastlingSide::King);
+        let files = self.files_mut(player);
+        if !queen_side {
+            files.queen_rook = classic.queen_rook;
+        }
+        if !king_side {
+            files.king_rook = classic.king_rook;
+        }
+        if !queen_side && !king_side {
+            files.king = classic.king;
+        }
+    }
+
+    fn home_square(player: PlayerColor, file: u8) -> BoardPosition {
+        match player {
+            PlayerColor::White => BoardPosition::new(file, 0),
+            PlayerColor::Black => BoardPosition::new(file, 7),
+        }
+    }
+
+    fn king_square(&self, player: PlayerColor) -> BoardPosition {
+        Castling::home_square(player, self.files(player).king)
+    }
+
+    fn rook_square(&self, player: PlayerColor, side: CastlingSide) -> BoardPosition {
+        let files = self.files(player);
+        match side {
+            CastlingSide::Queen => Castling::home_square(player, files.queen_rook),
+            CastlingSide::King => Castling::home_square(player, files.king_rook),
+        }
+    }
+
+    /// Where the king ends up after castling, this is the c or g file.
+    fn king_destination(player: PlayerColor, side: CastlingSide) -> BoardPosition {
+        match side {
+            CastlingSide::Queen => Castling::home_square(player, 2),
+            CastlingSide::King => Castling::home_square(player, 6),
+        }
+    }
+
+    /// Where the rook ends up after castling, this is the d or f file.
+    fn rook_destination(player: PlayerColor, side: CastlingSide) -> BoardPosition {
+        match side {
+            CastlingSide::Queen => Castling::home_square(player, 3),
+            CastlingSide::King => Castling::home_square(player, 5),
+        }
+    }
+
+    /// The square where the king is placed to castle. This is the destination
+    /// of the king, unless the king moves less than two files. Then the king is
+    /// placed on the rook instead, so castling can't be confused with a normal
+    /// move of the king.
+    fn target(&self, player: PlayerColor, side: CastlingSide) -> BoardPosition {
+        let destination = Castling::king_destination(player, side);
+        if (destination.x() as i8 - self.files(player).king as i8).abs() >= 2 {
+            destination
+        } else {
+            self.rook_square(player, side)
         }
     }
 }
@@ -332,6 +683,15 @@ impl Hand {
             Pair { position, .. } => Some(*position),
         }
     }
+    /// The piece of the current player that is held in hand.
+    fn piece(&self) -> Option<PieceType> {
+        use Hand::*;
+        match self {
+            Empty => None,
+            Single { piece, .. } => Some(*piece),
+            Pair { piece, .. } => Some(*piece),
+        }
+    }
 }
 
 /// A PacoAction is an action that can be applied to a PacoBoard to modify it.
@@ -364,15 +724,30 @@ impl PacoAction {
 
 /// The PacoBoard trait encapsulates arbitrary Board implementations.
 pub trait PacoBoard: Clone + Eq + std::hash::Hash + Display {
+    /// Everything needed to take back a single action.
+    type Undo;
     /// Check if a PacoAction is legal and execute it. Otherwise return an error.
     fn execute(&mut self, action: PacoAction) -> Result<&mut Self, PacoError>;
     /// Executes a PacoAction. This call may assume that the action is legal
     /// without checking it. Only call it when you generate the actions yourself.
-    fn execute_trusted(&mut self, action: PacoAction) -> Result<&mut Self, PacoError>;
+    /// Returns a record that `undo` uses to restore the state before the action.
+    fn execute_trusted(&mut self, action: PacoAction) -> Result<Self::Undo, PacoError>;
+    /// Restores the state before the action that returned the record. Actions
+    /// need to be undone in the reverse order of their execution.
+    fn undo(&mut self, record: Self::Undo);
+    /// Checks if the action is one of `actions()` without generating all of
+    /// them. Only castling needs to look at the threats of the opponent.
+    fn is_legal(&self, action: PacoAction) -> Result<bool, PacoError>;
     /// List all actions that can be executed in the current state. Note that actions which leave
     /// the board in a deadend state (like lifting up a pawn that is blocked) should be included
     /// in the list as well.
     fn actions(&self) -> Result<Vec<PacoAction>, PacoError>;
+    /// Like `actions`, but without actions that lead into a dead end. From each
+    /// returned action the player can still finish the turn. This is meant for
+    /// user interfaces, so players don't lift a piece that can't move.
+    fn productive_actions(&self) -> Result<Vec<PacoAction>, PacoError> {
+        productive_actions(self)
+    }
     /// List all actions, that threaten to capture a position. This means pairs
     /// are excluded and king movement is also excluded. Movement to an empty
     /// square which could capture is included. Actions which leave the
@@ -409,6 +784,8 @@ impl DenseBoard {
             promotion: None,
             castling: Castling::new(),
             victory_state: VictoryState::Running,
+            draw_state: DrawState::new(),
+            rules: RuleSet::default(),
         };
 
         // Board structure
@@ -456,6 +833,8 @@ impl DenseBoard {
             promotion: None,
             castling: Castling::new(),
             victory_state: VictoryState::Running,
+            draw_state: DrawState::new(),
+            rules: RuleSet::default(),
         }
     }
 
@@ -464,6 +843,33 @@ impl DenseBoard {
         self.lifted_piece = new_hand;
     }
 
+    /// Sets the amount of half-moves without progress after which the game is
+    /// drawn. Progress means moving a pawn, forming a new union or promoting.
+    pub fn set_no_progress_limit(&mut self, limit: u16) {
+        self.rules.no_progress_limit = limit;
+    }
+
+    /// The starting position for a game with the given rules.
+    pub fn with_rules(rules: RuleSet) -> Self {
+        let mut board = DenseBoard::new();
+        board.rules = rules;
+        board
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    /// Changes the rules, for example for a board read from a fen.
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    /// The amount of half-moves that were played since the last progress.
+    pub fn no_progress_half_moves(&self) -> u16 {
+        self.draw_state.no_progress_half_moves
+    }
+
     pub fn from_squares(squares: HashMap<BoardPosition, parser::Square>) -> Self {
         let mut result = Self::empty();
         for (position, square) in squares.iter() {
@@ -474,55 +880,157 @@ impl DenseBoard {
                 *result.black.get_mut(position.0 as usize).unwrap() = Some(piece_type);
             }
         }
+        result.forfeit_impossible_castling();
         result
     }
 
+    /// Castling needs the king and the rook on their starting squares. This
+    /// removes all other rights, for boards that are only given by their pieces.
+    fn forfeit_impossible_castling(&mut self) {
+        for &player in &[PlayerColor::White, PlayerColor::Black] {
+            for &side in &[CastlingSide::Queen, CastlingSide::King] {
+                if !self.castling_possible(player, side) {
+                    self.castling.forfeit(player, side);
+                }
+            }
+        }
+    }
+
+    /// Are the king and the rook of a castling right on their squares?
+    fn castling_possible(&self, player: PlayerColor, side: CastlingSide) -> bool {
+        let pieces = self.pieces_of_color(player);
+        let king = pieces[self.castling.king_square(player).0 as usize];
+        let rook = pieces[self.castling.rook_square(player, side).0 as usize];
+        king == Some(PieceType::King) && rook == Some(PieceType::Rook)
+    }
+
+    /// The side the current player castles to, when the king is lifted from
+    /// `from` and placed on `target`. This checks the castling rights and that
+    /// the rook is still there, but not if the way is free.
+    fn castling_side(&self, from: BoardPosition, target: BoardPosition) -> Option<CastlingSide> {
+        let player = self.current_player;
+        if from != self.castling.king_square(player) {
+            return None;
+        }
+        [CastlingSide::Queen, CastlingSide::King]
+            .iter()
+            .copied()
+            .find(|&side| {
+                let rook = self.castling.rook_square(player, side);
+                self.castling.has_right(player, side)
+                    && self.castling.target(player, side) == target
+                    && self.active_pieces()[rook.0 as usize] == Some(PieceType::Rook)
+            })
+    }
+
+    /// A piece in hand was set free by a chain, if the piece that took its
+    /// place is still on the square it came from.
+    fn hand_is_chained(&self) -> bool {
+        match self.lifted_piece.position() {
+            Some(position) => self.active_piece_present(position),
+            None => false,
+        }
+    }
+
+    /// En passant is always possible for a pawn lifted at the start of the
+    /// turn, the rules decide if a chain may continue with it.
+    fn en_passant_allowed(&self) -> bool {
+        self.rules.en_passant_in_chains || !self.hand_is_chained()
+    }
+
+    /// Moving a rook of `owner` away from its starting square forfeits castling
+    /// on that side.
+    fn forfeit_castling_with_rook(&mut self, owner: PlayerColor, position: BoardPosition) {
+        for &side in &[CastlingSide::Queen, CastlingSide::King] {
+            if self.castling.rook_square(owner, side) == position {
+                self.castling.forfeit(owner, side);
+            }
+        }
+    }
+
+    /// Like `execute`, but also reports what happened on the board.
+    pub fn execute_with_events(&mut self, action: PacoAction) -> Result<Vec<PacoEvent>, PacoError> {
+        if !self.is_legal(action)? {
+            return Err(PacoError::ActionNotLegal);
+        }
+        let mut events = vec![];
+        self.execute_reporting(action, Some(&mut events))?;
+        Ok(events)
+    }
+
+    /// Executes an action without checking it and records the events when a
+    /// list is given.
+    fn execute_reporting(
+        &mut self,
+        action: PacoAction,
+        mut events: Option<&mut Vec<PacoEvent>>,
+    ) -> Result<undo::UndoRecord, PacoError> {
+        use PacoAction::*;
+        let mut record = undo::UndoRecord::new(self, action);
+        let was_running = !self.victory_state.is_over();
+        match action {
+            Lift(position) => {
+                self.lift(position, events.as_deref_mut())?;
+            }
+            Place(position) => {
+                self.place(position, events.as_deref_mut())?;
+                self.remove_en_passant_info();
+                record.set_cleared_history(self.finish_turn());
+            }
+            Promote(new_type) => {
+                self.promote(new_type, events.as_deref_mut())?;
+                record.set_cleared_history(self.finish_turn());
+            }
+        }
+        if was_running && self.victory_state.is_over() {
+            emit(
+                &mut events,
+                PacoEvent::GameOver {
+                    victory_state: self.victory_state,
+                },
+            );
+        }
+        Ok(record)
+    }
+
     /// Lifts the piece of the current player in the given position of the board.
     /// Only one piece may be lifted at a time.
-    fn lift(&mut self, position: BoardPosition) -> Result<&mut Self, PacoError> {
+    fn lift(
+        &mut self,
+        position: BoardPosition,
+        mut events: Option<&mut Vec<PacoEvent>>,
+    ) -> Result<&mut Self, PacoError> {
         if self.lifted_piece != Hand::Empty {
             return Err(PacoError::LiftFullHand);
         }
+        // A fresh board does not know its starting position yet.
+        if self.draw_state.history.is_empty() {
+            let position_hash = self.position_hash();
+            self.draw_state.history.push(position_hash);
+        }
         // We unwrap the pieces once to remove the outer Some() from the .get_mut(..) call.
         // We still recieve an optional where None represents an empty square.
         let piece = *self.active_pieces().get(position.0 as usize).unwrap();
         let partner = *self.opponent_pieces().get(position.0 as usize).unwrap();
 
         if let Some(piece_type) = piece {
+            emit(
+                &mut events,
+                PacoEvent::Lifted {
+                    position,
+                    piece: piece_type,
+                    partner,
+                },
+            );
             // When lifting a rook, castling may be forfeit.
             if piece_type == PieceType::Rook {
-                if position == BoardPosition(0) && self.current_player == PlayerColor::White {
-                    self.castling.white_queen_side = false;
-                } else if position == BoardPosition(7) && self.current_player == PlayerColor::White
-                {
-                    self.castling.white_king_side = false;
-                } else if position == BoardPosition(56) && self.current_player == PlayerColor::Black
-                {
-                    self.castling.black_queen_side = false;
-                } else if position == BoardPosition(63) && self.current_player == PlayerColor::Black
-                {
-                    self.castling.black_king_side = false;
-                }
+                self.forfeit_castling_with_rook(self.current_player, position);
             }
 
             if let Some(partner_type) = partner {
                 // When lifting an enemy rook, castling may be denied from them.
                 if partner_type == PieceType::Rook {
-                    if position == BoardPosition(0) && self.current_player == PlayerColor::Black {
-                        self.castling.white_queen_side = false;
-                    } else if position == BoardPosition(7)
-                        && self.current_player == PlayerColor::Black
-                    {
-                        self.castling.white_king_side = false;
-                    } else if position == BoardPosition(56)
-                        && self.current_player == PlayerColor::White
-                    {
-                        self.castling.black_queen_side = false;
-                    } else if position == BoardPosition(63)
-                        && self.current_player == PlayerColor::White
-                    {
-                        self.castling.black_king_side = false;
-                    }
+                    self.forfeit_castling_with_rook(self.current_player.other(), position);
                 }
                 self.lifted_piece = Hand::Pair {
                     piece: piece_type,
@@ -551,7 +1059,11 @@ impl DenseBoard {
 
     /// Places the piece that is currently lifted back on the board.
     /// Returns an error if no piece is currently being lifted.
-    fn place(&mut self, target: BoardPosition) -> Result<&mut Self, PacoError> {
+    fn place(
+        &mut self,
+        target: BoardPosition,
+        mut events: Option<&mut Vec<PacoEvent>>,
+    ) -> Result<&mut Self, PacoError> {
         match self.lifted_piece {
             Hand::Empty => Err(PacoError::PlaceEmptyHand),
             Hand::Single { piece, position } => {
@@ -561,6 +1073,7 @@ impl DenseBoard {
                 if self.en_passant == Some((target, self.current_player.other()))
                     && piece == PieceType::Pawn
                     && position.advance_pawn(self.current_player) != Some(target)
+                    && self.en_passant_allowed()
                 {
                     let en_passant_source_square = target
                         .advance_pawn(self.current_player().other())
@@ -568,6 +1081,15 @@ impl DenseBoard {
                         .0 as usize;
                     self.white.swap(target.0 as usize, en_passant_source_square);
                     self.black.swap(target.0 as usize, en_passant_source_square);
+                    emit(
+                        &mut events,
+                        PacoEvent::EnPassant {
+                            position: target,
+                            pawn_from: BoardPosition(en_passant_source_square as u8),
+                        },
+                    );
+                    // Uniting en passant always forms a new union.
+                    self.draw_state.note_progress(Progress::Irreversible);
                     // Now we don't need the en_passant information anymore
                     // This prevents us from seeing it multiple times in a
                     // single chain.
@@ -575,20 +1097,54 @@ impl DenseBoard {
                 }
 
                 // If a pawn is moved onto the opponents home row, track promotion.
-                if piece == PieceType::Pawn
-                    && target.home_row() == Some(self.current_player.other())
-                {
+                let promotes = piece == PieceType::Pawn
+                    && target.home_row() == Some(self.current_player.other());
+                if promotes {
                     self.promotion = Some(target)
                 }
 
+                if piece == PieceType::Pawn {
+                    self.draw_state.note_progress(Progress::Reversible);
+                }
+
                 // Special case to handle castling
                 if piece == PieceType::King {
-                    return self.place_king(position, target);
+                    return self.place_king(position, target, events);
                 }
 
                 // Read piece currently on the board at the target position and place the
                 // held piece there.
                 let board_piece = *self.active_pieces().get(target.0 as usize).unwrap();
+                let board_partner = *self.opponent_pieces().get(target.0 as usize).unwrap();
+                let event = match (board_piece, board_partner) {
+                    (Some(lifted), _) => PacoEvent::ChainContinued {
+                        position: target,
+                        placed: piece,
+                        lifted,
+                    },
+                    (None, Some(partner)) => {
+                        // Placing a single piece on a single opponent piece forms a new union.
+                        self.draw_state.note_progress(Progress::Irreversible);
+                        PacoEvent::UnionFormed {
+                            position: target,
+                            piece,
+                            partner,
+                        }
+                    }
+                    (None, None) => PacoEvent::Moved {
+                        from: position,
+                        to: target,
+                        piece,
+                        partner: None,
+                    },
+                };
+                emit(&mut events, event);
+                if promotes {
+                    emit(
+                        &mut events,
+                        PacoEvent::PromotionScheduled { position: target },
+                    );
+                }
                 *self.active_pieces_mut().get_mut(target.0 as usize).unwrap() = Some(piece);
                 if let Some(new_hand_piece) = board_piece {
                     self.lifted_piece = Hand::Single {
@@ -621,6 +1177,13 @@ impl DenseBoard {
                     {
                         // We have united with the opponent king, the game is now won.
                         self.victory_state = VictoryState::PacoVictory(self.current_player);
+                        emit(
+                            &mut events,
+                            PacoEvent::KingUnited {
+                                position: target,
+                                winner: self.current_player,
+                            },
+                        );
                     }
 
                     // Placing without chaining means the current player switches.
@@ -664,6 +1227,25 @@ impl DenseBoard {
                     if promote_own_piece || promote_partner_piece {
                         self.promotion = Some(target)
                     }
+                    emit(
+                        &mut events,
+                        PacoEvent::Moved {
+                            from: position,
+                            to: target,
+                            piece,
+                            partner: Some(partner),
+                        },
+                    );
+                    if promote_own_piece || promote_partner_piece {
+                        emit(
+                            &mut events,
+                            PacoEvent::PromotionScheduled { position: target },
+                        );
+                    }
+
+                    if piece == PieceType::Pawn || partner == PieceType::Pawn {
+                        self.draw_state.note_progress(Progress::Reversible);
+                    }
 
                     *self.active_pieces_mut().get_mut(target.0 as usize).unwrap() = Some(piece);
                     *self
@@ -682,72 +1264,69 @@ impl DenseBoard {
         &mut self,
         position: BoardPosition,
         target: BoardPosition,
+        mut events: Option<&mut Vec<PacoEvent>>,
     ) -> Result<&mut Self, PacoError> {
-        if self.current_player == PlayerColor::White {
-            // White queen side castling
-            if position == BoardPosition(4) && target == BoardPosition(2) {
-                // Some extra safety checkes to make sure we don't overwrite pieces.
-                if !self.is_empty(BoardPosition(2)) || !self.is_empty(BoardPosition(3)) {
-                    return Err(PacoError::NoSpaceToMoveTheKing);
-                }
-                // Swap the rook (and possibly the partner) into place
-                self.white.swap(0, 3);
-                self.black.swap(0, 3);
+        let player = self.current_player;
+        let mut king_target = target;
+        let mut castled = None;
+        if let Some(side) = self.castling_side(position, target) {
+            let rook = self.castling.rook_square(player, side);
+            let rook_target = Castling::rook_destination(player, side);
+            king_target = Castling::king_destination(player, side);
+            // Some extra safety checkes to make sure we don't overwrite pieces.
+            let blocked = |p: BoardPosition| p != rook && !self.is_empty(p);
+            if blocked(king_target) || blocked(rook_target) {
+                return Err(PacoError::NoSpaceToMoveTheKing);
             }
-            // White king side castling
-            else if position == BoardPosition(4) && target == BoardPosition(6) {
-                // Some extra safety checkes to make sure we don't overwrite pieces.
-                if !self.is_empty(BoardPosition(5)) || !self.is_empty(BoardPosition(6)) {
-                    return Err(PacoError::NoSpaceToMoveTheKing);
-                }
-                // Swap the rook (and possibly the partner) into place
-                self.white.swap(5, 7);
-                self.black.swap(5, 7);
-            }
-            *self.white.get_mut(target.0 as usize).unwrap() = Some(PieceType::King);
-            self.lifted_piece = Hand::Empty;
-            // Any move of the king forfeits castling rights.
-            self.castling.white_king_side = false;
-            self.castling.white_queen_side = false;
-            self.current_player = self.current_player.other();
-            Ok(self)
-        } else {
-            // Black queen side castling
-            if position == BoardPosition(60) && target == BoardPosition(58) {
-                // Some extra safety checkes to make sure we don't overwrite pieces.
-                if !self.is_empty(BoardPosition(58)) || !self.is_empty(BoardPosition(59)) {
-                    return Err(PacoError::NoSpaceToMoveTheKing);
-                }
-                // Swap the rook (and possibly the partner) into place
-                self.white.swap(56, 59);
-                self.black.swap(56, 59);
-            }
-            // Black king side castling
-            else if position == BoardPosition(60) && target == BoardPosition(62) {
-                // Some extra safety checkes to make sure we don't overwrite pieces.
-                if !self.is_empty(BoardPosition(61)) || !self.is_empty(BoardPosition(62)) {
-                    return Err(PacoError::NoSpaceToMoveTheKing);
-                }
-                // Swap the rook (and possibly the partner) into place
-                self.white.swap(61, 63);
-                self.black.swap(61, 63);
-            }
-            *self.black.get_mut(target.0 as usize).unwrap() = Some(PieceType::King);
-            self.lifted_piece = Hand::Empty;
-            // Any move of the king forfeits castling rights.
-            self.castling.black_king_side = false;
-            self.castling.black_queen_side = false;
-            self.current_player = self.current_player.other();
-            Ok(self)
+            // Move the rook (and possibly the partner) into place
+            let rook_pieces = (self.white[rook.0 as usize], self.black[rook.0 as usize]);
+            self.white[rook.0 as usize] = None;
+            self.black[rook.0 as usize] = None;
+            self.white[rook_target.0 as usize] = rook_pieces.0;
+            self.black[rook_target.0 as usize] = rook_pieces.1;
+            castled = Some((rook, rook_target));
         }
+        emit(
+            &mut events,
+            match castled {
+                Some((rook_from, rook_to)) => PacoEvent::Castled {
+                    king_from: position,
+                    king_to: king_target,
+                    rook_from,
+                    rook_to,
+                },
+                None => PacoEvent::Moved {
+                    from: position,
+                    to: king_target,
+                    piece: PieceType::King,
+                    partner: None,
+                },
+            },
+        );
+        *self
+            .active_pieces_mut()
+            .get_mut(king_target.0 as usize)
+            .unwrap() = Some(PieceType::King);
+        self.lifted_piece = Hand::Empty;
+        // Any move of the king forfeits castling rights.
+        self.castling.forfeit(player, CastlingSide::Queen);
+        self.castling.forfeit(player, CastlingSide::King);
+        self.current_player = player.other();
+        Ok(self)
     }
 
     /// Promotes the current promotion target to the given type.
-    fn promote(&mut self, new_type: PieceType) -> Result<&mut Self, PacoError> {
+    fn promote(
+        &mut self,
+        new_type: PieceType,
+        mut events: Option<&mut Vec<PacoEvent>>,
+    ) -> Result<&mut Self, PacoError> {
         if new_type == PieceType::Pawn {
             Err(PacoError::PromoteToPawn)
         } else if new_type == PieceType::King {
             Err(PacoError::PromoteToKing)
+        } else if !self.rules.promotion_pieces.allows(new_type) {
+            Err(PacoError::PromoteNotAllowed(new_type))
         } else if let Some(target) = self.promotion {
             // Here we .unwrap() instead of returning an error, because a promotion target outside
             // the home row indicates an error as does a promotion target without a piece at that
@@ -764,6 +1343,14 @@ impl DenseBoard {
 
             *promoted_pawn = Some(new_type);
             self.promotion = None;
+            emit(
+                &mut events,
+                PacoEvent::Promoted {
+                    position: target,
+                    piece: new_type,
+                },
+            );
+            self.draw_state.note_progress(Progress::Irreversible);
 
             Ok(self)
         } else {
@@ -869,7 +1456,10 @@ impl DenseBoard {
             let strike_directions = [(-1, forward), (1, forward)];
             let targets_on_board = strike_directions.iter().filter_map(|d| position.add(*d));
 
-            let en_passant_square = self.en_passant.map(|(p, _)| p);
+            let en_passant_square = self
+                .en_passant
+                .filter(|_| self.en_passant_allowed())
+                .map(|(p, _)| p);
 
             targets_on_board
                 .filter(|p| self.opponent_present(*p) || en_passant_square == Some(*p))
@@ -1021,63 +1611,46 @@ impl DenseBoard {
             board_clone.lifted_piece = Hand::Empty;
             determine_all_threats(&board_clone)
         };
-        // Check if the castling right was not void earlier
-        if self.current_player == PlayerColor::White && self.castling.white_queen_side {
-            // Check if the spaces are empty
-            if self.is_empty(BoardPosition(1))
-                && self.is_empty(BoardPosition(2))
-                && self.is_empty(BoardPosition(3))
+        let player = self.current_player;
+        for &side in &[CastlingSide::Queen, CastlingSide::King] {
+            // Check if the castling right was not void earlier
+            if !self.castling.has_right(player, side)
+                || position != self.castling.king_square(player)
             {
-                // Check that there are no threats
-                if lazy_threats.is_none() {
-                    lazy_threats = Some(calc_threats()?);
-                }
-                let threats = lazy_threats.unwrap();
-                if !threats[2].0 && !threats[3].0 && !threats[4].0 {
-                    targets_on_board.push(BoardPosition(2));
-                }
+                continue;
             }
-        }
-        if self.current_player == PlayerColor::White && self.castling.white_king_side {
-            // Check if the spaces are empty
-            if self.is_empty(BoardPosition(5)) && self.is_empty(BoardPosition(6)) {
-                // Check that there are no threats
-                if lazy_threats.is_none() {
-                    lazy_threats = Some(calc_threats()?);
-                }
-                let threats = lazy_threats.unwrap();
-                if !threats[4].0 && !threats[5].0 && !threats[6].0 {
-                    targets_on_board.push(BoardPosition(6));
-                }
+            let rook = self.castling.rook_square(player, side);
+            if self.active_pieces()[rook.0 as usize] != Some(PieceType::Rook) {
+                continue;
             }
-        }
-        if self.current_player == PlayerColor::Black && self.castling.black_queen_side {
-            // Check if the spaces are empty
-            if self.is_empty(BoardPosition(57))
-                && self.is_empty(BoardPosition(58))
-                && self.is_empty(BoardPosition(59))
+            // Check if the spaces are empty. The king and the rook may pass
+            // through each other's starting squares.
+            let king_target = Castling::king_destination(player, side);
+            let rook_target = Castling::rook_destination(player, side);
+            let files = [position.x(), rook.x(), king_target.x(), rook_target.x()];
+            let (min, max) = (*files.iter().min().unwrap(), *files.iter().max().unwrap());
+            let row = position.y();
+            if !(min..=max)
+                .map(|x| BoardPosition::new(x, row))
+                .all(|p| p == rook || self.is_empty(p))
             {
-                // Check that there are no threats
-                if lazy_threats.is_none() {
-                    lazy_threats = Some(calc_threats()?);
-                }
-                let threats = lazy_threats.unwrap();
-                if !threats[58].0 && !threats[59].0 && !threats[60].0 {
-                    targets_on_board.push(BoardPosition(58));
-                }
+                continue;
             }
-        }
-        if self.current_player == PlayerColor::Black && self.castling.black_king_side {
-            // Check if the spaces are empty
-            if self.is_empty(BoardPosition(61)) && self.is_empty(BoardPosition(62)) {
-                // Check that there are no threats
-                if lazy_threats.is_none() {
-                    lazy_threats = Some(calc_threats()?);
-                }
-                let threats = lazy_threats.unwrap();
-                if !threats[60].0 && !threats[61].0 && !threats[62].0 {
-                    targets_on_board.push(BoardPosition(62));
-                }
+            if self.rules.castle_through_threats {
+                targets_on_board.push(self.castling.target(player, side));
+                continue;
+            }
+            // Check that there are no threats on the way of the king
+            if lazy_threats.is_none() {
+                lazy_threats = Some(calc_threats()?);
+            }
+            let threats = lazy_threats.unwrap();
+            let (from, to) = (
+                position.x().min(king_target.x()),
+                position.x().max(king_target.x()),
+            );
+            if (from..=to).all(|x| !threats[BoardPosition::new(x, row).0 as usize].0) {
+                targets_on_board.push(self.castling.target(player, side));
             }
         }
 
@@ -1180,6 +1753,100 @@ impl DenseBoard {
         }
     }
 
+    /// Hashes everything that makes two positions equal for the purpose of
+    /// detecting repetitions. The hashes are stored in snapshots, so they must
+    /// not change between versions. This is why it is 64 bit FNV-1a over an
+    /// explicit encoding of the position instead of the `Hash` trait.
+    pub(crate) fn position_hash(&self) -> u64 {
+        let player_code = |player: PlayerColor| match player {
+            PlayerColor::White => 0,
+            PlayerColor::Black => 1,
+        };
+        let piece_code = |piece: Option<PieceType>| match piece {
+            None => 0,
+            Some(PieceType::Pawn) => 1,
+            Some(PieceType::Rook) => 2,
+            Some(PieceType::Knight) => 3,
+            Some(PieceType::Bishop) => 4,
+            Some(PieceType::Queen) => 5,
+            Some(PieceType::King) => 6,
+        };
+
+        let mut bytes = Vec::with_capacity(64 * 2 + 13);
+        bytes.extend(self.white.iter().chain(&self.black).map(|&p| piece_code(p)));
+        bytes.push(player_code(self.current_player));
+        let castling = &self.castling;
+        for files in &[castling.white_files, castling.black_files] {
+            bytes.extend(&[files.king, files.queen_rook, files.king_rook]);
+        }
+        bytes.extend(&[
+            castling.white_queen_side as u8,
+            castling.white_king_side as u8,
+            castling.black_queen_side as u8,
+            castling.black_king_side as u8,
+        ]);
+        match self.en_passant {
+            Some((position, player)) => bytes.extend(&[position.0, player_code(player)]),
+            None => bytes.push(64),
+        }
+
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Checks if all pieces except for the kings are united.
+    fn all_pieces_paired(&self) -> bool {
+        let is_single = |piece: Option<PieceType>, partner: Option<PieceType>| {
+            piece.is_some() && piece != Some(PieceType::King) && partner.is_none()
+        };
+        (0..64).all(|i| {
+            !is_single(self.white[i], self.black[i]) && !is_single(self.black[i], self.white[i])
+        })
+    }
+
+    /// Must be called after every action. Once the turn is over, this updates
+    /// the draw information and ends the game if a draw condition is met.
+    /// Returns the position history if it was cleared.
+    fn finish_turn(&mut self) -> Option<Vec<u64>> {
+        if !self.is_settled() || self.promotion.is_some() {
+            return None;
+        }
+
+        let draw_state = &mut self.draw_state;
+        let mut cleared_history = None;
+        match std::mem::replace(&mut draw_state.turn_progress, Progress::None) {
+            Progress::None => draw_state.no_progress_half_moves += 1,
+            Progress::Reversible => draw_state.no_progress_half_moves = 0,
+            Progress::Irreversible => {
+                draw_state.no_progress_half_moves = 0;
+                cleared_history = Some(std::mem::take(&mut draw_state.history));
+            }
+        }
+
+        if self.victory_state.is_over() {
+            return cleared_history;
+        }
+
+        let position_hash = self.position_hash();
+        let draw_state = &mut self.draw_state;
+        draw_state.history.push(position_hash);
+        let repetitions = draw_state
+            .history
+            .iter()
+            .filter(|&&h| h == position_hash)
+            .count();
+
+        if repetitions >= 3 {
+            self.victory_state = VictoryState::Draw(DrawReason::Repetition);
+        } else if draw_state.no_progress_half_moves >= self.rules.no_progress_limit {
+            self.victory_state = VictoryState::Draw(DrawReason::NoProgress);
+        } else if self.all_pieces_paired() {
+            self.victory_state = VictoryState::Draw(DrawReason::AllPaired);
+        }
+        cleared_history
+    }
+
     fn remove_en_passant_info(&mut self) {
         if self.is_settled() {
             if let Some((_, player)) = self.en_passant {
@@ -1194,25 +1861,64 @@ impl DenseBoard {
 }
 
 impl PacoBoard for DenseBoard {
+    type Undo = undo::UndoRecord;
+
     fn execute(&mut self, action: PacoAction) -> Result<&mut Self, PacoError> {
-        // This can be optimized a lot. But the current implementation is at
-        // least easy and definitely consistent with the rules.
-        if self.actions()?.contains(&action) {
-            self.execute_trusted(action)
+        if self.is_legal(action)? {
+            self.execute_trusted(action)?;
+            Ok(self)
         } else {
             Err(PacoError::ActionNotLegal)
         }
     }
-    fn execute_trusted(&mut self, action: PacoAction) -> Result<&mut Self, PacoError> {
+    fn execute_trusted(&mut self, action: PacoAction) -> Result<undo::UndoRecord, PacoError> {
+        self.execute_reporting(action, None)
+    }
+    fn undo(&mut self, record: undo::UndoRecord) {
+        record.restore(self);
+    }
+    fn is_legal(&self, action: PacoAction) -> Result<bool, PacoError> {
         use PacoAction::*;
-        match action {
-            Lift(position) => self.lift(position),
-            Place(position) => {
-                self.place(position)?;
-                self.remove_en_passant_info();
-                Ok(self)
+        if self.victory_state.is_over() {
+            return Ok(false);
+        }
+        if self.promotion.is_some() {
+            return Ok(matches!(
+                action,
+                Promote(piece) if self.rules.promotion_pieces.allows(piece)
+            ));
+        }
+        if matches!(action.position(), Some(p) if p.0 >= 64) {
+            return Ok(false);
+        }
+
+        match (action, &self.lifted_piece) {
+            (Lift(position), Hand::Empty) => Ok(self.active_piece_present(position)),
+            (Place(target), Hand::Single { piece, position }) => {
+                if *piece == PieceType::King {
+                    // Only castling needs the expensive threat computation.
+                    let (dx, dy) = (
+                        target.x() as i8 - position.x() as i8,
+                        target.y() as i8 - position.y() as i8,
+                    );
+                    let adjacent = dx.abs() <= 1 && dy.abs() <= 1 && (dx, dy) != (0, 0);
+                    if adjacent && self.is_empty(target) {
+                        return Ok(true);
+                    }
+                }
+                Ok(self
+                    .place_targets(*position, *piece, false)?
+                    .contains(&target))
             }
-            Promote(new_type) => self.promote(new_type),
+            (
+                Place(target),
+                Hand::Pair {
+                    piece, position, ..
+                },
+            ) => Ok(self
+                .place_targets(*position, *piece, true)?
+                .contains(&target)),
+            _ => Ok(false),
         }
     }
     fn actions(&self) -> Result<Vec<PacoAction>, PacoError> {
@@ -1223,12 +1929,13 @@ impl PacoBoard for DenseBoard {
         }
 
         if self.promotion.is_some() {
-            return Ok(vec![
-                Promote(PieceType::Bishop),
-                Promote(PieceType::Rook),
-                Promote(PieceType::Knight),
-                Promote(PieceType::Queen),
-            ]);
+            return Ok(self
+                .rules
+                .promotion_pieces
+                .pieces()
+                .into_iter()
+                .map(Promote)
+                .collect());
         }
 
         match self.lifted_piece {
@@ -1260,12 +1967,13 @@ impl PacoBoard for DenseBoard {
         use PacoAction::*;
         // Promotion can threaten, because it can be done as part of a chain.
         if self.promotion.is_some() {
-            return vec![
-                Promote(PieceType::Bishop),
-                Promote(PieceType::Rook),
-                Promote(PieceType::Knight),
-                Promote(PieceType::Queen),
-            ];
+            return self
+                .rules
+                .promotion_pieces
+                .pieces()
+                .into_iter()
+                .map(Promote)
+                .collect();
         }
 
         match self.lifted_piece {
@@ -1300,14 +2008,10 @@ impl PacoBoard for DenseBoard {
         self.lifted_piece == Hand::Empty
     }
     fn king_in_union(&self, color: PlayerColor) -> bool {
-        let (king_pos, _) = self
-            .pieces_of_color(color)
+        self.pieces_of_color(color)
             .iter()
-            .enumerate()
-            .find(|&(_, &p)| p == Some(PieceType::King))
-            .unwrap();
-
-        self.pieces_of_color(color.other())[king_pos].is_some()
+            .zip(self.pieces_of_color(color.other()))
+            .any(|(&piece, partner)| piece == Some(PieceType::King) && partner.is_some())
     }
     fn current_player(&self) -> PlayerColor {
         self.current_player
@@ -1326,6 +2030,9 @@ impl PacoBoard for DenseBoard {
         )
     }
     fn en_passant_capture_possible(&self) -> bool {
+        if !self.en_passant_allowed() {
+            return false;
+        }
         if let Hand::Single {
             piece: PieceType::Pawn,
             position,
@@ -1514,7 +2221,10 @@ pub fn analyse_sako(board: impl PacoBoard) -> Result<(), PacoError> {
 }
 
 struct ExploredState<T: PacoBoard> {
-    settled: HashSet<T>,
+    /// Terminal states in the order in which they were found.
+    settled: Vec<T>,
+    /// States that are not terminal, but where no action is possible.
+    dead_ends: Vec<T>,
     found_via: HashMap<T, Vec<(PacoAction, Option<T>)>>,
 }
 
@@ -1526,39 +2236,46 @@ struct ExploredState<T: PacoBoard> {
 /// are marked (settled boards) and I wish to find all acyclic paths from the root to these
 /// marked (settled) nodes.
 fn determine_all_moves<T: PacoBoard>(board: T) -> Result<ExploredState<T>, PacoError> {
+    explore_moves(board, |b| b.is_settled())
+}
+
+/// Explores the graph of board states reachable from `board` until a state is
+/// terminal. Each state is only visited once, so the `found_via` map can be
+/// used to trace back how it was reached.
+fn explore_moves<T: PacoBoard>(
+    board: T,
+    is_terminal: impl Fn(&T) -> bool,
+) -> Result<ExploredState<T>, PacoError> {
     let mut todo_list: VecDeque<T> = VecDeque::new();
-    let mut settled: HashSet<T> = HashSet::new();
+    let mut settled: Vec<T> = Vec::new();
+    let mut dead_ends: Vec<T> = Vec::new();
     let mut found_via: HashMap<T, Vec<(PacoAction, Option<T>)>> = HashMap::new();
 
-    // Put all starting moves into the initialisation
-    for action in board.actions()? {
-        let mut b = board.clone();
-        b.execute_trusted(action)?;
-        found_via
-            .entry(b.clone())
-            .and_modify(|v| v.push((action, None)))
-            .or_insert_with(|| vec![(action, None)]);
-        todo_list.push_back(b);
-    }
+    // The root is explored like every other state, but it has no parent.
+    let mut pivot: Option<T> = None;
+    loop {
+        let todo = pivot.as_ref().unwrap_or(&board);
+        let actions = todo.actions()?;
+        if actions.is_empty() && pivot.is_some() {
+            dead_ends.push(todo.clone());
+        }
 
-    // Pull entries from the todo_list until it is empty.
-    while let Some(todo) = todo_list.pop_front() {
         // Execute all actions and look at the resulting board state.
-        for action in todo.actions()? {
+        for action in actions {
             let mut b = todo.clone();
             b.execute_trusted(action)?;
             // look up if this action has already been found.
             match found_via.entry(b.clone()) {
                 // We have seen this state already and don't need to add it to the todo list.
                 Entry::Occupied(mut o_entry) => {
-                    o_entry.get_mut().push((action, Some(todo.clone())));
+                    o_entry.get_mut().push((action, pivot.clone()));
                 }
                 // We encounter this state for the first time.
                 Entry::Vacant(v_entry) => {
-                    v_entry.insert(vec![(action, Some(todo.clone()))]);
-                    if b.is_settled() {
-                        // The state is settled, we don't look at the following moves.
-                        settled.insert(b);
+                    v_entry.insert(vec![(action, pivot.clone())]);
+                    if is_terminal(&b) {
+                        // The state is terminal, we don't look at the following moves.
+                        settled.push(b);
                     } else {
                         // We will look at the possible chain moves later.
                         todo_list.push_back(b);
@@ -1566,9 +2283,111 @@ fn determine_all_moves<T: PacoBoard>(board: T) -> Result<ExploredState<T>, PacoE
                 }
             }
         }
+
+        // Pull entries from the todo_list until it is empty.
+        match todo_list.pop_front() {
+            Some(next) => pivot = Some(next),
+            None => break,
+        }
     }
 
-    Ok(ExploredState { settled, found_via })
+    Ok(ExploredState {
+        settled,
+        dead_ends,
+        found_via,
+    })
+}
+
+/// A complete move together with the board it results in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacoMove<T: PacoBoard> {
+    pub actions: Vec<PacoAction>,
+    pub board: T,
+}
+
+/// Decides if `legal_moves` also returns action sequences that get stuck
+/// before the move is complete, like lifting a blocked pawn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeadEnds {
+    Include,
+    Exclude,
+}
+
+/// Determines all complete moves of the player that controls the board.
+/// A move is complete when the other player takes control or the game is over.
+/// This includes promotions and chains. Moves that lead to the same board are
+/// only returned once, using the shortest action sequence.
+///
+/// When dead ends are included, they are returned after the complete moves and
+/// their board is the state where no further action is possible.
+pub fn legal_moves<T: PacoBoard>(
+    board: &T,
+    dead_ends: DeadEnds,
+) -> Result<Vec<PacoMove<T>>, PacoError> {
+    if board.victory_state().is_over() {
+        return Ok(vec![]);
+    }
+
+    let player = board.controlling_player();
+    let ExploredState {
+        settled: mut targets,
+        dead_ends: stuck,
+        found_via,
+    } = explore_moves(board.clone(), |b| {
+        b.controlling_player() != player || b.victory_state().is_over()
+    })?;
+
+    if dead_ends == DeadEnds::Include {
+        targets.extend(stuck);
+    }
+
+    Ok(targets
+        .into_iter()
+        .filter_map(|target| {
+            let actions = trace_first_move(&target, &found_via)?;
+            Some(PacoMove {
+                actions,
+                board: target,
+            })
+        })
+        .collect())
+}
+
+/// Explores the rest of the turn once and walks back from all states where the
+/// turn is over. Every state on the way can still finish the turn, so the first
+/// actions leading into such a state are productive.
+fn productive_actions<T: PacoBoard>(board: &T) -> Result<Vec<PacoAction>, PacoError> {
+    if board.victory_state().is_over() {
+        return Ok(vec![]);
+    }
+
+    let player = board.controlling_player();
+    let explored = explore_moves(board.clone(), |b| {
+        b.controlling_player() != player || b.victory_state().is_over()
+    })?;
+
+    let mut productive: Vec<PacoAction> = vec![];
+    let mut reaches_end: HashSet<&T> = explored.settled.iter().collect();
+    let mut todo_list: VecDeque<&T> = explored.settled.iter().collect();
+    while let Some(state) = todo_list.pop_front() {
+        for (action, parent) in explored.found_via.get(state).into_iter().flatten() {
+            match parent {
+                None => productive.push(*action),
+                Some(parent) => {
+                    if reaches_end.insert(parent) {
+                        todo_list.push_back(parent);
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep the order of `actions`.
+    Ok(board
+        .actions()?
+        .into_iter()
+        .filter(|action| productive.contains(action))
+        .collect())
 }
 
 /// Traces a action sequence to the `target` state via the `found_via` map.
@@ -1697,27 +2516,18 @@ pub fn execute_sequence<T: PacoBoard>(
 pub fn find_last_checkpoint_index<'a>(
     actions: impl Iterator<Item = &'a PacoAction>,
 ) -> Result<usize, PacoError> {
-    let mut board = DenseBoard::new();
-    let mut action_counter = 0;
-    let mut last_checkpoint_index = action_counter;
-    let mut last_controlling_player = board.controlling_player();
-
-    for action in actions {
-        action_counter += 1;
-        board.execute_trusted(action.clone())?;
-        let new_controlling_player = board.controlling_player();
-        if new_controlling_player != last_controlling_player {
-            last_checkpoint_index = action_counter;
-            last_controlling_player = new_controlling_player;
-        }
-    }
-
-    // Check if the game is still running, otherwise we can't roll back.
-    if board.victory_state().is_over() {
-        return Ok(action_counter);
-    }
+    find_last_checkpoint_index_from(&DenseBoard::new(), actions)
+}
 
-    Ok(last_checkpoint_index)
+/// Like `find_last_checkpoint_index`, for games that don't start from the
+/// classic position.
+pub fn find_last_checkpoint_index_from<'a>(
+    start: &DenseBoard,
+    actions: impl Iterator<Item = &'a PacoAction>,
+) -> Result<usize, PacoError> {
+    let actions: Vec<PacoAction> = actions.copied().collect();
+    let history = game_history::GameHistory::from_trusted_actions(start.clone(), &actions)?;
+    Ok(history.last_checkpoint())
 }
 
 #[cfg(test)]
@@ -1750,7 +2560,7 @@ mod tests {
 
         Ok(determine_all_moves(board)?
             .settled
-            .drain()
+            .into_iter()
             .filter(|b| b.king_in_union(opponent))
             .collect())
     }
@@ -2790,30 +3600,433 @@ mod tests {
 
         Ok(())
     }
-}
 
-pub fn find_sako_sequences(board: &EditorBoard) -> Result<SakoSearchResult, PacoError> {
-    let mut white = vec![];
-    let mut black = vec![];
+    ////////////////////////////////////////////////////////////////////////////
+    // Test draw detection /////////////////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////
 
-    let white_board = board.with_active_player(PlayerColor::White);
-    let explored = determine_all_moves(white_board)?;
-    // Is there a state where the black king is dancing?
-    for board in explored.settled {
-        if board.king_in_union(PlayerColor::Black) {
-            if let Some(trace) = trace_first_move(&board, &explored.found_via) {
-                white.push(trace);
+    /// Moving the knights back and forth repeats the starting position. The
+    /// third occurrence of the position draws the game.
+    #[test]
+    fn test_draw_by_repetition() -> Result<(), PacoError> {
+        let mut board = DenseBoard::new();
+
+        for _ in 0..2 {
+            assert_eq!(board.victory_state(), VictoryState::Running);
+            execute_action!(board, lift, "b1");
+            execute_action!(board, place, "c3");
+            execute_action!(board, lift, "b8");
+            execute_action!(board, place, "c6");
+            execute_action!(board, lift, "c3");
+            execute_action!(board, place, "b1");
+            assert_eq!(board.victory_state(), VictoryState::Running);
+            execute_action!(board, lift, "c6");
+            execute_action!(board, place, "b8");
+        }
+
+        assert_eq!(
+            board.victory_state(),
+            VictoryState::Draw(DrawReason::Repetition)
+        );
+        assert!(board.actions()?.is_empty());
+
+        Ok(())
+    }
+
+    /// The hashes are stored in snapshots and must never change.
+    #[test]
+    fn test_position_hash_is_stable() {
+        assert_eq!(DenseBoard::new().position_hash(), 5_800_571_496_550_730_131);
+    }
+
+    /// Equality and hashing describe the position and ignore the draw state, so
+    /// a transposition is found in a `HashSet` even though the history differs.
+    #[test]
+    fn test_equality_ignores_draw_state() -> Result<(), PacoError> {
+        let mut board = DenseBoard::new();
+        execute_action!(board, lift, "b1");
+        execute_action!(board, place, "c3");
+        execute_action!(board, lift, "b8");
+        execute_action!(board, place, "c6");
+        execute_action!(board, lift, "c3");
+        execute_action!(board, place, "b1");
+        execute_action!(board, lift, "c6");
+        execute_action!(board, place, "b8");
+
+        assert_ne!(board.draw_state, DenseBoard::new().draw_state);
+        assert_eq!(board, DenseBoard::new());
+        let mut seen = HashSet::new();
+        seen.insert(DenseBoard::new());
+        assert!(seen.contains(&board));
+
+        Ok(())
+    }
+
+    /// The game is drawn when the no progress limit is reached. Moving a pawn
+    /// resets the counter.
+    #[test]
+    fn test_draw_by_no_progress() -> Result<(), PacoError> {
+        let mut board = DenseBoard::new();
+        board.set_no_progress_limit(4);
+
+        execute_action!(board, lift, "b1");
+        execute_action!(board, place, "c3");
+        execute_action!(board, lift, "b8");
+        execute_action!(board, place, "c6");
+        execute_action!(board, lift, "g1");
+        execute_action!(board, place, "f3");
+        assert_eq!(board.no_progress_half_moves(), 3);
+
+        execute_action!(board, lift, "e7");
+        execute_action!(board, place, "e6");
+        assert_eq!(board.no_progress_half_moves(), 0);
+
+        execute_action!(board, lift, "f3");
+        execute_action!(board, place, "g1");
+        execute_action!(board, lift, "g8");
+        execute_action!(board, place, "f6");
+        execute_action!(board, lift, "g1");
+        execute_action!(board, place, "h3");
+        assert_eq!(board.victory_state(), VictoryState::Running);
+        execute_action!(board, lift, "f6");
+        execute_action!(board, place, "g8");
+
+        assert_eq!(
+            board.victory_state(),
+            VictoryState::Draw(DrawReason::NoProgress)
+        );
+
+        Ok(())
+    }
+
+    /// Once the last single piece is united, nobody can unite with a king.
+    #[test]
+    fn test_draw_all_paired() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert("h8".try_into().unwrap(), Square::black(King));
+        squares.insert(pos("d1"), Square::white(Rook));
+        squares.insert(pos("d5"), Square::black(Knight));
+        squares.insert(pos("f5"), Square::pair(Pawn, Bishop));
+        let mut board = DenseBoard::from_squares(squares);
+
+        execute_action!(board, lift, "d1");
+        execute_action!(board, place, "d5");
+
+        assert_eq!(
+            board.victory_state(),
+            VictoryState::Draw(DrawReason::AllPaired)
+        );
+    }
+
+    /// Uniting with the king wins the game, even if this leaves all pieces
+    /// paired up.
+    #[test]
+    fn test_pacosako_beats_all_paired() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert(pos("d5"), Square::black(King));
+        squares.insert(pos("d1"), Square::white(Rook));
+        let mut board = DenseBoard::from_squares(squares);
+
+        execute_action!(board, lift, "d1");
+        execute_action!(board, place, "d5");
+
+        assert_eq!(
+            board.victory_state(),
+            VictoryState::PacoVictory(PlayerColor::White)
+        );
+    }
+
+    #[test]
+    fn test_legal_moves_initial_position() {
+        let board = DenseBoard::new();
+        let moves = legal_moves(&board, DeadEnds::Exclude).unwrap();
+
+        // 16 pawn moves and 4 knight moves.
+        assert_eq!(moves.len(), 20);
+        let boards: HashSet<&DenseBoard> = moves.iter().map(|m| &m.board).collect();
+        assert_eq!(boards.len(), 20);
+        for m in &moves {
+            assert_eq!(m.actions.len(), 2);
+            assert_eq!(m.board.controlling_player(), PlayerColor::Black);
+            let replayed = execute_sequence(&board, m.actions.clone(), PlayerColor::White);
+            assert_eq!(replayed.unwrap(), m.board);
+        }
+    }
+
+    /// Promotions are part of the move, so each promotion option is a move.
+    #[test]
+    fn test_legal_moves_promotion() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("h1"), Square::white(King));
+        squares.insert(pos("h8"), Square::black(King));
+        squares.insert(pos("a7"), Square::white(Pawn));
+        let board = DenseBoard::from_squares(squares);
+
+        let moves = legal_moves(&board, DeadEnds::Exclude).unwrap();
+        let promotions: Vec<&PacoMove<DenseBoard>> = moves
+            .iter()
+            .filter(|m| m.actions.iter().any(PacoAction::is_promotion))
+            .collect();
+        assert_eq!(promotions.len(), 4);
+        for m in promotions {
+            assert_eq!(
+                m.actions[..2],
+                [PacoAction::Lift(pos("a7")), PacoAction::Place(pos("a8"))]
+            );
+            assert_eq!(m.board.controlling_player(), PlayerColor::Black);
+        }
+    }
+
+    /// Lifting a blocked pawn is a dead end that is only returned on request.
+    #[test]
+    fn test_legal_moves_dead_ends() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("h1"), Square::white(King));
+        squares.insert(pos("h8"), Square::black(King));
+        squares.insert(pos("e2"), Square::white(Pawn));
+        squares.insert(pos("e3"), Square::black(Rook));
+        let board = DenseBoard::from_squares(squares);
+
+        let lifts_pawn = |m: &&PacoMove<DenseBoard>| m.actions[0] == PacoAction::Lift(pos("e2"));
+
+        let moves = legal_moves(&board, DeadEnds::Exclude).unwrap();
+        assert_eq!(moves.iter().filter(lifts_pawn).count(), 0);
+
+        let moves = legal_moves(&board, DeadEnds::Include).unwrap();
+        let dead_ends: Vec<&PacoMove<DenseBoard>> = moves.iter().filter(lifts_pawn).collect();
+        assert_eq!(dead_ends.len(), 1);
+        assert_eq!(dead_ends[0].actions, vec![PacoAction::Lift(pos("e2"))]);
+        assert!(!dead_ends[0].board.is_settled());
+    }
+
+    /// Every action on the board, including some that are never legal.
+    fn all_conceivable_actions() -> Vec<PacoAction> {
+        use PacoAction::*;
+        let mut result: Vec<PacoAction> = (0..64)
+            .flat_map(|i| vec![Lift(BoardPosition(i)), Place(BoardPosition(i))])
+            .collect();
+        result.extend(
+            [
+                PieceType::Pawn,
+                PieceType::Rook,
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Queen,
+                PieceType::King,
+            ]
+            .iter()
+            .map(|&p| Promote(p)),
+        );
+        result
+    }
+
+    /// `is_legal` agrees with `actions` on all positions of a random game.
+    #[quickcheck]
+    fn is_legal_matches_actions(seed: u64) -> bool {
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut board: DenseBoard = if rng.gen_bool(0.5) {
+            DenseBoard::new()
+        } else {
+            rng.gen()
+        };
+        let conceivable = all_conceivable_actions();
+
+        for _ in 0..100 {
+            let actions = board.actions().unwrap();
+            for &action in &conceivable {
+                if board.is_legal(action).unwrap() != actions.contains(&action) {
+                    return false;
+                }
             }
+            match actions.choose(&mut rng) {
+                Some(&action) => board.execute_trusted(action).unwrap(),
+                None => break,
+            };
         }
+        true
     }
 
-    let black_board = board.with_active_player(PlayerColor::Black);
-    let explored = determine_all_moves(black_board)?;
-    // Is there a state where the black king is dancing?
-    for board in explored.settled {
-        if board.king_in_union(PlayerColor::White) {
-            if let Some(trace) = trace_first_move(&board, &explored.found_via) {
-                black.push(trace);
+    #[test]
+    fn is_legal_castling() {
+        let mut board =
+            fen::parse_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w 0 ahah - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("e1"))).unwrap();
+        assert!(board.is_legal(PacoAction::Place(pos("c1"))).unwrap());
+        assert!(board.is_legal(PacoAction::Place(pos("g1"))).unwrap());
+        assert!(board.is_legal(PacoAction::Place(pos("d1"))).unwrap());
+        assert!(!board.is_legal(PacoAction::Place(pos("e1"))).unwrap());
+        assert!(!board.is_legal(PacoAction::Place(pos("e2"))).unwrap());
+        assert!(!board
+            .is_legal(PacoAction::Place(BoardPosition(64)))
+            .unwrap());
+    }
+
+    #[test]
+    fn productive_actions_skip_blocked_pieces() {
+        let board = DenseBoard::new();
+        assert_eq!(board.actions().unwrap().len(), 16);
+        let productive = board.productive_actions().unwrap();
+        assert_eq!(productive.len(), 10);
+        assert!(!productive.contains(&PacoAction::Lift(pos("a1"))));
+        assert!(!productive.contains(&PacoAction::Lift(pos("e1"))));
+        assert!(productive.contains(&PacoAction::Lift(pos("b1"))));
+    }
+
+    /// An action is productive exactly when the turn can still be finished
+    /// after it.
+    #[quickcheck]
+    fn productive_actions_finish_the_turn(seed: u64) -> bool {
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut board: DenseBoard = rng.gen();
+
+        for _ in 0..20 {
+            let player = board.controlling_player();
+            let actions = board.actions().unwrap();
+            let productive = board.productive_actions().unwrap();
+            for &action in &actions {
+                let mut b = board.clone();
+                b.execute_trusted(action).unwrap();
+                let finished = b.controlling_player() != player
+                    || b.victory_state().is_over()
+                    || !legal_moves(&b, DeadEnds::Exclude).unwrap().is_empty();
+                if finished != productive.contains(&action) {
+                    return false;
+                }
+            }
+            match actions.choose(&mut rng) {
+                Some(&action) => board.execute_trusted(action).unwrap(),
+                None => break,
+            };
+        }
+        true
+    }
+
+    #[test]
+    fn editor_board_without_state() {
+        let board: EditorBoard = serde_json::from_str(r#"{"pieces": []}"#).unwrap();
+        assert_eq!(*board.state(), EditorState::default());
+
+        // Castling rights follow the pieces when they are not given.
+        let board: EditorBoard =
+            (&fen::parse_fen("4k3/8/8/8/8/8/8/4K2R w 0 ---- - - - - -").unwrap()).into();
+        let board = board.with_state(EditorState::default());
+        let dense = board.with_active_player(PlayerColor::Black).unwrap();
+        assert_eq!(
+            fen::write_fen(&dense),
+            "4k3/8/8/8/8/8/8/4K2R b 0 -h-- - - - - - 0"
+        );
+    }
+
+    #[test]
+    fn editor_board_keeps_state() -> Result<(), PacoError> {
+        let mut dense = fen::parse_fen("r3k3/8/8/8/8/8/8/K7 b 0 ---- - - - - -").unwrap();
+        dense.execute(PacoAction::Lift(pos("a8")))?;
+
+        let board: EditorBoard = (&dense).into();
+        let json = serde_json::to_string(&board).unwrap();
+        let board: EditorBoard = serde_json::from_str(&json).unwrap();
+        assert_eq!(board.state().current_player, Some(PlayerColor::Black));
+        assert_eq!(
+            fen::write_fen(&board.with_active_player(PlayerColor::Black)?),
+            fen::write_fen(&dense)
+        );
+
+        // Only black is analysed and the search continues with the rook in hand.
+        let sequences = find_sako_sequences(&board)?;
+        assert_eq!(sequences.black, vec![vec![PacoAction::Place(pos("a1"))]]);
+        assert!(sequences.white.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn editor_board_hand_belongs_to_player_to_move() -> Result<(), PacoError> {
+        let mut dense = fen::parse_fen("r3k3/8/8/8/8/8/8/K7 b 0 ---- - - - - -").unwrap();
+        dense.execute(PacoAction::Lift(pos("a8")))?;
+        let board: EditorBoard = (&dense).into();
+
+        assert_ne!(
+            board.with_active_player(PlayerColor::Black)?.lifted_piece,
+            Hand::Empty
+        );
+        assert_eq!(
+            board.with_active_player(PlayerColor::White)?.lifted_piece,
+            Hand::Empty
+        );
+
+        let state = EditorState {
+            current_player: None,
+            ..board.state().clone()
+        };
+        let board = board.with_state(state);
+        assert_eq!(
+            board.with_active_player(PlayerColor::Black),
+            Err(PacoError::InvalidPosition(vec![
+                PositionProblem::HandWithoutPlayer
+            ]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn editor_board_lost_castling() -> Result<(), PacoError> {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w 0 ahah - - - - -";
+        let board: EditorBoard = (&fen::parse_fen(fen).unwrap()).into();
+        let board = board.with_state(EditorState {
+            castling: Some("----".to_owned()),
+            ..EditorState::default()
+        });
+        let dense = board.with_active_player(PlayerColor::White)?;
+        assert_eq!(
+            fen::write_fen(&dense),
+            "r3k2r/8/8/8/8/8/8/R3K2R w 0 ---- - - - - - 0"
+        );
+
+        let board = board.with_state(EditorState {
+            castling: Some("castle".to_owned()),
+            ..EditorState::default()
+        });
+        assert_eq!(
+            board.with_active_player(PlayerColor::White),
+            Err(PacoError::InputJsonMalformed)
+        );
+        Ok(())
+    }
+}
+
+pub fn find_sako_sequences(board: &EditorBoard) -> Result<SakoSearchResult, PacoError> {
+    let problems = board.validate()?;
+    if !problems.is_empty() {
+        return Err(PacoError::InvalidPosition(problems));
+    }
+
+    let mut white = vec![];
+    let mut black = vec![];
+
+    for player in board.players_to_analyse() {
+        let explored = determine_all_moves(board.with_active_player(player)?)?;
+        // Is there a state where the opponent king is dancing?
+        for settled in explored.settled {
+            if settled.king_in_union(player.other()) {
+                if let Some(trace) = trace_first_move(&settled, &explored.found_via) {
+                    match player {
+                        PlayerColor::White => white.push(trace),
+                        PlayerColor::Black => black.push(trace),
+                    }
+                }
             }
         }
     }
@@ -2823,8 +4036,9 @@ pub fn find_sako_sequences(board: &EditorBoard) -> Result<SakoSearchResult, Paco
 
 #[wasm_bindgen]
 pub fn find_sako_sequences_json(board: &str) -> String {
-    let editor_board: EditorBoard = serde_json::from_str(board).unwrap();
-    let search_result = find_sako_sequences(&editor_board);
+    let search_result = serde_json::from_str(board)
+        .map_err(|_| PacoError::InputJsonMalformed)
+        .and_then(|board: EditorBoard| find_sako_sequences(&board));
 
     match search_result {
         Ok(search_result) => serde_json::to_string(&search_result).unwrap(),
@@ -1,6 +1,18 @@
-use pacosako::{DenseBoard, EditorBoard, PacoError};
+use pacosako::solver::{Solution, Solver, SolverLimits};
+use pacosako::{DenseBoard, EditorBoard, EditorState, PacoBoard, PacoError};
 
 fn main() -> Result<(), PacoError> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("perft") {
+        return perft(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("solve") {
+        return solve(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("chains") {
+        return chains(&args[2..]);
+    }
+
     let schema = "8 .. .. .. .. .K .B .. .R
 7 .P .. .. .. .P .. .. .P
 6 .. .. .P .. QB .R .P ..
@@ -16,8 +28,9 @@ fn main() -> Result<(), PacoError> {
     if let Ok((_, matrix)) = parsed {
         let board = DenseBoard::from_squares(matrix.0);
 
-        // Print the board as json using serde.
-        let pieces: EditorBoard = (&board).into();
+        // Print the board as json using serde. The exchange notation only
+        // has pieces, so the analysis looks at both players.
+        let pieces = EditorBoard::from(&board).with_state(EditorState::default());
 
         let sequences = pacosako::find_sako_sequences(&pieces);
 
@@ -38,3 +51,128 @@ fn main() -> Result<(), PacoError> {
 
     Ok(())
 }
+
+/// Prints the perft divide output for a position.
+/// Usage: pacosako-example perft <actions|turns> <depth> [fen]
+fn perft(args: &[String]) -> Result<(), PacoError> {
+    let usage = "Usage: pacosako-example perft <actions|turns> <depth> [fen]";
+    let depth: usize = match args.get(1).and_then(|d| d.parse().ok()) {
+        Some(depth) => depth,
+        None => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+    let board = match args.get(2) {
+        Some(fen) => match pacosako::fen::parse_fen(fen) {
+            Ok(board) => board,
+            Err(error) => {
+                println!("{}", error);
+                return Ok(());
+            }
+        },
+        None => DenseBoard::new(),
+    };
+
+    let mut total = 0;
+    match args.get(0).map(String::as_str) {
+        Some("actions") => {
+            for (action, count) in pacosako::perft::divide_actions(&board, depth)? {
+                println!("{:?}: {}", action, count);
+                total += count;
+            }
+        }
+        Some("turns") => {
+            for (actions, count) in pacosako::perft::divide_turns(&board, depth)? {
+                let notation = pacosako::notation::write_turn(&board, &actions)
+                    .unwrap_or_else(|_| format!("{:?}", actions));
+                println!("{}: {}", notation, count);
+                total += count;
+            }
+        }
+        _ => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    }
+    println!("Total: {}", total);
+
+    Ok(())
+}
+
+/// Checks if the side to move can force a win and prints the main line.
+/// Usage: pacosako-example solve <turns> <fen>
+fn solve(args: &[String]) -> Result<(), PacoError> {
+    let usage = "Usage: pacosako-example solve <turns> <fen>";
+    let max_turns: usize = match args.get(0).and_then(|t| t.parse().ok()) {
+        Some(max_turns) => max_turns,
+        None => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+    let board = match args.get(1).map(|fen| pacosako::fen::parse_fen(fen)) {
+        Some(Ok(board)) => board,
+        Some(Err(error)) => {
+            println!("{}", error);
+            return Ok(());
+        }
+        None => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+
+    let mut solver = Solver::new(SolverLimits {
+        max_turns,
+        node_limit: None,
+    });
+    match solver.solve(&board)? {
+        Solution::Win(win) => {
+            println!("Win in {}:", win.turns());
+            let mut replay = board.clone();
+            for turn in win.main_line() {
+                let notation = pacosako::notation::write_turn(&replay, &turn)
+                    .unwrap_or_else(|_| format!("{:?}", turn));
+                println!("  {}", notation);
+                for action in turn {
+                    replay.execute(action)?;
+                }
+            }
+        }
+        Solution::NoWin => println!("No win in {} turns.", max_turns),
+        Solution::Unknown => println!("Undecided."),
+    }
+    println!("Nodes: {}", solver.nodes());
+
+    Ok(())
+}
+
+/// Prints the chain graph of a position in the Graphviz DOT language.
+/// Usage: pacosako-example chains <depth> <fen> | dot -Tsvg > chains.svg
+fn chains(args: &[String]) -> Result<(), PacoError> {
+    let usage = "Usage: pacosako-example chains <depth> <fen>";
+    let depth: usize = match args.get(0).and_then(|d| d.parse().ok()) {
+        Some(depth) => depth,
+        None => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+    let board = match args.get(1).map(|fen| pacosako::fen::parse_fen(fen)) {
+        Some(Ok(board)) => board,
+        Some(Err(error)) => {
+            println!("{}", error);
+            return Ok(());
+        }
+        None => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+
+    let graph = pacosako::chain_graph::chain_graph(&board, depth)?;
+    print!("{}", graph.to_dot());
+
+    Ok(())
+}
@@ -0,0 +1,473 @@
+//! Monte Carlo tree search as an alternative to the alpha-beta search.
+//!
+//! Each playout walks down the tree of complete turns using UCT to balance
+//! good turns against turns that were not tried often, adds one new turn to
+//! the tree and then plays the game to the end with random turns. The
+//! result is propagated back up the tree. The turn that was visited most
+//! often is played.
+//!
+//! The tree is kept between calls. When the engine is asked for a turn in a
+//! position that is already part of the tree, usually after the opponent
+//! answered, the statistics of that subtree are reused.
+
+use crate::ai::Engine;
+use crate::{
+    legal_moves, DeadEnds, DenseBoard, PacoAction, PacoBoard, PacoError, PacoMove, PlayerColor,
+    VictoryState,
+};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// How the game is played to the end after a new turn was added to the tree.
+/// The rollout picks complete turns, so it never gets stuck by lifting a
+/// piece that can't move.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rollout {
+    /// Uniformly random turns.
+    Random,
+    /// Random turns, but a winning turn is always taken and turns that unite
+    /// with an opponent piece are preferred.
+    Heuristic,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MctsConfig {
+    /// Number of playouts for each call to `choose_turn`.
+    pub playouts: u32,
+    /// The exploration constant of UCT. Higher values try more turns.
+    pub exploration: f64,
+    pub rollout: Rollout,
+    /// Rollouts that take longer than this many actions count as a draw.
+    pub max_rollout_actions: usize,
+    pub seed: u64,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            playouts: 1000,
+            exploration: std::f64::consts::SQRT_2,
+            rollout: Rollout::Heuristic,
+            max_rollout_actions: 300,
+            seed: 0,
+        }
+    }
+}
+
+struct Node {
+    board: DenseBoard,
+    /// The turn that leads from the parent to this node.
+    actions: Vec<PacoAction>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Turns that have no node yet. None until the node is expanded.
+    untried: Option<Vec<PacoMove<DenseBoard>>>,
+    visits: u32,
+    /// Sum of results from the perspective of the player that did the turn
+    /// leading to this node. A win is 1, a draw is 0.5.
+    reward: f64,
+}
+
+impl Node {
+    fn new(board: DenseBoard, actions: Vec<PacoAction>, parent: Option<usize>) -> Self {
+        Node {
+            board,
+            actions,
+            parent,
+            children: Vec::new(),
+            untried: None,
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+}
+
+/// A Monte Carlo tree search player. The tree is stored as a list of nodes
+/// where the root is always at index 0.
+pub struct Mcts {
+    pub config: MctsConfig,
+    rng: StdRng,
+    nodes: Vec<Node>,
+}
+
+impl Mcts {
+    pub fn new(config: MctsConfig) -> Self {
+        let rng = StdRng::seed_from_u64(config.seed);
+        Mcts {
+            config,
+            rng,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Number of playouts that went through the current root. This includes
+    /// the playouts of earlier calls when the tree was reused.
+    pub fn root_visits(&self) -> u32 {
+        self.nodes.first().map_or(0, |root| root.visits)
+    }
+
+    /// Makes the node with the given board the root, keeping its subtree.
+    /// Only the root, its children and grandchildren are checked.
+    fn reuse_tree(&mut self, board: &DenseBoard) {
+        let mut candidates = vec![0];
+        if let Some(root) = self.nodes.first() {
+            for &child in &root.children {
+                candidates.push(child);
+                candidates.extend(&self.nodes[child].children);
+            }
+        }
+        let new_root = candidates
+            .into_iter()
+            .find(|&index| self.nodes.get(index).map(|n| &n.board) == Some(board));
+
+        match new_root {
+            Some(0) => {}
+            Some(index) => self.extract_subtree(index),
+            None => self.nodes = vec![Node::new(board.clone(), Vec::new(), None)],
+        }
+    }
+
+    /// Replaces the tree by the subtree starting at `index`.
+    fn extract_subtree(&mut self, index: usize) {
+        let mut old_nodes: Vec<Option<Node>> = self.nodes.drain(..).map(Some).collect();
+        let mut todo = vec![(index, None)];
+        while let Some((old_index, parent)) = todo.pop() {
+            let mut node = match old_nodes[old_index].take() {
+                Some(node) => node,
+                None => continue,
+            };
+            let new_index = self.nodes.len();
+            let children = std::mem::take(&mut node.children);
+            node.parent = parent;
+            self.nodes.push(node);
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(new_index);
+            }
+            todo.extend(children.into_iter().map(|child| (child, Some(new_index))));
+        }
+    }
+
+    /// Walks down the tree with UCT until a node with untried turns is found.
+    fn select(&mut self) -> Result<usize, PacoError> {
+        let mut index = 0;
+        loop {
+            let node = &mut self.nodes[index];
+            if node.board.victory_state().is_over() {
+                return Ok(index);
+            }
+            if node.untried.is_none() {
+                let mut moves = legal_moves(&node.board, DeadEnds::Exclude)?;
+                // A winning turn makes all other turns pointless.
+                let win = VictoryState::PacoVictory(node.board.controlling_player());
+                match moves.iter().position(|m| m.board.victory_state() == win) {
+                    Some(index) => moves = vec![moves.swap_remove(index)],
+                    None => moves.shuffle(&mut self.rng),
+                }
+                node.untried = Some(moves);
+            }
+            let untried = node.untried.as_ref().map(Vec::len).unwrap_or(0);
+            if untried > 0 || node.children.is_empty() {
+                return Ok(index);
+            }
+            index = self.best_child(index);
+        }
+    }
+
+    fn best_child(&self, index: usize) -> usize {
+        let parent = &self.nodes[index];
+        let log_visits = (parent.visits.max(1) as f64).ln();
+        let uct = |child: usize| {
+            let node = &self.nodes[child];
+            let visits = node.visits.max(1) as f64;
+            node.reward / visits + self.config.exploration * (log_visits / visits).sqrt()
+        };
+        let mut best = parent.children[0];
+        for &child in &parent.children[1..] {
+            if uct(child) > uct(best) {
+                best = child;
+            }
+        }
+        best
+    }
+
+    /// Adds one untried turn as a new child node, if there is one.
+    fn expand(&mut self, index: usize) -> usize {
+        let next = self.nodes[index].untried.as_mut().and_then(|u| u.pop());
+        match next {
+            Some(m) => {
+                let child = self.nodes.len();
+                self.nodes.push(Node::new(m.board, m.actions, Some(index)));
+                self.nodes[index].children.push(child);
+                child
+            }
+            None => index,
+        }
+    }
+
+    /// Plays random turns until the game is over. Returns the winner or
+    /// None for a draw.
+    fn rollout(&mut self, board: &DenseBoard) -> Result<Option<PlayerColor>, PacoError> {
+        let mut board = board.clone();
+        let mut action_count = 0;
+        while action_count < self.config.max_rollout_actions {
+            match board.victory_state() {
+                VictoryState::Running => {}
+                VictoryState::PacoVictory(winner) | VictoryState::TimeoutVictory(winner) => {
+                    return Ok(Some(winner))
+                }
+                VictoryState::Draw(_) => return Ok(None),
+            }
+            let mut moves = legal_moves(&board, DeadEnds::Exclude)?;
+            let index = match self.config.rollout {
+                Rollout::Random => self.random_index(moves.len()),
+                Rollout::Heuristic => self.heuristic_index(&board, &moves),
+            };
+            match index {
+                Some(index) => {
+                    let m = moves.swap_remove(index);
+                    action_count += m.actions.len();
+                    board = m.board;
+                }
+                // No turn can be finished.
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+
+    fn random_index(&mut self, len: usize) -> Option<usize> {
+        if len == 0 {
+            None
+        } else {
+            Some(self.rng.gen_range(0, len))
+        }
+    }
+
+    fn heuristic_index(
+        &mut self,
+        board: &DenseBoard,
+        moves: &[PacoMove<DenseBoard>],
+    ) -> Option<usize> {
+        let player = board.controlling_player();
+        let win = VictoryState::PacoVictory(player);
+        if let Some(index) = moves.iter().position(|m| m.board.victory_state() == win) {
+            return Some(index);
+        }
+        let opponent = player.other();
+        let uniting: Vec<usize> = (0..moves.len())
+            .filter(|&index| unites(board, opponent, &moves[index].actions))
+            .collect();
+        if !uniting.is_empty() && self.rng.gen_bool(0.5) {
+            return uniting.choose(&mut self.rng).cloned();
+        }
+        self.random_index(moves.len())
+    }
+
+    /// Adds the result of a playout to all nodes on the path to the root.
+    fn backpropagate(&mut self, mut index: usize, winner: Option<PlayerColor>) {
+        loop {
+            let parent = self.nodes[index].parent;
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            if let Some(parent) = parent {
+                // The reward is seen from the player that did the turn.
+                let mover = self.nodes[parent].board.controlling_player();
+                let node = &mut self.nodes[index];
+                node.reward += match winner {
+                    Some(winner) if winner == mover => 1.0,
+                    Some(_) => 0.0,
+                    None => 0.5,
+                };
+                index = parent;
+            } else {
+                return;
+            }
+        }
+    }
+}
+
+/// Does the turn place a piece on a square where the opponent has a piece?
+fn unites(board: &DenseBoard, opponent: PlayerColor, actions: &[PacoAction]) -> bool {
+    actions.iter().any(|action| match action {
+        PacoAction::Place(target) => {
+            let (white, black) = board.get_at(*target);
+            match opponent {
+                PlayerColor::White => white.is_some(),
+                PlayerColor::Black => black.is_some(),
+            }
+        }
+        _ => false,
+    })
+}
+
+impl Engine for Mcts {
+    fn choose_turn(&mut self, board: &DenseBoard) -> Result<Option<Vec<PacoAction>>, PacoError> {
+        if board.victory_state().is_over() {
+            return Ok(None);
+        }
+        self.reuse_tree(board);
+
+        // The root needs a child to have a turn to return, even without any
+        // playouts. Select stops at the root while it has no children.
+        if self.nodes[0].children.is_empty() {
+            let root = self.select()?;
+            self.expand(root);
+        }
+
+        for _ in 0..self.config.playouts {
+            let selected = self.select()?;
+            let expanded = self.expand(selected);
+            let board = self.nodes[expanded].board.clone();
+            let winner = self.rollout(&board)?;
+            self.backpropagate(expanded, winner);
+        }
+
+        let root = &self.nodes[0];
+        Ok(root
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+            .map(|&child| self.nodes[child].actions.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{AlphaBeta, SearchLimits};
+    use crate::parser::Square;
+    use crate::{execute_sequence, BoardPosition, PieceType};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn config(playouts: u32) -> MctsConfig {
+        MctsConfig {
+            playouts,
+            max_rollout_actions: 100,
+            ..MctsConfig::default()
+        }
+    }
+
+    #[test]
+    fn finds_immediate_win() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert(pos("d5"), Square::black(King));
+        squares.insert(pos("d1"), Square::white(Rook));
+        squares.insert(pos("h7"), Square::black(Pawn));
+        let board = DenseBoard::from_squares(squares);
+
+        let turn = Mcts::new(config(300)).choose_turn(&board).unwrap();
+        assert_eq!(
+            turn,
+            Some(vec![
+                PacoAction::Lift(pos("d1")),
+                PacoAction::Place(pos("d5"))
+            ])
+        );
+    }
+
+    /// Without playouts the engine still plays a legal turn.
+    #[test]
+    fn zero_playouts() {
+        let board = DenseBoard::new();
+        let turn = Mcts::new(config(0)).choose_turn(&board).unwrap().unwrap();
+        execute_sequence(&board, turn, PlayerColor::White).unwrap();
+    }
+
+    #[test]
+    fn reuses_tree_after_reply() {
+        // Only the tree matters here, so the rollouts can stay short.
+        let mut engine = Mcts::new(MctsConfig {
+            max_rollout_actions: 10,
+            ..config(100)
+        });
+        let board = DenseBoard::new();
+        let turn = engine.choose_turn(&board).unwrap().unwrap();
+        let board = execute_sequence(&board, turn, PlayerColor::White).unwrap();
+
+        // Black replies with the turn that was explored most.
+        let reply = {
+            let child = engine.nodes.iter().position(|n| n.board == board).unwrap();
+            let grandchild = *engine.nodes[child]
+                .children
+                .iter()
+                .max_by_key(|&&c| engine.nodes[c].visits)
+                .unwrap();
+            engine.nodes[grandchild].actions.clone()
+        };
+        let board = execute_sequence(&board, reply, PlayerColor::Black).unwrap();
+        let reused = engine
+            .nodes
+            .iter()
+            .find(|n| n.board == board)
+            .map(|n| n.visits)
+            .unwrap();
+        assert!(reused > 0);
+
+        engine.choose_turn(&board).unwrap().unwrap();
+        assert_eq!(engine.root_visits(), reused + 100);
+        assert!(engine.nodes[0].parent.is_none());
+        assert_eq!(engine.nodes[0].board, board);
+    }
+
+    /// Turns like Rb1-b8 keep the black king on rows the rooks threaten,
+    /// so white wins next turn whatever black does. With the short limit on
+    /// half-moves without progress, every other turn leads to a draw after
+    /// the king escapes to g8.
+    #[test]
+    fn prefers_forced_win() {
+        use PieceType::*;
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("a1"), Square::white(King));
+        squares.insert(pos("h8"), Square::black(King));
+        squares.insert(pos("a7"), Square::white(Rook));
+        squares.insert(pos("b1"), Square::white(Rook));
+        squares.insert(pos("h3"), Square::white(Pawn));
+        squares.insert(pos("h4"), Square::black(Pawn));
+        let mut board = DenseBoard::from_squares(squares);
+        board.set_no_progress_limit(3);
+
+        let turn = Mcts::new(config(300)).choose_turn(&board).unwrap().unwrap();
+        let board = execute_sequence(&board, turn, PlayerColor::White).unwrap();
+        let replies = legal_moves(&board, DeadEnds::Exclude).unwrap();
+        assert!(!replies.is_empty());
+        for reply in replies {
+            let answers = legal_moves(&reply.board, DeadEnds::Exclude).unwrap();
+            let win = VictoryState::PacoVictory(PlayerColor::White);
+            assert!(answers.iter().any(|m| m.board.victory_state() == win));
+        }
+    }
+
+    /// Both engines can be used through the same interface.
+    #[test]
+    fn engines_are_interchangeable() {
+        let mut engines: Vec<Box<dyn Engine>> = vec![
+            Box::new(Mcts::new(MctsConfig {
+                rollout: Rollout::Random,
+                ..config(20)
+            })),
+            Box::new(AlphaBeta::new(SearchLimits {
+                max_depth: 1,
+                ..SearchLimits::default()
+            })),
+        ];
+
+        let mut board = DenseBoard::new();
+        for _ in 0..2 {
+            for engine in &mut engines {
+                let player = board.controlling_player();
+                let turn = engine.choose_turn(&board).unwrap().unwrap();
+                board = execute_sequence(&board, turn, player).unwrap();
+            }
+        }
+    }
+}
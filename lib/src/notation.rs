@@ -0,0 +1,694 @@
+//! Human readable notation for a single turn of Paco Ŝako.
+//!
+//! A turn is written as the lifted piece and the square it is lifted from,
+//! followed by every square it (or a piece it replaced in a chain) is placed
+//! on. A promotion is written right after the place that caused it.
+//!
+//! ```text
+//! e2>e4           A pawn moves two squares forward.
+//! Nc3>d5          A knight unites with an opponent piece on d5.
+//! Nc5>d7>d8=Q     A chain: The knight replaces a pawn, which is then promoted.
+//! O-O             Castling king side, this is the same as Ke1>g1 in a classic game.
+//! =QNb1>c3        A promotion that is left over from the opponent's turn.
+//! ```
+//!
+//! When reading a turn, you may also use the short form `Nd5` for a turn that
+//! consists of a single lift and place. This is only accepted if exactly one
+//! piece of that type can move there. A single square that holds a piece of
+//! the player to move stands for a turn that was stopped after lifting it.
+
+use crate::{
+    BoardPosition, CastlingSide, DenseBoard, PacoAction, PacoBoard, PacoError, PieceType,
+    PlayerColor,
+};
+use std::convert::TryFrom;
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum NotationError {
+    #[error("I can not read '{0}' as a turn. Write it like 'Nc3>d5'.")]
+    Syntax(String),
+    #[error("The game is already over.")]
+    GameOver,
+    #[error("There is no piece of the player to move on {0}.")]
+    NoPieceToLift(BoardPosition),
+    #[error("There is no {expected:?} to lift on {position}.")]
+    WrongPiece {
+        position: BoardPosition,
+        expected: PieceType,
+    },
+    #[error("The {piece:?} from {from} can not be placed on {target}.")]
+    IllegalPlace {
+        piece: PieceType,
+        from: BoardPosition,
+        target: BoardPosition,
+    },
+    #[error("You can not promote to a {0:?} right now.")]
+    IllegalPromotion(PieceType),
+    #[error("{0:?} is not legal in this position: {1}")]
+    IllegalAction(PacoAction, PacoError),
+    #[error("The pawn on {0} still needs to be promoted.")]
+    PromotionMissing(BoardPosition),
+    #[error("The chain is not finished, there is still a piece in hand.")]
+    ChainIncomplete,
+    #[error("The actions belong to more than one turn.")]
+    MultipleTurns,
+    #[error("No {piece:?} can move to {target}.")]
+    NoCandidate {
+        piece: PieceType,
+        target: BoardPosition,
+    },
+    #[error("'{notation}' is ambiguous, it could mean any of {candidates:?}.")]
+    Ambiguous {
+        notation: String,
+        candidates: Vec<String>,
+    },
+}
+
+/// Writes a turn in human readable notation. The actions must all be done by
+/// the player that controls the board. The turn does not need to be complete.
+pub fn write_turn(board: &DenseBoard, actions: &[PacoAction]) -> Result<String, NotationError> {
+    let mut board = board.clone();
+    let player = board.controlling_player();
+    let mut result = String::new();
+    // Remembers where the lift is written, so it can be replaced by castling.
+    let mut lift: Option<(usize, BoardPosition, PieceType)> = None;
+
+    for &action in actions {
+        if board.controlling_player() != player {
+            return Err(NotationError::MultipleTurns);
+        }
+        match action {
+            PacoAction::Lift(position) => {
+                let piece =
+                    own_piece_at(&board, position).ok_or(NotationError::NoPieceToLift(position))?;
+                lift = Some((result.len(), position, piece));
+                result.push_str(&piece_letter(piece));
+                result.push_str(&position.to_string());
+            }
+            PacoAction::Place(target) => {
+                let castling = match lift {
+                    Some((start, from, PieceType::King)) => {
+                        board.castling_side(from, target).map(|side| (start, side))
+                    }
+                    _ => None,
+                };
+                match castling {
+                    Some((start, side)) => {
+                        result.truncate(start);
+                        result.push_str(match side {
+                            CastlingSide::Queen => "O-O-O",
+                            CastlingSide::King => "O-O",
+                        });
+                    }
+                    None => {
+                        result.push('>');
+                        result.push_str(&target.to_string());
+                    }
+                }
+            }
+            PacoAction::Promote(piece) => {
+                result.push('=');
+                result.push_str(&piece_letter(piece));
+            }
+        }
+        execute_explained(&mut board, action)?;
+    }
+
+    Ok(result)
+}
+
+/// Reads a complete turn written in human readable notation and returns the
+/// actions it consists of. Returns an error explaining the problem, if the
+/// turn can not be executed on the given board.
+pub fn parse_turn(board: &DenseBoard, notation: &str) -> Result<Vec<PacoAction>, NotationError> {
+    let actions = parse_partial_turn(board, notation)?;
+
+    let mut result_board = board.clone();
+    execute_all(&mut result_board, &actions)?;
+    check_complete(board, &result_board)?;
+
+    Ok(actions)
+}
+
+/// Like `parse_turn`, but also accepts a turn that is not finished yet. This
+/// is useful to read the last turn of a game that is still in progress.
+pub fn parse_partial_turn(
+    board: &DenseBoard,
+    notation: &str,
+) -> Result<Vec<PacoAction>, NotationError> {
+    let parsed = ParsedTurn::parse(notation.trim())
+        .ok_or_else(|| NotationError::Syntax(notation.to_owned()))?;
+
+    if board.victory_state().is_over() {
+        return Err(NotationError::GameOver);
+    }
+
+    let mut actions = Vec::new();
+    if let Some(piece) = parsed.leading_promotion {
+        actions.push(PacoAction::Promote(piece));
+    }
+
+    match parsed.body {
+        Body::Empty => {}
+        Body::Castling { queen_side } => {
+            let mut after_promotion = board.clone();
+            execute_all(&mut after_promotion, &actions)?;
+            let player = after_promotion.current_player();
+            let side = if queen_side {
+                CastlingSide::Queen
+            } else {
+                CastlingSide::King
+            };
+            let king = after_promotion.castling.king_square(player);
+            let target = after_promotion.castling.target(player, side);
+            if own_piece_at(&after_promotion, king) != Some(PieceType::King)
+                || !after_promotion.castling.has_right(player, side)
+            {
+                return Err(NotationError::IllegalPlace {
+                    piece: PieceType::King,
+                    from: king,
+                    target,
+                });
+            }
+            actions.push(PacoAction::Lift(king));
+            actions.push(PacoAction::Place(target));
+        }
+        Body::Full {
+            piece,
+            lift,
+            places,
+        } => {
+            actions.push(PacoAction::Lift(lift));
+            for (target, promotion) in places {
+                actions.push(PacoAction::Place(target));
+                if let Some(promotion) = promotion {
+                    actions.push(PacoAction::Promote(promotion));
+                }
+            }
+            check_lifted_piece(board, &actions, piece)?;
+        }
+        Body::Short {
+            piece,
+            target,
+            promotion,
+        } => {
+            let mut after_promotion = board.clone();
+            execute_all(&mut after_promotion, &actions)?;
+            let own_piece = own_piece_at(&after_promotion, target);
+            if own_piece.is_some() && promotion.is_none() {
+                // No piece can move onto a square with a piece of the same
+                // player, so this is a turn that was stopped after lifting.
+                if piece.is_some() && piece != own_piece {
+                    return Err(NotationError::WrongPiece {
+                        position: target,
+                        expected: piece.unwrap_or(PieceType::Pawn),
+                    });
+                }
+                actions.push(PacoAction::Lift(target));
+                return Ok(actions);
+            }
+            let lift = resolve_short_form(&after_promotion, notation, piece, target, promotion)?;
+            actions.push(PacoAction::Lift(lift));
+            actions.push(PacoAction::Place(target));
+            if let Some(promotion) = promotion {
+                actions.push(PacoAction::Promote(promotion));
+            }
+        }
+    }
+
+    let mut result_board = board.clone();
+    execute_all(&mut result_board, &actions)?;
+
+    Ok(actions)
+}
+
+/// The structure of a turn as it was written, before it is matched with a board.
+struct ParsedTurn {
+    leading_promotion: Option<PieceType>,
+    body: Body,
+}
+
+enum Body {
+    Empty,
+    Castling {
+        queen_side: bool,
+    },
+    Full {
+        piece: Option<PieceType>,
+        lift: BoardPosition,
+        places: Vec<(BoardPosition, Option<PieceType>)>,
+    },
+    Short {
+        piece: Option<PieceType>,
+        target: BoardPosition,
+        promotion: Option<PieceType>,
+    },
+}
+
+impl ParsedTurn {
+    fn parse(input: &str) -> Option<Self> {
+        let mut rest = input;
+        let leading_promotion = parse_promotion(&mut rest);
+
+        let body = if rest.is_empty() {
+            // A turn may consist of a single promotion, but it can't be empty.
+            leading_promotion?;
+            Body::Empty
+        } else if rest == "O-O-O" || rest == "0-0-0" {
+            Body::Castling { queen_side: true }
+        } else if rest == "O-O" || rest == "0-0" {
+            Body::Castling { queen_side: false }
+        } else {
+            let piece = parse_piece(&mut rest);
+            let first_square = parse_square(&mut rest)?;
+            let first_promotion = parse_promotion(&mut rest);
+
+            if rest.is_empty() {
+                Body::Short {
+                    piece,
+                    target: first_square,
+                    promotion: first_promotion,
+                }
+            } else {
+                // Promotions are only possible after placing a piece.
+                if first_promotion.is_some() {
+                    return None;
+                }
+                let mut places = Vec::new();
+                while let Some(after_arrow) = rest.strip_prefix('>') {
+                    rest = after_arrow;
+                    let square = parse_square(&mut rest)?;
+                    places.push((square, parse_promotion(&mut rest)));
+                }
+                if !rest.is_empty() {
+                    return None;
+                }
+                Body::Full {
+                    piece,
+                    lift: first_square,
+                    places,
+                }
+            }
+        };
+
+        Some(ParsedTurn {
+            leading_promotion,
+            body,
+        })
+    }
+}
+
+/// Reads an optional upper case piece letter.
+fn parse_piece(input: &mut &str) -> Option<PieceType> {
+    let piece = input.chars().next().and_then(PieceType::from_letter)?;
+    *input = &input[1..];
+    Some(piece)
+}
+
+/// Reads a square like "d5".
+fn parse_square(input: &mut &str) -> Option<BoardPosition> {
+    let square = BoardPosition::try_from(input.get(0..2)?).ok()?;
+    *input = &input[2..];
+    Some(square)
+}
+
+/// Reads an optional promotion like "=Q".
+fn parse_promotion(input: &mut &str) -> Option<PieceType> {
+    let mut rest = input.strip_prefix('=')?;
+    let piece = parse_piece(&mut rest)?;
+    *input = rest;
+    Some(piece)
+}
+
+/// Pawns are written without a letter, like in chess.
+fn piece_letter(piece: PieceType) -> String {
+    match piece {
+        PieceType::Pawn => String::new(),
+        _ => piece.letter().to_string(),
+    }
+}
+
+/// The piece of the player to move at the given position.
+fn own_piece_at(board: &DenseBoard, position: BoardPosition) -> Option<PieceType> {
+    let (white, black) = board.get_at(position);
+    match board.current_player() {
+        PlayerColor::White => white,
+        PlayerColor::Black => black,
+    }
+}
+
+/// Executes an action and turns a failure into a specific explanation.
+fn execute_explained(board: &mut DenseBoard, action: PacoAction) -> Result<(), NotationError> {
+    if board.victory_state().is_over() {
+        return Err(NotationError::GameOver);
+    }
+    let hand = board.lifted_piece.clone();
+    match board.execute(action) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(match (action, hand.position()) {
+            (PacoAction::Lift(position), None) if own_piece_at(board, position).is_none() => {
+                NotationError::NoPieceToLift(position)
+            }
+            (PacoAction::Place(target), Some(from)) => NotationError::IllegalPlace {
+                piece: hand.piece().unwrap_or(PieceType::Pawn),
+                from,
+                target,
+            },
+            (PacoAction::Promote(piece), _) => NotationError::IllegalPromotion(piece),
+            _ => NotationError::IllegalAction(action, error),
+        }),
+    }
+}
+
+fn execute_all(board: &mut DenseBoard, actions: &[PacoAction]) -> Result<(), NotationError> {
+    for &action in actions {
+        execute_explained(board, action)?;
+    }
+    Ok(())
+}
+
+/// Checks that the lifted piece is of the type given in the notation.
+fn check_lifted_piece(
+    board: &DenseBoard,
+    actions: &[PacoAction],
+    expected: Option<PieceType>,
+) -> Result<(), NotationError> {
+    let mut board = board.clone();
+    for &action in actions {
+        if let PacoAction::Lift(position) = action {
+            let piece = own_piece_at(&board, position);
+            if piece.is_none() {
+                return Err(NotationError::NoPieceToLift(position));
+            }
+            if let Some(expected) = expected {
+                if piece != Some(expected) {
+                    return Err(NotationError::WrongPiece { position, expected });
+                }
+            }
+            return Ok(());
+        }
+        execute_explained(&mut board, action)?;
+    }
+    Ok(())
+}
+
+/// A turn is complete once the other player takes control or the game ends.
+fn check_complete(before: &DenseBoard, after: &DenseBoard) -> Result<(), NotationError> {
+    if after.controlling_player() != before.controlling_player() || after.victory_state().is_over()
+    {
+        Ok(())
+    } else if let Some(position) = after.promotion {
+        Err(NotationError::PromotionMissing(position))
+    } else {
+        Err(NotationError::ChainIncomplete)
+    }
+}
+
+/// Finds the only piece that can complete a turn by moving to the target.
+fn resolve_short_form(
+    board: &DenseBoard,
+    notation: &str,
+    piece: Option<PieceType>,
+    target: BoardPosition,
+    promotion: Option<PieceType>,
+) -> Result<BoardPosition, NotationError> {
+    let piece = piece.unwrap_or(PieceType::Pawn);
+    let mut candidates = Vec::new();
+
+    for from in (0..64).map(BoardPosition) {
+        if own_piece_at(board, from) != Some(piece) {
+            continue;
+        }
+        let mut actions = vec![PacoAction::Lift(from), PacoAction::Place(target)];
+        if let Some(promotion) = promotion {
+            actions.push(PacoAction::Promote(promotion));
+        }
+        let mut candidate_board = board.clone();
+        if execute_all(&mut candidate_board, &actions).is_ok()
+            && check_complete(board, &candidate_board).is_ok()
+        {
+            candidates.push(from);
+        }
+    }
+
+    match candidates.len() {
+        0 => Err(NotationError::NoCandidate { piece, target }),
+        1 => Ok(candidates[0]),
+        _ => Err(NotationError::Ambiguous {
+            notation: notation.to_owned(),
+            candidates: candidates
+                .iter()
+                .map(|from| format!("{}{}>{}", piece_letter(piece), from, target))
+                .collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Square;
+    use crate::testing;
+    use quickcheck::{Arbitrary, Gen};
+    use rand::Rng;
+    use std::collections::HashMap;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    /// Checks that a turn is written as expected and that reading it back
+    /// gives the same actions.
+    fn assert_roundtrip(board: &DenseBoard, actions: &[PacoAction], notation: &str) {
+        assert_eq!(write_turn(board, actions).unwrap(), notation);
+        assert_eq!(parse_turn(board, notation).unwrap(), actions);
+    }
+
+    #[test]
+    fn simple_moves() {
+        use PacoAction::*;
+        let board = DenseBoard::new();
+        assert_roundtrip(&board, &[Lift(pos("e2")), Place(pos("e4"))], "e2>e4");
+        assert_roundtrip(&board, &[Lift(pos("b1")), Place(pos("c3"))], "Nb1>c3");
+        assert_eq!(
+            parse_turn(&board, "Pe2>e4").unwrap(),
+            vec![Lift(pos("e2")), Place(pos("e4"))]
+        );
+    }
+
+    #[test]
+    fn chain_with_promotion() {
+        use PacoAction::*;
+        use PieceType::*;
+        let mut squares = HashMap::new();
+        squares.insert(pos("c5"), Square::white(Knight));
+        squares.insert(pos("d7"), Square::pair(Pawn, Rook));
+        let board = DenseBoard::from_squares(squares);
+
+        assert_roundtrip(
+            &board,
+            &[
+                Lift(pos("c5")),
+                Place(pos("d7")),
+                Place(pos("d8")),
+                Promote(Queen),
+            ],
+            "Nc5>d7>d8=Q",
+        );
+    }
+
+    #[test]
+    fn castling() {
+        use PacoAction::*;
+        use PieceType::*;
+        let mut squares = HashMap::new();
+        squares.insert(pos("e1"), Square::white(King));
+        squares.insert(pos("a1"), Square::white(Rook));
+        squares.insert(pos("h1"), Square::white(Rook));
+        let board = DenseBoard::from_squares(squares);
+
+        assert_roundtrip(&board, &[Lift(pos("e1")), Place(pos("g1"))], "O-O");
+        assert_roundtrip(&board, &[Lift(pos("e1")), Place(pos("c1"))], "O-O-O");
+        assert_eq!(
+            parse_turn(&board, "Ke1>g1").unwrap(),
+            vec![Lift(pos("e1")), Place(pos("g1"))]
+        );
+    }
+
+    #[test]
+    fn short_form() {
+        use PacoAction::*;
+        use PieceType::*;
+        let board = DenseBoard::new();
+        assert_eq!(
+            parse_turn(&board, "Nf3").unwrap(),
+            vec![Lift(pos("g1")), Place(pos("f3"))]
+        );
+        assert_eq!(
+            parse_turn(&board, "e4").unwrap(),
+            vec![Lift(pos("e2")), Place(pos("e4"))]
+        );
+
+        let mut squares = HashMap::new();
+        squares.insert(pos("b1"), Square::white(Knight));
+        squares.insert(pos("f3"), Square::white(Knight));
+        let board = DenseBoard::from_squares(squares);
+        assert_eq!(
+            parse_turn(&board, "Nd2"),
+            Err(NotationError::Ambiguous {
+                notation: "Nd2".to_owned(),
+                candidates: vec!["Nb1>d2".to_owned(), "Nf3>d2".to_owned()],
+            })
+        );
+        assert_eq!(
+            parse_turn(&board, "Nb1>d2").unwrap(),
+            vec![Lift(pos("b1")), Place(pos("d2"))]
+        );
+        assert_eq!(
+            parse_turn(&board, "Nd3"),
+            Err(NotationError::NoCandidate {
+                piece: Knight,
+                target: pos("d3")
+            })
+        );
+    }
+
+    #[test]
+    fn leading_promotion() {
+        use PacoAction::*;
+        use PieceType::*;
+        // Black moves a pair with a white pawn onto the black home row. White
+        // needs to promote the pawn before starting their own turn.
+        let mut squares = HashMap::new();
+        squares.insert(pos("d7"), Square::pair(Pawn, Rook));
+        squares.insert(pos("b1"), Square::white(Knight));
+        let mut board = DenseBoard::from_squares(squares);
+        board.current_player = PlayerColor::Black;
+        board.execute(Lift(pos("d7"))).unwrap();
+        board.execute(Place(pos("d8"))).unwrap();
+
+        assert_roundtrip(
+            &board,
+            &[Promote(Queen), Lift(pos("b1")), Place(pos("c3"))],
+            "=QNb1>c3",
+        );
+    }
+
+    #[test]
+    fn errors() {
+        use PieceType::*;
+        let board = DenseBoard::new();
+        assert_eq!(
+            parse_turn(&board, "Nz9"),
+            Err(NotationError::Syntax("Nz9".to_owned()))
+        );
+        assert_eq!(
+            parse_turn(&board, "Nb1>"),
+            Err(NotationError::Syntax("Nb1>".to_owned()))
+        );
+        assert_eq!(
+            parse_turn(&board, "Nd4>e6"),
+            Err(NotationError::NoPieceToLift(pos("d4")))
+        );
+        assert_eq!(
+            parse_turn(&board, "Bb1>c3"),
+            Err(NotationError::WrongPiece {
+                position: pos("b1"),
+                expected: Bishop
+            })
+        );
+        assert_eq!(
+            parse_turn(&board, "Nb1>b3"),
+            Err(NotationError::IllegalPlace {
+                piece: Knight,
+                from: pos("b1"),
+                target: pos("b3")
+            })
+        );
+        assert_eq!(
+            parse_turn(&board, "e7>e5"),
+            Err(NotationError::NoPieceToLift(pos("e7")))
+        );
+        assert_eq!(
+            write_turn(
+                &board,
+                &[
+                    PacoAction::Lift(pos("e2")),
+                    PacoAction::Place(pos("e4")),
+                    PacoAction::Lift(pos("e7"))
+                ]
+            ),
+            Err(NotationError::MultipleTurns)
+        );
+    }
+
+    #[test]
+    fn incomplete_turns() {
+        use PieceType::*;
+        let mut squares = HashMap::new();
+        squares.insert(pos("c5"), Square::white(Knight));
+        squares.insert(pos("d7"), Square::pair(Pawn, Rook));
+        let board = DenseBoard::from_squares(squares);
+
+        assert_eq!(
+            parse_turn(&board, "Nc5>d7"),
+            Err(NotationError::ChainIncomplete)
+        );
+        assert_eq!(
+            parse_turn(&board, "Nc5>d7>d8"),
+            Err(NotationError::PromotionMissing(pos("d8")))
+        );
+        assert_eq!(
+            parse_turn(&board, "Nc5>d7>d8=K"),
+            Err(NotationError::IllegalPromotion(King))
+        );
+        assert_eq!(
+            parse_partial_turn(&board, "Nc5>d7"),
+            Ok(vec![
+                PacoAction::Lift(pos("c5")),
+                PacoAction::Place(pos("d7"))
+            ])
+        );
+    }
+
+    /// A random board together with a random turn played on it.
+    #[derive(Clone, Debug)]
+    struct RandomTurn(DenseBoard, Vec<PacoAction>);
+
+    impl Arbitrary for RandomTurn {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            let mut rng = testing::rng(g);
+            let board: DenseBoard = rng.gen();
+            let mut after = board.clone();
+            let mut turn = Vec::new();
+            while after.controlling_player() == board.controlling_player()
+                && !after.victory_state().is_over()
+            {
+                let actions = after.actions().unwrap();
+                if actions.is_empty() || turn.len() > 100 {
+                    break;
+                }
+                let action = actions[rng.gen_range(0, actions.len())];
+                after.execute_trusted(action).unwrap();
+                turn.push(action);
+            }
+            RandomTurn(board, turn)
+        }
+    }
+
+    #[quickcheck]
+    fn notation_roundtrip(turn: RandomTurn) -> bool {
+        let RandomTurn(board, actions) = turn;
+        if board.victory_state().is_over() {
+            return true;
+        }
+        let notation = write_turn(&board, &actions).unwrap();
+        let mut after = board.clone();
+        execute_all(&mut after, &actions).unwrap();
+        match parse_turn(&board, &notation) {
+            Ok(parsed) => parsed == actions,
+            // Random play may run into a dead end, then the turn is not complete.
+            Err(_) => check_complete(&board, &after).is_err(),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Perft counts all paths through the game tree up to a given depth. The
+//! numbers are a regression net for the rules: Any change to how pieces move,
+//! chain, castle or capture en passant changes them.
+//!
+//! There are two ways to count. `perft_actions` counts sequences of single
+//! actions, `perft_turns` counts sequences of complete turns as returned by
+//! `legal_moves`. Turns leading to the same board are only counted once and
+//! dead ends are never counted. Games that are over have no children.
+//!
+//! The `divide` functions split the count by the first action or turn, which
+//! makes it easy to find the move where two implementations disagree.
+//!
+//! Reference numbers, see the tests for the positions. Castling needs the king
+//! and the rook on their starting squares, so the castling rights in the fen
+//! of the chain, en passant and promotion positions make no difference.
+//!
+//! | Position         | Actions 1..4        | Turns 1..3              |
+//! |------------------|---------------------|-------------------------|
+//! | Start            | 16, 20, 320, 400    | 20, 400, 8902           |
+//! | Chains           | 16, 52, 816, 2376   | 215, 34765, 4676496     |
+//! | En passant chain | 3, 13, 32, 86       | 20, 113, 1308           |
+//! | Promotion chain  | 4, 13, 41, 142      | 126, 1431, 71980        |
+//! | Castling         | 11, 25, 275, 625    | 25, 625, 15206          |
+
+use crate::{legal_moves, DeadEnds, PacoAction, PacoBoard, PacoError};
+
+/// Counts all action sequences of the given length.
+pub fn perft_actions<T: PacoBoard>(board: &T, depth: usize) -> Result<u64, PacoError> {
+    count_actions(&mut board.clone(), depth)
+}
+
+/// Like `perft_actions`, but returns the count for each first action.
+pub fn divide_actions<T: PacoBoard>(
+    board: &T,
+    depth: usize,
+) -> Result<Vec<(PacoAction, u64)>, PacoError> {
+    if depth == 0 || board.victory_state().is_over() {
+        return Ok(vec![]);
+    }
+
+    let mut board = board.clone();
+    let mut result = Vec::new();
+    for action in board.actions()? {
+        let record = board.execute_trusted(action)?;
+        result.push((action, count_actions(&mut board, depth - 1)?));
+        board.undo(record);
+    }
+    Ok(result)
+}
+
+/// Walks the tree in place, every action is undone after it was counted.
+fn count_actions<T: PacoBoard>(board: &mut T, depth: usize) -> Result<u64, PacoError> {
+    if depth == 0 {
+        return Ok(1);
+    }
+    if board.victory_state().is_over() {
+        return Ok(0);
+    }
+    let actions = board.actions()?;
+    if depth == 1 {
+        return Ok(actions.len() as u64);
+    }
+
+    let mut total = 0;
+    for action in actions {
+        let record = board.execute_trusted(action)?;
+        total += count_actions(board, depth - 1)?;
+        board.undo(record);
+    }
+    Ok(total)
+}
+
+/// Counts all sequences of complete turns of the given length.
+pub fn perft_turns<T: PacoBoard>(board: &T, depth: usize) -> Result<u64, PacoError> {
+    if depth == 0 {
+        return Ok(1);
+    }
+    let moves = legal_moves(board, DeadEnds::Exclude)?;
+    if depth == 1 {
+        return Ok(moves.len() as u64);
+    }
+
+    let mut total = 0;
+    for m in moves {
+        total += perft_turns(&m.board, depth - 1)?;
+    }
+    Ok(total)
+}
+
+/// Like `perft_turns`, but returns the count for each first turn.
+pub fn divide_turns<T: PacoBoard>(
+    board: &T,
+    depth: usize,
+) -> Result<Vec<(Vec<PacoAction>, u64)>, PacoError> {
+    if depth == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut result = Vec::new();
+    for m in legal_moves(board, DeadEnds::Exclude)? {
+        result.push((m.actions, perft_turns(&m.board, depth - 1)?));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::DenseBoard;
+
+    /// The position from the example binary, full of chain opportunities.
+    const CHAINS: &str =
+        "4kb1r/p3p2p/2p1wrp1/1p2e1N1/1P1F2sD/2N1A3/P1P2P1P/R4RK1 w 0 ahah - - - - -";
+    /// Black can unite en passant with a pair and continue the chain from there.
+    const EN_PASSANT_CHAIN: &str = "7k/8/8/8/2pD4/8/8/4K3 b 0 ahah d3 - - - -";
+    /// White can chain through a promotion.
+    const PROMOTION_CHAIN: &str = "4e3/3P1e2/3k4/8/8/8/8/K7 w 0 ahah - - - - -";
+    /// Both sides can castle to either side.
+    const CASTLING: &str = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w 0 ahah - - - - -";
+
+    fn assert_perft(fen: &str, actions: &[u64], turns: &[u64]) {
+        let board = parse_fen(fen).unwrap();
+        for (depth, &expected) in actions.iter().enumerate() {
+            assert_eq!(perft_actions(&board, depth + 1).unwrap(), expected);
+        }
+        for (depth, &expected) in turns.iter().enumerate() {
+            assert_eq!(perft_turns(&board, depth + 1).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn start_position() {
+        let board = DenseBoard::new();
+        assert_eq!(perft_actions(&board, 0).unwrap(), 1);
+        assert_perft(
+            &crate::fen::write_fen(&board),
+            &[16, 20, 320, 400],
+            &[20, 400],
+        );
+    }
+
+    #[test]
+    fn chains() {
+        assert_perft(CHAINS, &[16, 52, 816, 2376], &[215]);
+    }
+
+    #[test]
+    fn en_passant_chain() {
+        assert_perft(EN_PASSANT_CHAIN, &[3, 13, 32, 86], &[20, 113]);
+    }
+
+    #[test]
+    fn promotion_chain() {
+        assert_perft(PROMOTION_CHAIN, &[4, 13, 41, 142], &[126, 1431]);
+    }
+
+    #[test]
+    fn castling() {
+        assert_perft(CASTLING, &[11, 25, 275, 625], &[25, 625]);
+    }
+
+    /// The divide output adds up to the total.
+    #[test]
+    fn divide_sums_up() {
+        let board = parse_fen(CHAINS).unwrap();
+        let divide = divide_actions(&board, 3).unwrap();
+        let sum: u64 = divide.iter().map(|(_, count)| count).sum();
+        assert_eq!(sum, perft_actions(&board, 3).unwrap());
+
+        let board = parse_fen(PROMOTION_CHAIN).unwrap();
+        let divide = divide_turns(&board, 2).unwrap();
+        let sum: u64 = divide.iter().map(|(_, count)| count).sum();
+        assert_eq!(sum, perft_turns(&board, 2).unwrap());
+    }
+}
@@ -0,0 +1,302 @@
+//! Generates Paco Ŝako puzzles from random positions.
+//!
+//! A puzzle is a position where the side to move can unite with the opponent
+//! king in a single turn. Random positions are turned into candidates and
+//! kept when they pass a filter. Filters can be combined with `and`, `or` and
+//! `!`, and there are presets for the collections we built so far.
+//!
+//! Generation is seeded, so the same seed and filter always produce the same
+//! puzzles.
+
+use crate::fen::write_fen;
+use crate::{BoardPosition, DenseBoard, PacoAction, PacoBoard, PacoError, PlayerColor};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A position together with all turns that solve it.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub board: DenseBoard,
+    /// All turns of the controlling player that unite with the opponent king.
+    pub solutions: Vec<Vec<PacoAction>>,
+}
+
+impl Candidate {
+    /// Finds all solutions for the player that controls the board.
+    pub fn new(board: DenseBoard) -> Result<Self, PacoError> {
+        let solutions = board.sako_threats(board.controlling_player().other())?;
+        Ok(Candidate { board, solutions })
+    }
+
+    /// Number of actions in the shortest solution, or 0 without a solution.
+    pub fn shortest_solution(&self) -> usize {
+        self.solutions.iter().map(Vec::len).min().unwrap_or(0)
+    }
+
+    /// Squares where a solution starts.
+    pub fn starting_points(&self) -> HashSet<BoardPosition> {
+        self.solutions
+            .iter()
+            .filter_map(|solution| solution.first().and_then(PacoAction::position))
+            .collect()
+    }
+
+    fn has_promotion(&self) -> bool {
+        self.solutions
+            .iter()
+            .any(|solution| solution.iter().any(PacoAction::is_promotion))
+    }
+}
+
+/// Decides if a candidate is a puzzle we want to keep.
+pub struct Filter(Box<dyn Fn(&Candidate) -> bool>);
+
+impl Filter {
+    pub fn new(predicate: impl Fn(&Candidate) -> bool + 'static) -> Self {
+        Filter(Box::new(predicate))
+    }
+
+    pub fn accepts(&self, candidate: &Candidate) -> bool {
+        (self.0)(candidate)
+    }
+
+    /// Accepts candidates that pass both filters.
+    pub fn and(self, other: Filter) -> Self {
+        Filter::new(move |c| self.accepts(c) && other.accepts(c))
+    }
+
+    /// Accepts candidates that pass at least one of the filters.
+    pub fn or(self, other: Filter) -> Self {
+        Filter::new(move |c| self.accepts(c) || other.accepts(c))
+    }
+
+    /// There is at least one solution.
+    pub fn has_solution() -> Self {
+        Filter::new(|c| !c.solutions.is_empty())
+    }
+
+    /// There is exactly one solution.
+    pub fn unique_solution() -> Self {
+        Filter::new(|c| c.solutions.len() == 1)
+    }
+
+    /// There are at least `count` solutions.
+    pub fn min_solutions(count: usize) -> Self {
+        Filter::new(move |c| c.solutions.len() >= count)
+    }
+
+    /// Even the shortest solution needs at least `length` actions.
+    pub fn min_shortest_solution(length: usize) -> Self {
+        Filter::new(move |c| c.shortest_solution() >= length)
+    }
+
+    /// No solution is a simple lift and place.
+    pub fn no_direct_solution() -> Self {
+        Filter::new(|c| c.solutions.iter().all(|solution| solution.len() > 2))
+    }
+
+    /// No solution needs to promote a pawn.
+    pub fn no_promotion() -> Self {
+        Filter::new(|c| !c.has_promotion())
+    }
+
+    /// The solutions start from at least `count` different squares.
+    pub fn min_starting_points(count: usize) -> Self {
+        Filter::new(move |c| c.starting_points().len() >= count)
+    }
+}
+
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::new(move |c| !self.accepts(c))
+    }
+}
+
+/// Long chains without promotion, as posted on discord.
+pub fn long_chains_for_discord() -> Filter {
+    Filter::min_shortest_solution(15).and(Filter::no_promotion())
+}
+
+/// Many different solutions that start from many different squares.
+pub fn many_start_positions() -> Filter {
+    Filter::no_direct_solution()
+        .and(Filter::no_promotion())
+        .and(Filter::min_solutions(5))
+        .and(Filter::min_starting_points(7))
+}
+
+/// Many short solutions, which makes them easy enough for children.
+pub fn puzzle_book_for_children() -> Filter {
+    Filter::no_direct_solution()
+        .and(Filter::no_promotion())
+        .and(Filter::min_solutions(5))
+        .and(Filter::min_starting_points(3))
+}
+
+/// Estimates how hard a puzzle is. Long chains and promotions are harder to
+/// see, while many solutions make it easier to stumble upon one.
+pub fn difficulty(candidate: &Candidate) -> u32 {
+    if candidate.solutions.is_empty() {
+        return 0;
+    }
+    let chain = 10 * candidate.shortest_solution() as u32;
+    let promotion = if candidate.has_promotion() { 15 } else { 0 };
+    let easy_to_find = 5 * (candidate.solutions.len().min(10) as u32 - 1)
+        + 5 * (candidate.starting_points().len().min(10) as u32 - 1);
+    (chain + promotion).saturating_sub(easy_to_find)
+}
+
+/// A puzzle as it is stored in the puzzle database.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Puzzle {
+    /// The position in the notation of the `fen` module.
+    pub fen: String,
+    pub to_move: PlayerColor,
+    pub solutions: Vec<Vec<PacoAction>>,
+    pub difficulty: u32,
+}
+
+impl Puzzle {
+    pub fn from_candidate(candidate: &Candidate) -> Self {
+        Puzzle {
+            fen: write_fen(&candidate.board),
+            to_move: candidate.board.controlling_player(),
+            solutions: candidate.solutions.clone(),
+            difficulty: difficulty(candidate),
+        }
+    }
+}
+
+/// Creates puzzles from random positions.
+pub struct Generator {
+    rng: StdRng,
+    filter: Filter,
+    /// Positions that were already returned.
+    seen: HashSet<DenseBoard>,
+}
+
+impl Generator {
+    pub fn new(seed: u64, filter: Filter) -> Self {
+        Generator {
+            rng: StdRng::seed_from_u64(seed),
+            filter,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Looks at up to `attempts` random positions and returns the first one
+    /// that passes the filter. Each position is tried with both players to
+    /// move, and no position is returned twice.
+    pub fn next_puzzle(&mut self, attempts: usize) -> Result<Option<Puzzle>, PacoError> {
+        for _ in 0..attempts {
+            let board: DenseBoard = self.rng.gen();
+            for &color in &[PlayerColor::White, PlayerColor::Black] {
+                let mut board = board.clone();
+                board.current_player = color;
+                if self.seen.contains(&board) {
+                    continue;
+                }
+                let candidate = Candidate::new(board)?;
+                if Filter::has_solution().accepts(&candidate) && self.filter.accepts(&candidate) {
+                    let puzzle = Puzzle::from_candidate(&candidate);
+                    self.seen.insert(candidate.board);
+                    return Ok(Some(puzzle));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn candidate(fen: &str) -> Candidate {
+        Candidate::new(parse_fen(fen).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn direct_solution() {
+        let c = candidate("r6k/8/8/8/8/8/8/K7 b 0 ---- - - - - -");
+        assert_eq!(
+            c.solutions,
+            vec![vec![
+                PacoAction::Lift(pos("a8")),
+                PacoAction::Place(pos("a1"))
+            ]]
+        );
+        assert!(Filter::unique_solution().accepts(&c));
+        assert!(!Filter::no_direct_solution().accepts(&c));
+        assert!((!Filter::no_direct_solution()).accepts(&c));
+        assert_eq!(difficulty(&c), 20);
+    }
+
+    #[test]
+    fn chain_solution() {
+        // The rook takes over the union on d4 and the bishop reaches the king.
+        let c = candidate("8/8/5k2/8/R2e4/8/8/7K w 0 ---- - - - - -");
+        assert_eq!(
+            c.solutions,
+            vec![vec![
+                PacoAction::Lift(pos("a4")),
+                PacoAction::Place(pos("d4")),
+                PacoAction::Place(pos("f6"))
+            ]]
+        );
+        assert!(Filter::no_direct_solution().accepts(&c));
+        assert!(Filter::min_shortest_solution(3).accepts(&c));
+        assert!(!Filter::min_shortest_solution(4).accepts(&c));
+        assert_eq!(difficulty(&c), 30);
+    }
+
+    #[test]
+    fn combinators() {
+        let c = candidate("r6k/8/8/8/8/8/8/K7 b 0 ---- - - - - -");
+        let yes = || Filter::has_solution();
+        let no = || Filter::min_solutions(2);
+        assert!(yes().and(yes()).accepts(&c));
+        assert!(!yes().and(no()).accepts(&c));
+        assert!(no().or(yes()).accepts(&c));
+        assert!(!no().or(no()).accepts(&c));
+        assert!((!no()).accepts(&c));
+    }
+
+    #[test]
+    fn generation_is_seeded() {
+        let puzzles = |seed| {
+            let mut generator = Generator::new(seed, Filter::has_solution());
+            (0..3)
+                .map(|_| generator.next_puzzle(1000).unwrap().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first = puzzles(7);
+        assert_eq!(first, puzzles(7));
+        // No position is returned twice.
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first[1], first[2]);
+
+        for puzzle in &first {
+            let board = parse_fen(&puzzle.fen).unwrap();
+            assert_eq!(board.controlling_player(), puzzle.to_move);
+            assert!(!puzzle.solutions.is_empty());
+        }
+    }
+
+    #[test]
+    fn puzzle_serialization() {
+        let c = candidate("r6k/8/8/8/8/8/8/K7 b 0 ---- - - - - -");
+        let puzzle = Puzzle::from_candidate(&c);
+        let json = serde_json::to_string(&puzzle).unwrap();
+        assert_eq!(serde_json::from_str::<Puzzle>(&json).unwrap(), puzzle);
+    }
+}
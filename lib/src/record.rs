@@ -0,0 +1,1039 @@
+//! A text format to archive and exchange complete games, modelled after PGN.
+//!
+//! ```text
+//! [Event "Club evening"]
+//! [Date "2021.03.14"]
+//! [White "Alice"]
+//! [Black "Bob"]
+//! [TimeControl "600+5"]
+//! [Result "*"]
+//!
+//! 1. e2>e4 {[%clk 0:09:58] A classic start.} e7>e5 2. Nb1>c3 *
+//! ```
+//!
+//! Tag pairs are written in the order they are stored, followed by the result
+//! and, when the game does not start from the initial position, by `SetUp` and
+//! `FEN` tags using the format of the `fen` module. Turns are written in the
+//! notation of the `notation` module. A comment in braces belongs to the turn
+//! before it and may contain clock annotations: `[%clk h:mm:ss]` is the time
+//! left after the turn and `[%emt h:mm:ss]` is the time the turn took.
+//! A file may contain many games, each one ends with its result marker.
+//!
+//! The reader has two modes. `ReadMode::Strict` only accepts records as the
+//! writer would produce them, with correct move numbers and matching results.
+//! `ReadMode::Lenient` is meant for records typed by hand. It skips malformed
+//! tags, move numbers, annotation symbols like `!?`, `$1` and variations, and
+//! it does not require a result marker at the end of a game.
+
+use crate::fen::{parse_fen, write_fen, FenError};
+use crate::notation::{parse_partial_turn, write_turn, NotationError};
+use crate::{DenseBoard, PacoAction, PacoBoard, PacoError, PlayerColor, VictoryState};
+use std::iter::Peekable;
+use std::time::Duration;
+
+/// The result of a game as it is written in the record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game is still running or the result is not known.
+    Unknown,
+}
+
+impl GameResult {
+    pub fn from_victory_state(state: VictoryState) -> Self {
+        match state {
+            VictoryState::Running => GameResult::Unknown,
+            VictoryState::PacoVictory(PlayerColor::White)
+            | VictoryState::TimeoutVictory(PlayerColor::White) => GameResult::WhiteWins,
+            VictoryState::PacoVictory(PlayerColor::Black)
+            | VictoryState::TimeoutVictory(PlayerColor::Black) => GameResult::BlackWins,
+            VictoryState::Draw(_) => GameResult::Draw,
+        }
+    }
+
+    fn token(self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unknown => "*",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unknown),
+            _ => None,
+        }
+    }
+}
+
+/// All actions of a single player between two changes of control, together
+/// with the annotations that belong to them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedTurn {
+    pub actions: Vec<PacoAction>,
+    pub comment: Option<String>,
+    /// Time left on the clock of the player after the turn.
+    pub clock: Option<Duration>,
+    /// Time the player used for the turn.
+    pub elapsed: Option<Duration>,
+}
+
+impl RecordedTurn {
+    pub fn new(actions: Vec<PacoAction>) -> Self {
+        RecordedTurn {
+            actions,
+            comment: None,
+            clock: None,
+            elapsed: None,
+        }
+    }
+}
+
+/// A complete game with its tag pairs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameRecord {
+    /// All tag pairs except `Result`, `SetUp` and `FEN`, which are derived
+    /// from the other fields.
+    pub headers: Vec<(String, String)>,
+    pub start: DenseBoard,
+    /// A comment that comes before the first turn.
+    pub comment: Option<String>,
+    pub turns: Vec<RecordedTurn>,
+    pub result: GameResult,
+}
+
+/// How forgiving the reader should be, see the module documentation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadMode {
+    Strict,
+    Lenient,
+}
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum RecordError {
+    #[error("Line {line}: The tag pair '{text}' is malformed.")]
+    MalformedTag { line: usize, text: String },
+    #[error("Line {line}: The starting position is invalid: {error}")]
+    StartingPosition { line: usize, error: FenError },
+    #[error("Line {line}: Expected move number {expected}, but found '{found}'.")]
+    MoveNumber {
+        line: usize,
+        expected: u32,
+        found: String,
+    },
+    #[error("Line {line}: The turn '{notation}' has no move number.")]
+    MissingMoveNumber { line: usize, notation: String },
+    #[error("Line {line}: Can not read the turn '{notation}': {error}")]
+    Turn {
+        line: usize,
+        notation: String,
+        error: NotationError,
+    },
+    #[error("Line {line}: The comment is never closed.")]
+    UnterminatedComment { line: usize },
+    #[error("Line {line}: The clock annotation '{text}' is malformed.")]
+    Clock { line: usize, text: String },
+    #[error("Line {line}: Unexpected '{token}'.")]
+    UnexpectedToken { line: usize, token: String },
+    #[error("Line {line}: The game has no result marker.")]
+    MissingResult { line: usize },
+    #[error("Line {line}: The result {found} does not match the result tag {tag}.")]
+    ResultMismatch {
+        line: usize,
+        tag: String,
+        found: String,
+    },
+    #[error("Line {line}: The result {found} does not match the final position.")]
+    ResultContradiction { line: usize, found: String },
+    #[error("Turn {index} of the game has no actions.")]
+    EmptyTurn { index: usize },
+    #[error("Turn {index} of the game can not be written: {error}")]
+    Write { index: usize, error: NotationError },
+}
+
+impl GameRecord {
+    /// An empty record of a game starting at the given position.
+    pub fn new(start: DenseBoard) -> Self {
+        GameRecord {
+            headers: Vec::new(),
+            start,
+            comment: None,
+            turns: Vec::new(),
+            result: GameResult::Unknown,
+        }
+    }
+
+    /// Splits an action history into turns and determines the result.
+    pub fn from_actions(start: DenseBoard, actions: &[PacoAction]) -> Result<Self, PacoError> {
+        let mut record = GameRecord::new(start.clone());
+        let mut board = start;
+        let mut turn_player = None;
+        for &action in actions {
+            if turn_player != Some(board.controlling_player()) {
+                turn_player = Some(board.controlling_player());
+                record.turns.push(RecordedTurn::new(Vec::new()));
+            }
+            board.execute(action)?;
+            if let Some(turn) = record.turns.last_mut() {
+                turn.actions.push(action);
+            }
+        }
+        record.result = GameResult::from_victory_state(board.victory_state());
+        Ok(record)
+    }
+
+    /// The value of a tag pair.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Sets the value of a tag pair, keeping its position if it already exists.
+    pub fn set_header(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+        match self.headers.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = value,
+            None => self.headers.push((name, value)),
+        }
+    }
+
+    /// All actions of the game in order.
+    pub fn actions(&self) -> Vec<PacoAction> {
+        self.turns
+            .iter()
+            .flat_map(|turn| turn.actions.iter().cloned())
+            .collect()
+    }
+
+    /// The position at the end of the record.
+    pub fn final_board(&self) -> Result<DenseBoard, PacoError> {
+        let mut board = self.start.clone();
+        for action in self.actions() {
+            board.execute(action)?;
+        }
+        Ok(board)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Writer //////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////
+
+/// Lines of move text are wrapped after this many characters where possible.
+const LINE_WIDTH: usize = 80;
+
+/// Writes several games, separated by an empty line.
+pub fn write_games(games: &[GameRecord]) -> Result<String, RecordError> {
+    let games: Result<Vec<String>, RecordError> = games.iter().map(write_game).collect();
+    Ok(games?.join("\n"))
+}
+
+/// Writes a single game. Every turn except the last one must be complete.
+pub fn write_game(game: &GameRecord) -> Result<String, RecordError> {
+    let mut result = String::new();
+    for (name, value) in &game.headers {
+        result.push_str(&format_tag(name, value));
+    }
+    result.push_str(&format_tag("Result", game.result.token()));
+    let fen = write_fen(&game.start);
+    if fen != write_fen(&DenseBoard::new()) {
+        result.push_str(&format_tag("SetUp", "1"));
+        result.push_str(&format_tag("FEN", &fen));
+    }
+    result.push('\n');
+
+    let mut tokens = Vec::new();
+    if let Some(comment) = &game.comment {
+        tokens.push(format_comment(comment, None, None));
+    }
+
+    let mut board = game.start.clone();
+    let mut move_number = 1;
+    let mut needs_number = true;
+    let mut previous_player = None;
+    for (index, turn) in game.turns.iter().enumerate() {
+        let player = board.controlling_player();
+        if previous_player == Some(player) {
+            // The previous turn did not hand over control to the other player.
+            return Err(RecordError::Write {
+                index: index - 1,
+                error: incomplete_reason(&board),
+            });
+        }
+        previous_player = Some(player);
+        if turn.actions.is_empty() {
+            return Err(RecordError::EmptyTurn { index });
+        }
+        if player == PlayerColor::White {
+            tokens.push(format!("{}.", move_number));
+        } else if needs_number {
+            tokens.push(format!("{}...", move_number));
+        }
+        let notation = write_turn(&board, &turn.actions)
+            .map_err(|error| RecordError::Write { index, error })?;
+        tokens.push(notation);
+        for &action in &turn.actions {
+            board.execute(action).map_err(|e| RecordError::Write {
+                index,
+                error: NotationError::IllegalAction(action, e),
+            })?;
+        }
+
+        needs_number = false;
+        if turn.comment.is_some() || turn.clock.is_some() || turn.elapsed.is_some() {
+            let comment = turn.comment.as_deref().unwrap_or("");
+            tokens.push(format_comment(comment, turn.clock, turn.elapsed));
+            needs_number = true;
+        }
+        if player == PlayerColor::Black {
+            move_number += 1;
+        }
+    }
+    tokens.push(game.result.token().to_owned());
+
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > LINE_WIDTH {
+            result.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            result.push(' ');
+            line_length += 1;
+        }
+        line_length += token.len();
+        result.push_str(&token);
+    }
+    result.push('\n');
+
+    Ok(result)
+}
+
+/// Explains why a turn did not hand over control to the other player.
+fn incomplete_reason(board: &DenseBoard) -> NotationError {
+    match board.promotion {
+        Some(position) => NotationError::PromotionMissing(position),
+        None => NotationError::ChainIncomplete,
+    }
+}
+
+fn format_tag(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", name, value)
+}
+
+fn format_comment(text: &str, clock: Option<Duration>, elapsed: Option<Duration>) -> String {
+    let mut parts = Vec::new();
+    if let Some(clock) = clock {
+        parts.push(format!("[%clk {}]", format_duration(clock)));
+    }
+    if let Some(elapsed) = elapsed {
+        parts.push(format!("[%emt {}]", format_duration(elapsed)));
+    }
+    // A comment can not contain its closing brace.
+    let text = text.replace('}', ")");
+    let text = text.trim();
+    if !text.is_empty() {
+        parts.push(text.to_owned());
+    }
+    format!("{{{}}}", parts.join(" "))
+}
+
+/// Formats a duration as h:mm:ss, with fractional seconds if required.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let mut result = format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    );
+    let millis = duration.subsec_millis();
+    if millis > 0 {
+        let fraction = format!(".{:03}", millis);
+        result.push_str(fraction.trim_end_matches('0'));
+    }
+    result
+}
+
+fn parse_duration(input: &str) -> Option<Duration> {
+    let mut parts = input.split(':').rev();
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+    let hours: u64 = parts.next().map_or(Some(0), |h| h.parse().ok())?;
+    if parts.next().is_some() || !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    let millis = (seconds * 1000.0).round() as u64;
+    Some(Duration::from_millis(
+        millis + 1000 * (60 * minutes + 3600 * hours),
+    ))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Reader //////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////
+
+/// Reads all games from the input.
+pub fn read_games(input: &str, mode: ReadMode) -> Result<Vec<GameRecord>, RecordError> {
+    let mut tokens = tokenize(input, mode)?.into_iter().peekable();
+    let mut games = Vec::new();
+    while tokens.peek().is_some() {
+        games.push(read_game(&mut tokens, mode)?);
+    }
+    Ok(games)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Tag(String, String),
+    MalformedTag(String),
+    Comment(String),
+    /// A move number, the flag is set for black ("12...").
+    MoveNumber(u32, bool, String),
+    Result(GameResult),
+    /// Numeric annotation glyphs like `$1`.
+    Nag(String),
+    VariationStart,
+    VariationEnd,
+    Turn(String),
+}
+
+fn tokenize(input: &str, mode: ReadMode) -> Result<Vec<(usize, Token)>, RecordError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '[' => {
+                let mut text = String::new();
+                let mut in_string = false;
+                let mut escaped = false;
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        in_string = !in_string;
+                    } else if c == ']' && !in_string {
+                        break;
+                    }
+                }
+                let token = match parse_tag(&text) {
+                    Some((name, value)) => Token::Tag(name, value),
+                    None => Token::MalformedTag(text),
+                };
+                tokens.push((line, token));
+            }
+            '{' => {
+                let start_line = line;
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => return Err(RecordError::UnterminatedComment { line: start_line }),
+                    }
+                }
+                tokens.push((start_line, Token::Comment(text)));
+            }
+            ';' => {
+                let mut text = String::new();
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push((line, Token::Comment(text)));
+            }
+            '(' => {
+                chars.next();
+                tokens.push((line, Token::VariationStart));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((line, Token::VariationEnd));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]{}();".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                for token in classify_word(&word, mode) {
+                    tokens.push((line, token));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a tag pair like `[White "Alice"]`.
+fn parse_tag(text: &str) -> Option<(String, String)> {
+    let inner = text.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let name_end = inner.find(char::is_whitespace)?;
+    let name = &inner[..name_end];
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let quoted = inner[name_end..].trim();
+    let quoted = quoted.strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?),
+            '"' => return None,
+            c => value.push(c),
+        }
+    }
+    Some((name.to_owned(), value))
+}
+
+/// Splits a word of move text into tokens. Move numbers may be written
+/// directly in front of the turn, like `1.e2>e4`.
+fn classify_word(word: &str, mode: ReadMode) -> Vec<Token> {
+    if let Some(result) = GameResult::from_token(word) {
+        return vec![Token::Result(result)];
+    }
+    if word.starts_with('$') {
+        return vec![Token::Nag(word.to_owned())];
+    }
+
+    let digits = word.chars().take_while(|c| c.is_ascii_digit()).count();
+    let dots = word[digits..].chars().take_while(|&c| c == '.').count();
+    if digits > 0 && dots > 0 {
+        let number = word[..digits].parse().unwrap_or(0);
+        let mut tokens = vec![Token::MoveNumber(
+            number,
+            dots >= 3,
+            word[..digits + dots].to_owned(),
+        )];
+        let rest = &word[digits + dots..];
+        if !rest.is_empty() {
+            tokens.extend(classify_word(rest, mode));
+        }
+        return tokens;
+    }
+
+    let turn = match mode {
+        ReadMode::Strict => word,
+        ReadMode::Lenient => word.trim_end_matches(|c| "!?+#".contains(c)),
+    };
+    vec![Token::Turn(turn.to_owned())]
+}
+
+/// Annotations extracted from a comment.
+struct Annotations {
+    text: String,
+    clock: Option<Duration>,
+    elapsed: Option<Duration>,
+}
+
+/// Extracts `[%clk ..]` and `[%emt ..]` from a comment. Other commands stay
+/// part of the comment text.
+fn parse_annotations(
+    line: usize,
+    comment: &str,
+    mode: ReadMode,
+) -> Result<Annotations, RecordError> {
+    let mut result = Annotations {
+        text: String::new(),
+        clock: None,
+        elapsed: None,
+    };
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        result.text.push_str(&rest[..start]);
+        let command = &rest[start..];
+        let end = match command.find(']') {
+            Some(end) => end,
+            None => {
+                rest = command;
+                break;
+            }
+        };
+        let inner = &command[2..end];
+        let mut words = inner.split_whitespace();
+        let target = match words.next() {
+            Some("clk") => Some(&mut result.clock),
+            Some("emt") => Some(&mut result.elapsed),
+            _ => None,
+        };
+        match target {
+            Some(target) => match words.next().and_then(parse_duration) {
+                Some(duration) if words.next().is_none() => *target = Some(duration),
+                _ if mode == ReadMode::Lenient => result.text.push_str(&command[..=end]),
+                _ => {
+                    return Err(RecordError::Clock {
+                        line,
+                        text: command[..=end].to_owned(),
+                    })
+                }
+            },
+            None => result.text.push_str(&command[..=end]),
+        }
+        rest = &command[end + 1..];
+    }
+    result.text.push_str(rest);
+    result.text = result.text.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok(result)
+}
+
+/// Appends a comment to an optional comment.
+fn append_comment(target: &mut Option<String>, text: String) {
+    if text.is_empty() {
+        return;
+    }
+    *target = Some(match target.take() {
+        Some(existing) => format!("{} {}", existing, text),
+        None => text,
+    });
+}
+
+fn read_game<I>(tokens: &mut Peekable<I>, mode: ReadMode) -> Result<GameRecord, RecordError>
+where
+    I: Iterator<Item = (usize, Token)>,
+{
+    let mut game = GameRecord::new(DenseBoard::new());
+    let mut result_tag: Option<String> = None;
+    let mut line = 1;
+
+    // Tag pairs
+    while let Some((tag_line, token)) = tokens.peek().cloned() {
+        line = tag_line;
+        match token {
+            Token::Tag(name, value) => match name.as_str() {
+                "Result" => result_tag = Some(value),
+                "SetUp" => {}
+                "FEN" => {
+                    game.start = parse_fen(&value)
+                        .map_err(|error| RecordError::StartingPosition { line, error })?
+                }
+                _ => game.headers.push((name, value)),
+            },
+            Token::MalformedTag(text) => {
+                if mode == ReadMode::Strict {
+                    return Err(RecordError::MalformedTag { line, text });
+                }
+            }
+            _ => break,
+        }
+        tokens.next();
+    }
+
+    // Move text
+    let mut board = game.start.clone();
+    let mut move_number = 1;
+    let mut numbered = false;
+    let mut incomplete: Option<(usize, String)> = None;
+    let mut result = None;
+
+    while let Some((token_line, token)) = tokens.peek().cloned() {
+        line = token_line;
+        if let Token::Tag(_, _) | Token::MalformedTag(_) = token {
+            // The next game starts without a result marker for this one.
+            break;
+        }
+        tokens.next();
+        match token {
+            Token::Tag(_, _) | Token::MalformedTag(_) => unreachable!(),
+            Token::Comment(text) => {
+                let annotations = parse_annotations(line, &text, mode)?;
+                match game.turns.last_mut() {
+                    Some(turn) => {
+                        append_comment(&mut turn.comment, annotations.text);
+                        turn.clock = annotations.clock.or(turn.clock);
+                        turn.elapsed = annotations.elapsed.or(turn.elapsed);
+                    }
+                    None => append_comment(&mut game.comment, annotations.text),
+                }
+            }
+            Token::MoveNumber(number, black, text) => {
+                let black_to_move = board.controlling_player() == PlayerColor::Black;
+                if mode == ReadMode::Strict && (number != move_number || black != black_to_move) {
+                    return Err(RecordError::MoveNumber {
+                        line,
+                        expected: move_number,
+                        found: text,
+                    });
+                }
+                numbered = true;
+            }
+            Token::Turn(notation) => {
+                if let Some((line, notation)) = incomplete.take() {
+                    return Err(RecordError::Turn {
+                        line,
+                        notation,
+                        error: incomplete_reason(&board),
+                    });
+                }
+                let player = board.controlling_player();
+                if mode == ReadMode::Strict && player == PlayerColor::White && !numbered {
+                    return Err(RecordError::MissingMoveNumber { line, notation });
+                }
+                let actions =
+                    parse_partial_turn(&board, &notation).map_err(|error| RecordError::Turn {
+                        line,
+                        notation: notation.clone(),
+                        error,
+                    })?;
+                for &action in &actions {
+                    board
+                        .execute_trusted(action)
+                        .map_err(|e| RecordError::Turn {
+                            line,
+                            notation: notation.clone(),
+                            error: NotationError::IllegalAction(action, e),
+                        })?;
+                }
+                if board.controlling_player() == player && !board.victory_state().is_over() {
+                    incomplete = Some((line, notation));
+                }
+                game.turns.push(RecordedTurn::new(actions));
+                numbered = false;
+                if player == PlayerColor::Black {
+                    move_number += 1;
+                }
+            }
+            Token::Result(found) => {
+                result = Some(found);
+                break;
+            }
+            Token::Nag(text) => {
+                if mode == ReadMode::Strict {
+                    return Err(RecordError::UnexpectedToken { line, token: text });
+                }
+            }
+            Token::VariationStart => {
+                if mode == ReadMode::Strict {
+                    return Err(RecordError::UnexpectedToken {
+                        line,
+                        token: "(".to_owned(),
+                    });
+                }
+                skip_variation(tokens);
+            }
+            Token::VariationEnd => {
+                if mode == ReadMode::Strict {
+                    return Err(RecordError::UnexpectedToken {
+                        line,
+                        token: ")".to_owned(),
+                    });
+                }
+            }
+        }
+    }
+
+    game.result = match (result, mode) {
+        (Some(found), ReadMode::Strict) => {
+            if let Some(tag) = result_tag {
+                if tag != found.token() {
+                    return Err(RecordError::ResultMismatch {
+                        line,
+                        tag,
+                        found: found.token().to_owned(),
+                    });
+                }
+            }
+            let decided = GameResult::from_victory_state(board.victory_state());
+            if decided != GameResult::Unknown && decided != found {
+                return Err(RecordError::ResultContradiction {
+                    line,
+                    found: found.token().to_owned(),
+                });
+            }
+            if let (Some((line, notation)), false) = (&incomplete, found == GameResult::Unknown) {
+                return Err(RecordError::Turn {
+                    line: *line,
+                    notation: notation.clone(),
+                    error: incomplete_reason(&board),
+                });
+            }
+            found
+        }
+        (None, ReadMode::Strict) => return Err(RecordError::MissingResult { line }),
+        (Some(found), ReadMode::Lenient) => found,
+        (None, ReadMode::Lenient) => result_tag
+            .as_deref()
+            .and_then(GameResult::from_token)
+            .unwrap_or(GameResult::Unknown),
+    };
+
+    Ok(game)
+}
+
+/// Skips the rest of a variation, including nested variations.
+fn skip_variation<I>(tokens: &mut Peekable<I>)
+where
+    I: Iterator<Item = (usize, Token)>,
+{
+    let mut depth = 1;
+    while depth > 0 {
+        match tokens.next() {
+            Some((_, Token::VariationStart)) => depth += 1,
+            Some((_, Token::VariationEnd)) => depth -= 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::BoardPosition;
+    use quickcheck::{Arbitrary, Gen};
+    use rand::Rng;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn example_game() -> GameRecord {
+        use PacoAction::*;
+        let actions = [
+            Lift(pos("e2")),
+            Place(pos("e4")),
+            Lift(pos("d7")),
+            Place(pos("d5")),
+            Lift(pos("e4")),
+            Place(pos("d5")),
+        ];
+        let mut game = GameRecord::from_actions(DenseBoard::new(), &actions).unwrap();
+        game.set_header("Event", "Club evening");
+        game.set_header("White", "Alice \"The Wall\"");
+        game.set_header("Black", "Bob");
+        game.turns[0].clock = Some(Duration::from_secs(598));
+        game.turns[1].elapsed = Some(Duration::from_millis(2500));
+        game.turns[1].comment = Some("Scandinavian.".to_owned());
+        game
+    }
+
+    const EXAMPLE_GAME: &str = r#"[Event "Club evening"]
+[White "Alice \"The Wall\""]
+[Black "Bob"]
+[Result "*"]
+
+1. e2>e4 {[%clk 0:09:58]} 1... d7>d5 {[%emt 0:00:02.5] Scandinavian.} 2. e4>d5 *
+"#;
+
+    #[test]
+    fn write_example() {
+        assert_eq!(write_game(&example_game()).unwrap(), EXAMPLE_GAME);
+    }
+
+    #[test]
+    fn read_example() {
+        let games = read_games(EXAMPLE_GAME, ReadMode::Strict).unwrap();
+        assert_eq!(games, vec![example_game()]);
+        assert_eq!(games[0].header("White"), Some("Alice \"The Wall\""));
+    }
+
+    #[test]
+    fn multiple_games() {
+        let mut second = GameRecord::from_actions(
+            DenseBoard::new(),
+            &[PacoAction::Lift(pos("g1")), PacoAction::Place(pos("f3"))],
+        )
+        .unwrap();
+        second.comment = Some("A short one.".to_owned());
+        second.result = GameResult::Draw;
+        let games = vec![example_game(), second];
+
+        let text = write_games(&games).unwrap();
+        assert_eq!(read_games(&text, ReadMode::Strict).unwrap(), games);
+    }
+
+    #[test]
+    fn starting_position() {
+        let start = parse_fen("4k3/8/8/8/8/8/8/R3K3 b 0 a--- - -").unwrap();
+        let mut game = GameRecord::from_actions(
+            start,
+            &[PacoAction::Lift(pos("e8")), PacoAction::Place(pos("d8"))],
+        )
+        .unwrap();
+        game.set_header("Event", "Endgame study");
+
+        let text = write_game(&game).unwrap();
+        let fen_tags = format!("[SetUp \"1\"]\n[FEN \"{}\"]", write_fen(&game.start));
+        assert!(text.contains(&fen_tags));
+        assert!(text.contains("1... Ke8>d8 *"));
+        assert_eq!(read_games(&text, ReadMode::Strict).unwrap(), vec![game]);
+    }
+
+    #[test]
+    fn lenient_reading() {
+        let input = r#"[Event "Hand written"]
+[Broken tag
+1.e4! e5?! ; Both sides play in the center.
+Nf3 (2. Nc3 Nc6) $1 Nb8>c6
+"#;
+        assert_eq!(
+            read_games(input, ReadMode::Strict),
+            Err(RecordError::MalformedTag {
+                line: 2,
+                text: "[Broken tag".to_owned()
+            })
+        );
+
+        let games = read_games(input, ReadMode::Lenient).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.header("Event"), Some("Hand written"));
+        assert_eq!(game.turns.len(), 4);
+        assert_eq!(
+            game.turns[1].comment.as_deref(),
+            Some("Both sides play in the center.")
+        );
+        assert_eq!(game.result, GameResult::Unknown);
+        assert_eq!(
+            write_game(game).unwrap(),
+            r#"[Event "Hand written"]
+[Result "*"]
+
+1. e2>e4 e7>e5 {Both sides play in the center.} 2. Ng1>f3 Nb8>c6 *
+"#
+        );
+    }
+
+    #[test]
+    fn strict_errors() {
+        assert_eq!(
+            read_games("1. e2>e4 e7>e5 3. Ng1>f3 *", ReadMode::Strict),
+            Err(RecordError::MoveNumber {
+                line: 1,
+                expected: 2,
+                found: "3.".to_owned()
+            })
+        );
+        assert_eq!(
+            read_games("1. e2>e4 e7>e5 Ng1>f3 *", ReadMode::Strict),
+            Err(RecordError::MissingMoveNumber {
+                line: 1,
+                notation: "Ng1>f3".to_owned()
+            })
+        );
+        assert_eq!(
+            read_games("1. e2>e4 e7>e5", ReadMode::Strict),
+            Err(RecordError::MissingResult { line: 1 })
+        );
+        assert_eq!(
+            read_games("[Result \"1-0\"]\n\n1. e2>e4 *", ReadMode::Strict),
+            Err(RecordError::ResultMismatch {
+                line: 3,
+                tag: "1-0".to_owned(),
+                found: "*".to_owned()
+            })
+        );
+        assert_eq!(
+            read_games("1. e2>e4 {never closed", ReadMode::Strict),
+            Err(RecordError::UnterminatedComment { line: 1 })
+        );
+        assert_eq!(
+            read_games("1. e2>e4 {[%clk 9 minutes]} *", ReadMode::Strict),
+            Err(RecordError::Clock {
+                line: 1,
+                text: "[%clk 9 minutes]".to_owned()
+            })
+        );
+        assert_eq!(
+            read_games("1. e2>e5 *", ReadMode::Lenient),
+            Err(RecordError::Turn {
+                line: 1,
+                notation: "e2>e5".to_owned(),
+                error: NotationError::IllegalPlace {
+                    piece: crate::PieceType::Pawn,
+                    from: pos("e2"),
+                    target: pos("e5")
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(format_duration(Duration::from_millis(1200)), "0:00:01.2");
+        assert_eq!(parse_duration("1:02:05"), Some(Duration::from_secs(3725)));
+        assert_eq!(
+            parse_duration("0:00:01.2"),
+            Some(Duration::from_millis(1200))
+        );
+        assert_eq!(parse_duration("1:x:05"), None);
+    }
+
+    /// A random game that may end at any point, even within a turn.
+    #[derive(Clone, Debug)]
+    struct RandomGame(GameRecord);
+
+    impl Arbitrary for RandomGame {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            let mut rng = testing::rng(g);
+            let mut board = DenseBoard::new();
+            let mut actions = Vec::new();
+            for _ in 0..rng.gen_range(0, 120) {
+                let legal = board.actions().unwrap();
+                if legal.is_empty() {
+                    break;
+                }
+                let action = legal[rng.gen_range(0, legal.len())];
+                board.execute_trusted(action).unwrap();
+                actions.push(action);
+            }
+            let mut game = GameRecord::from_actions(DenseBoard::new(), &actions).unwrap();
+            for turn in &mut game.turns {
+                if rng.gen_bool(0.2) {
+                    turn.clock = Some(Duration::from_millis(rng.gen_range(0, 1_000_000)));
+                }
+            }
+            RandomGame(game)
+        }
+    }
+
+    #[quickcheck]
+    fn record_roundtrip(game: RandomGame) -> bool {
+        let text = write_game(&game.0).unwrap();
+        read_games(&text, ReadMode::Strict).unwrap() == vec![game.0]
+    }
+}
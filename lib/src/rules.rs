@@ -0,0 +1,216 @@
+//! Rule choices that differ between the published versions of Paco Ŝako.
+//!
+//! A `RuleSet` is stored in every `DenseBoard` and respected by all move
+//! generation. `RuleSet::default()` are the rules this library always
+//! implemented. Other presets allow tournaments under an older rule version,
+//! and single rules can be changed to experiment with new ones.
+//!
+//! Kings are exempt from chains in every version: A king in a union ends the
+//! game, so there is nothing to configure.
+
+use crate::{PieceType, DEFAULT_NO_PROGRESS_LIMIT};
+use serde::{Deserialize, Serialize};
+
+/// The piece types a pawn may be promoted to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromotionPieces {
+    pub bishop: bool,
+    pub rook: bool,
+    pub knight: bool,
+    pub queen: bool,
+}
+
+impl PromotionPieces {
+    pub fn all() -> Self {
+        PromotionPieces {
+            bishop: true,
+            rook: true,
+            knight: true,
+            queen: true,
+        }
+    }
+
+    pub fn allows(self, piece: PieceType) -> bool {
+        match piece {
+            PieceType::Bishop => self.bishop,
+            PieceType::Rook => self.rook,
+            PieceType::Knight => self.knight,
+            PieceType::Queen => self.queen,
+            PieceType::Pawn | PieceType::King => false,
+        }
+    }
+
+    /// The allowed piece types in the order `actions` lists them.
+    pub fn pieces(self) -> Vec<PieceType> {
+        use PieceType::*;
+        [Bishop, Rook, Knight, Queen]
+            .iter()
+            .copied()
+            .filter(|&piece| self.allows(piece))
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleSet {
+    /// A pawn that was set free by a chain may still unite en passant.
+    pub en_passant_in_chains: bool,
+    /// The king may castle over squares that the opponent threatens, just like
+    /// it may move onto threatened squares.
+    pub castle_through_threats: bool,
+    pub promotion_pieces: PromotionPieces,
+    /// The game is drawn after this many half-moves without progress.
+    pub no_progress_limit: u16,
+}
+
+impl RuleSet {
+    /// The rules as implemented by this library and played on the website.
+    pub fn current() -> Self {
+        RuleSet {
+            en_passant_in_chains: true,
+            castle_through_threats: false,
+            promotion_pieces: PromotionPieces::all(),
+            no_progress_limit: DEFAULT_NO_PROGRESS_LIMIT,
+        }
+    }
+
+    /// The 2017 rule booklet. En passant is only possible for a pawn lifted at
+    /// the start of the turn.
+    pub fn rules_2017() -> Self {
+        RuleSet {
+            en_passant_in_chains: false,
+            ..RuleSet::current()
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet::current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::{BoardPosition, DenseBoard, PacoAction, PacoBoard, PacoError};
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn board_with_rules(fen: &str, rules: RuleSet) -> DenseBoard {
+        let mut board = parse_fen(fen).unwrap();
+        board.set_rules(rules);
+        board
+    }
+
+    /// The rook on e8 takes over the union on e4 and sets the black pawn free.
+    /// The pawn on c4 was not part of a chain.
+    const EN_PASSANT: &str = "4r2k/8/8/8/2pPd3/8/8/4K3 b 0 ---- d3 - - - -";
+
+    #[test]
+    fn en_passant_in_chains() -> Result<(), PacoError> {
+        let mut board = board_with_rules(EN_PASSANT, RuleSet::current());
+        board.execute(PacoAction::Lift(pos("e8")))?;
+        board.execute(PacoAction::Place(pos("e4")))?;
+        assert!(board.actions()?.contains(&PacoAction::Place(pos("d3"))));
+        assert!(board.is_legal(PacoAction::Place(pos("d3")))?);
+        Ok(())
+    }
+
+    #[test]
+    fn no_en_passant_in_chains_2017() -> Result<(), PacoError> {
+        let start = board_with_rules(EN_PASSANT, RuleSet::rules_2017());
+
+        let mut board = start.clone();
+        board.execute(PacoAction::Lift(pos("e8")))?;
+        board.execute(PacoAction::Place(pos("e4")))?;
+        assert!(!board.actions()?.contains(&PacoAction::Place(pos("d3"))));
+        assert!(!board.is_legal(PacoAction::Place(pos("d3")))?);
+
+        // A pawn lifted at the start of the turn may still unite en passant.
+        let mut board = start;
+        board.execute(PacoAction::Lift(pos("c4")))?;
+        board.execute(PacoAction::Place(pos("d3")))?;
+        assert_eq!(board.white[pos("d3").0 as usize], Some(PieceType::Pawn));
+        Ok(())
+    }
+
+    /// The black rook on f8 threatens f1.
+    const CASTLING: &str = "4kr2/8/8/8/8/8/8/R3K2R w 0 ah-- - - - - -";
+
+    #[test]
+    fn castling_through_threats() -> Result<(), PacoError> {
+        let mut board = board_with_rules(CASTLING, RuleSet::current());
+        board.execute(PacoAction::Lift(pos("e1")))?;
+        let actions = board.actions()?;
+        assert!(actions.contains(&PacoAction::Place(pos("c1"))));
+        assert!(!actions.contains(&PacoAction::Place(pos("g1"))));
+
+        let rules = RuleSet {
+            castle_through_threats: true,
+            ..RuleSet::current()
+        };
+        let mut board = board_with_rules(CASTLING, rules);
+        board.execute(PacoAction::Lift(pos("e1")))?;
+        board.execute(PacoAction::Place(pos("g1")))?;
+        assert_eq!(board.white[pos("g1").0 as usize], Some(PieceType::King));
+        assert_eq!(board.white[pos("f1").0 as usize], Some(PieceType::Rook));
+        Ok(())
+    }
+
+    #[test]
+    fn promotion_pieces() -> Result<(), PacoError> {
+        let rules = RuleSet {
+            promotion_pieces: PromotionPieces {
+                bishop: false,
+                rook: false,
+                knight: false,
+                queen: true,
+            },
+            ..RuleSet::current()
+        };
+        let mut board = board_with_rules("8/3P4/8/8/8/8/8/K6k w 0 ---- - - - - -", rules);
+        board.execute(PacoAction::Lift(pos("d7")))?;
+        board.execute(PacoAction::Place(pos("d8")))?;
+        assert_eq!(
+            board.actions()?,
+            vec![PacoAction::Promote(PieceType::Queen)]
+        );
+        assert_eq!(
+            board
+                .clone()
+                .execute(PacoAction::Promote(PieceType::Knight)),
+            Err(PacoError::ActionNotLegal)
+        );
+        assert_eq!(
+            board.execute_trusted(PacoAction::Promote(PieceType::Rook)),
+            Err(PacoError::PromoteNotAllowed(PieceType::Rook))
+        );
+        board.execute(PacoAction::Promote(PieceType::Queen))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rules_are_kept() -> Result<(), PacoError> {
+        let rules = RuleSet {
+            no_progress_limit: 10,
+            ..RuleSet::rules_2017()
+        };
+        let mut board = DenseBoard::with_rules(rules);
+        board.execute(PacoAction::Lift(pos("e2")))?;
+        board.execute(PacoAction::Place(pos("e4")))?;
+        assert_eq!(board.clone().rules(), rules);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_partial_rules() {
+        let rules: RuleSet = serde_json::from_str(r#"{"en_passant_in_chains": false}"#).unwrap();
+        assert_eq!(rules, RuleSet::rules_2017());
+    }
+}
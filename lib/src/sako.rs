@@ -0,0 +1,116 @@
+//! Ŝako is the Paco Ŝako version of check: The opponent can unite with your
+//! king on their next turn. Unlike chess, you are not forced to react, but
+//! ignoring a Ŝako loses the game.
+//!
+//! The queries work on the full board state, so castling rights, en passant
+//! and a pending promotion are taken into account.
+
+use crate::{
+    legal_moves, DeadEnds, DenseBoard, PacoAction, PacoBoard, PacoError, PacoMove, PlayerColor,
+    VictoryState,
+};
+
+impl DenseBoard {
+    /// Lists all turns of the opponent of `color` that unite with the king of
+    /// `color`. An empty list means the king is safe.
+    ///
+    /// When the opponent is in control of the board, this is the rest of
+    /// their current turn. Otherwise it is their turn after `color` passes,
+    /// which only works when `color` has not started a turn yet.
+    pub fn sako_threats(&self, color: PlayerColor) -> Result<Vec<Vec<PacoAction>>, PacoError> {
+        let opponent = color.other();
+        let moves = if self.controlling_player() == opponent {
+            legal_moves(self, DeadEnds::Exclude)?
+        } else {
+            if !self.is_settled() || self.promotion.is_some() {
+                return Err(PacoError::UnfinishedTurn);
+            }
+            let mut passed = self.clone();
+            passed.current_player = opponent;
+            passed.en_passant = None;
+            legal_moves(&passed, DeadEnds::Exclude)?
+        };
+
+        Ok(moves
+            .into_iter()
+            .filter(|m| m.board.victory_state() == VictoryState::PacoVictory(opponent))
+            .map(|m| m.actions)
+            .collect())
+    }
+
+    /// Lists all turns of the controlling player after which the opponent can
+    /// not unite with their king. This includes turns that win right away.
+    /// If the player is threatened and the list is empty, the game is lost.
+    pub fn defensive_turns(&self) -> Result<Vec<PacoMove<DenseBoard>>, PacoError> {
+        let player = self.controlling_player();
+        let mut result = Vec::new();
+        for m in legal_moves(self, DeadEnds::Exclude)? {
+            if m.board.victory_state().is_over() || m.board.sako_threats(player)?.is_empty() {
+                result.push(m);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::BoardPosition;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    #[test]
+    fn initial_position_is_safe() {
+        let board = DenseBoard::new();
+        assert!(board.sako_threats(PlayerColor::White).unwrap().is_empty());
+        assert!(board.sako_threats(PlayerColor::Black).unwrap().is_empty());
+        assert_eq!(board.defensive_turns().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn rook_threatens_king() {
+        let board = parse_fen("r6k/8/8/8/8/8/8/K7 w 0 ---- - - - - -").unwrap();
+        let threats = board.sako_threats(PlayerColor::White).unwrap();
+        assert_eq!(
+            threats,
+            vec![vec![
+                PacoAction::Lift(pos("a8")),
+                PacoAction::Place(pos("a1"))
+            ]]
+        );
+        assert!(board.sako_threats(PlayerColor::Black).unwrap().is_empty());
+
+        // The king has to leave the a file.
+        let defenses = board.defensive_turns().unwrap();
+        let mut targets: Vec<_> = defenses.iter().map(|m| m.actions[1]).collect();
+        targets.sort_by_key(|a| a.position().map(|p| p.0));
+        assert_eq!(
+            targets,
+            vec![PacoAction::Place(pos("b1")), PacoAction::Place(pos("b2"))]
+        );
+    }
+
+    #[test]
+    fn forced_loss() {
+        let board = parse_fen("rr5k/8/8/8/8/8/8/K7 w 0 ---- - - - - -").unwrap();
+        assert_eq!(board.sako_threats(PlayerColor::White).unwrap().len(), 1);
+        assert!(board.defensive_turns().unwrap().is_empty());
+    }
+
+    #[test]
+    fn threats_during_a_turn() {
+        let mut board = parse_fen("r6k/8/8/8/8/8/8/K7 w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("a1"))).unwrap();
+        assert_eq!(
+            board.sako_threats(PlayerColor::White),
+            Err(PacoError::UnfinishedTurn)
+        );
+        // White is in control, so this looks at the rest of their turn.
+        assert!(board.sako_threats(PlayerColor::Black).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+//! Versioned serde representation of the full `DenseBoard` state. Snapshots
+//! can be cached, sent to bots and clients, and loaded again without
+//! replaying the game from the start.
+//!
+//! A snapshot is tagged with its version:
+//!
+//! ```text
+//! {"version": "3", "white": [...], "black": [...], "current_player": "White", ...}
+//! ```
+//!
+//! When the board gets new state, add a new version next to the old ones and
+//! convert the old versions into the board, so stored snapshots keep loading.
+
+use crate::{
+    BoardPosition, Castling, DenseBoard, DrawState, Hand, PieceType, PlayerColor, Progress,
+    RuleSet, StartFiles, VictoryState,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("The board needs 64 squares for each color, but it has {0}.")]
+    SquareCount(usize),
+}
+
+/// All versions of the snapshot format. The board is always written with the
+/// latest version.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "version")]
+pub(crate) enum Snapshot {
+    #[serde(rename = "1")]
+    V1(SnapshotV1),
+    #[serde(rename = "2")]
+    V2(SnapshotV2),
+    /// The same fields as version 2. The history holds the specified
+    /// `DenseBoard::position_hash`, version 2 used the hasher of the standard
+    /// library, which may change with every Rust release.
+    #[serde(rename = "3")]
+    V3(SnapshotV2),
+}
+
+/// The first version. Both players had their king and rooks on the same files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SnapshotV1 {
+    white: Vec<Option<PieceType>>,
+    black: Vec<Option<PieceType>>,
+    current_player: PlayerColor,
+    hand: Hand,
+    en_passant: Option<(BoardPosition, PlayerColor)>,
+    promotion: Option<BoardPosition>,
+    castling: CastlingV1,
+    victory_state: VictoryState,
+    history: Vec<u64>,
+    no_progress_half_moves: u16,
+    turn_progress: Progress,
+    rules: RuleSet,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CastlingV1 {
+    white_queen_side: bool,
+    white_king_side: bool,
+    black_queen_side: bool,
+    black_king_side: bool,
+    king_file: u8,
+    queen_rook_file: u8,
+    king_rook_file: u8,
+}
+
+/// Each player has their own castling files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SnapshotV2 {
+    white: Vec<Option<PieceType>>,
+    black: Vec<Option<PieceType>>,
+    current_player: PlayerColor,
+    hand: Hand,
+    en_passant: Option<(BoardPosition, PlayerColor)>,
+    promotion: Option<BoardPosition>,
+    castling: Castling,
+    victory_state: VictoryState,
+    /// Position hashes to detect repetitions, see `DenseBoard::position_hash`.
+    history: Vec<u64>,
+    no_progress_half_moves: u16,
+    turn_progress: Progress,
+    rules: RuleSet,
+}
+
+impl From<DenseBoard> for Snapshot {
+    fn from(board: DenseBoard) -> Self {
+        Snapshot::V3(SnapshotV2 {
+            white: board.white,
+            black: board.black,
+            current_player: board.current_player,
+            hand: board.lifted_piece,
+            en_passant: board.en_passant,
+            promotion: board.promotion,
+            castling: board.castling,
+            victory_state: board.victory_state,
+            history: board.draw_state.history,
+            no_progress_half_moves: board.draw_state.no_progress_half_moves,
+            turn_progress: board.draw_state.turn_progress,
+            rules: board.rules,
+        })
+    }
+}
+
+impl From<SnapshotV1> for SnapshotV2 {
+    fn from(v1: SnapshotV1) -> Self {
+        let old = v1.castling;
+        let files = StartFiles {
+            king: old.king_file,
+            queen_rook: old.queen_rook_file,
+            king_rook: old.king_rook_file,
+        };
+        let mut castling = Castling::with_files(files);
+        castling.white_queen_side = old.white_queen_side;
+        castling.white_king_side = old.white_king_side;
+        castling.black_queen_side = old.black_queen_side;
+        castling.black_king_side = old.black_king_side;
+        SnapshotV2 {
+            white: v1.white,
+            black: v1.black,
+            current_player: v1.current_player,
+            hand: v1.hand,
+            en_passant: v1.en_passant,
+            promotion: v1.promotion,
+            castling,
+            victory_state: v1.victory_state,
+            // The hashes covered the old castling layout and never match a
+            // position again, so the repetition history starts over.
+            history: Vec::new(),
+            no_progress_half_moves: v1.no_progress_half_moves,
+            turn_progress: v1.turn_progress,
+            rules: v1.rules,
+        }
+    }
+}
+
+impl TryFrom<Snapshot> for DenseBoard {
+    type Error = SnapshotError;
+
+    fn try_from(snapshot: Snapshot) -> Result<Self, Self::Error> {
+        match snapshot {
+            Snapshot::V1(v1) => DenseBoard::try_from(SnapshotV2::from(v1)),
+            Snapshot::V2(v2) => DenseBoard::try_from(SnapshotV2 {
+                // These hashes can't be compared with the ones we compute.
+                history: Vec::new(),
+                ..v2
+            }),
+            Snapshot::V3(v3) => DenseBoard::try_from(v3),
+        }
+    }
+}
+
+impl TryFrom<SnapshotV2> for DenseBoard {
+    type Error = SnapshotError;
+
+    fn try_from(v2: SnapshotV2) -> Result<Self, Self::Error> {
+        for squares in &[&v2.white, &v2.black] {
+            if squares.len() != 64 {
+                return Err(SnapshotError::SquareCount(squares.len()));
+            }
+        }
+        Ok(DenseBoard {
+            white: v2.white,
+            black: v2.black,
+            current_player: v2.current_player,
+            lifted_piece: v2.hand,
+            en_passant: v2.en_passant,
+            promotion: v2.promotion,
+            castling: v2.castling,
+            victory_state: v2.victory_state,
+            draw_state: DrawState {
+                history: v2.history,
+                no_progress_half_moves: v2.no_progress_half_moves,
+                turn_progress: v2.turn_progress,
+            },
+            rules: v2.rules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::testing::identical;
+    use crate::{PacoAction, PacoBoard};
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    fn round_trip(board: &DenseBoard) -> DenseBoard {
+        let json = serde_json::to_string(board).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn start_position() {
+        let board = DenseBoard::new();
+        assert!(identical(&round_trip(&board), &board));
+
+        let json = serde_json::to_value(&board).unwrap();
+        assert_eq!(json["version"], "3");
+        assert_eq!(json["current_player"], "White");
+    }
+
+    #[test]
+    fn board_with_chain_and_rules() {
+        let fen = "4k3/8/8/8/8/8/8/RK5R w 0 ah-- - - - - -";
+        let mut board = parse_fen(fen).unwrap();
+        board.set_rules(RuleSet::rules_2017());
+        board
+            .execute(PacoAction::Lift(BoardPosition::new(1, 0)))
+            .unwrap();
+        assert!(identical(&round_trip(&board), &board));
+    }
+
+    /// A snapshot written by the first version. This must keep loading.
+    #[test]
+    fn load_version_1() {
+        let squares = |pieces: &[(usize, &str)]| {
+            let mut squares = vec!["null".to_owned(); 64];
+            for (index, piece) in pieces {
+                squares[*index] = format!("\"{}\"", piece);
+            }
+            squares.join(",")
+        };
+        let json = format!(
+            r#"{{"version": "1",
+                "white": [{}], "black": [{}],
+                "current_player": "White", "hand": "Empty",
+                "en_passant": null, "promotion": null,
+                "castling": {{"white_queen_side": true, "white_king_side": false,
+                    "black_queen_side": false, "black_king_side": false,
+                    "king_file": 4, "queen_rook_file": 0, "king_rook_file": 7}},
+                "victory_state": "Running",
+                "history": [], "no_progress_half_moves": 3, "turn_progress": "None",
+                "rules": {{"en_passant_in_chains": true, "castle_through_threats": false,
+                    "promotion_pieces": {{"bishop": true, "rook": true, "knight": true,
+                        "queen": true}},
+                    "no_progress_limit": 100}}}}"#,
+            squares(&[(0, "Rook"), (4, "King"), (12, "Pawn")]),
+            squares(&[(60, "King")]),
+        );
+        let board: DenseBoard = serde_json::from_str(&json).unwrap();
+        let expected = parse_fen("4k3/8/8/8/8/8/4P3/R3K3 w 0 a--- - - - - - 3").unwrap();
+        assert!(identical(&board, &expected));
+    }
+
+    /// Version 2 stored hashes we can't reproduce, so its history is dropped.
+    #[test]
+    fn load_version_2_without_history() {
+        let mut json = serde_json::to_value(&DenseBoard::new()).unwrap();
+        json["version"] = "2".into();
+        json["history"] = serde_json::json!([1, 2, 3]);
+        let loaded: DenseBoard = serde_json::from_value(json).unwrap();
+        assert!(identical(&loaded, &DenseBoard::new()));
+    }
+
+    #[test]
+    fn reject_unknown_version_and_bad_squares() {
+        let json = serde_json::to_value(&DenseBoard::new()).unwrap();
+
+        let mut unknown = json.clone();
+        unknown["version"] = "0".into();
+        assert!(serde_json::from_value::<DenseBoard>(unknown).is_err());
+
+        let mut short = json;
+        short["white"] = serde_json::Value::Array(vec![]);
+        let error = serde_json::from_value::<DenseBoard>(short).unwrap_err();
+        assert!(error.to_string().contains("64 squares"));
+    }
+
+    /// Boards from random games, including finished games and boards in the
+    /// middle of a chain, survive a round trip.
+    #[quickcheck]
+    fn round_trip_random_game(seed: u64) -> bool {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut board: DenseBoard = if rng.gen_bool(0.5) {
+            DenseBoard::new()
+        } else {
+            rng.gen()
+        };
+
+        for _ in 0..200 {
+            if !identical(&round_trip(&board), &board) {
+                return false;
+            }
+            let actions = board.actions().unwrap();
+            match actions.choose(&mut rng) {
+                Some(&action) => board.execute_trusted(action).unwrap(),
+                None => break,
+            };
+        }
+        identical(&round_trip(&board), &board)
+    }
+}
@@ -0,0 +1,303 @@
+//! Proves or disproves that the side to move can force a Paco Ŝako within a
+//! number of turns. This is used to verify "win in N" puzzles.
+//!
+//! The solver tries all turns of the attacker and all answers of the defender.
+//! A turn only counts as winning when every answer can be refuted again. It
+//! looks for a win in one turn first, then in two turns and so on, so the
+//! first win it finds is also the shortest one.
+
+use crate::{
+    legal_moves, DeadEnds, DenseBoard, DrawState, PacoAction, PacoBoard, PacoError, VictoryState,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Decides how far the solver looks.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct SolverLimits {
+    /// Maximum number of turns of the attacker, including the winning turn.
+    pub max_turns: usize,
+    #[serde(default)]
+    pub node_limit: Option<u64>,
+}
+
+impl Default for SolverLimits {
+    fn default() -> Self {
+        SolverLimits {
+            max_turns: 2,
+            node_limit: None,
+        }
+    }
+}
+
+/// A proven forced win: The attacker plays `turn` and has an answer to every
+/// defence.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ForcedWin {
+    pub turn: Vec<PacoAction>,
+    /// How the attacker continues after each defence. This is empty when
+    /// `turn` already unites with the king.
+    pub refutations: Vec<Refutation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Refutation {
+    pub defence: Vec<PacoAction>,
+    pub win: ForcedWin,
+}
+
+impl ForcedWin {
+    /// The number of attacker turns needed against the best defence.
+    pub fn turns(&self) -> usize {
+        1 + self
+            .refutations
+            .iter()
+            .map(|r| r.win.turns())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The turns of both players when the defender always picks the defence
+    /// that resists the longest.
+    pub fn main_line(&self) -> Vec<Vec<PacoAction>> {
+        let mut line = vec![self.turn.clone()];
+        // max_by_key returns the last maximum, we want the first one.
+        let longest = self.refutations.iter().rev().max_by_key(|r| r.win.turns());
+        if let Some(refutation) = longest {
+            line.push(refutation.defence.clone());
+            line.extend(refutation.win.main_line());
+        }
+        line
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Solution {
+    /// The side to move can force a win within the turn limit.
+    Win(ForcedWin),
+    /// There is no forced win within the turn limit.
+    NoWin,
+    /// The node limit was reached before the question was decided.
+    Unknown,
+}
+
+/// Why the search was interrupted.
+enum Interrupt {
+    NodeLimit,
+    Error(PacoError),
+}
+
+impl From<PacoError> for Interrupt {
+    fn from(error: PacoError) -> Self {
+        Interrupt::Error(error)
+    }
+}
+
+pub struct Solver {
+    pub limits: SolverLimits,
+    nodes: u64,
+    /// Positions where the attacker is known to have no win in the given
+    /// number of turns. Board equality ignores the draw state, but repetitions
+    /// and the no progress counter decide whether a line ends in a draw, so
+    /// the draw state is part of the key.
+    no_win: HashSet<(DenseBoard, DrawState, usize)>,
+}
+
+impl Solver {
+    pub fn new(limits: SolverLimits) -> Self {
+        Solver {
+            limits,
+            nodes: 0,
+            no_win: HashSet::new(),
+        }
+    }
+
+    /// Number of positions the last call to `solve` looked at.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Looks for a forced win of the player that controls the board.
+    pub fn solve(&mut self, board: &DenseBoard) -> Result<Solution, PacoError> {
+        self.nodes = 0;
+        self.no_win.clear();
+
+        for turns in 1..=self.limits.max_turns {
+            match self.win_in(board, turns) {
+                Ok(Some(win)) => return Ok(Solution::Win(win)),
+                Ok(None) => {}
+                Err(Interrupt::NodeLimit) => return Ok(Solution::Unknown),
+                Err(Interrupt::Error(error)) => return Err(error),
+            }
+        }
+        Ok(Solution::NoWin)
+    }
+
+    fn visit(&mut self) -> Result<(), Interrupt> {
+        self.nodes += 1;
+        match self.limits.node_limit {
+            Some(limit) if self.nodes > limit => Err(Interrupt::NodeLimit),
+            _ => Ok(()),
+        }
+    }
+
+    /// Finds a win for the controlling player in at most `turns` turns.
+    fn win_in(&mut self, board: &DenseBoard, turns: usize) -> Result<Option<ForcedWin>, Interrupt> {
+        let key = (board.clone(), board.draw_state.clone(), turns);
+        if self.no_win.contains(&key) {
+            return Ok(None);
+        }
+        self.visit()?;
+
+        let attacker = board.controlling_player();
+        let moves = legal_moves(board, DeadEnds::Exclude)?;
+
+        // Winning right away is always the best.
+        for m in &moves {
+            if m.board.victory_state() == VictoryState::PacoVictory(attacker) {
+                return Ok(Some(ForcedWin {
+                    turn: m.actions.clone(),
+                    refutations: vec![],
+                }));
+            }
+        }
+
+        if turns > 1 {
+            'attack: for m in moves {
+                if m.board.victory_state().is_over() {
+                    continue;
+                }
+                self.visit()?;
+                let defences = legal_moves(&m.board, DeadEnds::Exclude)?;
+                if defences.is_empty() {
+                    continue;
+                }
+
+                let mut refutations = Vec::with_capacity(defences.len());
+                for defence in defences {
+                    if defence.board.victory_state().is_over() {
+                        continue 'attack;
+                    }
+                    match self.win_in(&defence.board, turns - 1)? {
+                        Some(win) => refutations.push(Refutation {
+                            defence: defence.actions,
+                            win,
+                        }),
+                        None => continue 'attack,
+                    }
+                }
+                return Ok(Some(ForcedWin {
+                    turn: m.actions,
+                    refutations,
+                }));
+            }
+        }
+
+        self.no_win.insert(key);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::BoardPosition;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn limits(max_turns: usize) -> SolverLimits {
+        SolverLimits {
+            max_turns,
+            ..SolverLimits::default()
+        }
+    }
+
+    /// White to move, the black king in the corner can be trapped by a rook.
+    const WIN_IN_TWO: &str = "k7/8/8/8/8/8/7R/1R5K w 0 ---- - - - - -";
+
+    #[test]
+    fn win_in_one() {
+        let board = parse_fen("r6k/8/8/8/8/8/8/K7 b 0 ---- - - - - -").unwrap();
+        match Solver::new(limits(1)).solve(&board).unwrap() {
+            Solution::Win(win) => {
+                assert_eq!(win.turns(), 1);
+                assert_eq!(
+                    win.main_line(),
+                    vec![vec![
+                        PacoAction::Lift(pos("a8")),
+                        PacoAction::Place(pos("a1"))
+                    ]]
+                );
+            }
+            other => panic!("Expected a win, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn win_in_two() {
+        let board = parse_fen(WIN_IN_TWO).unwrap();
+        assert_eq!(
+            Solver::new(limits(1)).solve(&board).unwrap(),
+            Solution::NoWin
+        );
+
+        match Solver::new(limits(2)).solve(&board).unwrap() {
+            Solution::Win(win) => {
+                assert_eq!(win.turns(), 2);
+                // Every move of the black king is refuted right away.
+                assert!(!win.refutations.is_empty());
+                assert!(win.refutations.iter().all(|r| r.win.turns() == 1));
+                let line = win.main_line();
+                assert_eq!(line.len(), 3);
+
+                let mut replay = board.clone();
+                for action in line.iter().flatten() {
+                    replay.execute(*action).unwrap();
+                }
+                assert_eq!(
+                    replay.victory_state(),
+                    VictoryState::PacoVictory(crate::PlayerColor::White)
+                );
+            }
+            other => panic!("Expected a win, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_win_from_the_start() {
+        let mut solver = Solver::new(limits(1));
+        assert_eq!(solver.solve(&DenseBoard::new()).unwrap(), Solution::NoWin);
+        assert_eq!(solver.nodes(), 1);
+    }
+
+    /// A result for one history must not be used for the same position with
+    /// another history, where a draw may come earlier or later.
+    #[test]
+    fn cache_respects_the_draw_state() {
+        let board = parse_fen("r6k/8/8/8/8/8/8/K7 b 0 ---- - - - - -").unwrap();
+        let mut other_history = board.clone();
+        other_history.draw_state.no_progress_half_moves = 5;
+        assert_eq!(board, other_history);
+
+        let mut solver = Solver::new(limits(1));
+        solver
+            .no_win
+            .insert((other_history.clone(), other_history.draw_state.clone(), 1));
+        assert!(matches!(solver.win_in(&board, 1), Ok(Some(_))));
+        assert!(matches!(solver.win_in(&other_history, 1), Ok(None)));
+    }
+
+    #[test]
+    fn node_limit() {
+        let board = parse_fen(WIN_IN_TWO).unwrap();
+        let mut solver = Solver::new(SolverLimits {
+            max_turns: 2,
+            node_limit: Some(3),
+        });
+        assert_eq!(solver.solve(&board).unwrap(), Solution::Unknown);
+    }
+}
@@ -0,0 +1,41 @@
+//! Generators for the property tests.
+
+use crate::{DenseBoard, PacoBoard};
+use quickcheck::{Arbitrary, Gen};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Quickcheck uses a different version of rand, so we only take a seed. The
+/// seed comes from the whole range: `u64::arbitrary` stays below the size of
+/// the generator and would give the same hundred boards on every run.
+pub fn rng<G: Gen>(g: &mut G) -> StdRng {
+    StdRng::seed_from_u64(g.next_u64())
+}
+
+/// A board in some random state that is reached by executing random
+/// actions on a random board.
+#[derive(Clone, Debug)]
+pub struct RandomBoard(pub DenseBoard);
+
+impl Arbitrary for RandomBoard {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut rng = rng(g);
+        let mut board: DenseBoard = rng.gen();
+        let action_count = rng.gen_range(0, 40);
+        for _ in 0..action_count {
+            let actions = board.actions().unwrap();
+            if actions.is_empty() {
+                break;
+            }
+            let action = actions[rng.gen_range(0, actions.len())];
+            board.execute_trusted(action).unwrap();
+        }
+        RandomBoard(board)
+    }
+}
+
+/// Like `==`, but also compares the draw state. Tests use this where a board
+/// has to be restored exactly, e.g. by undo or a round trip.
+pub fn identical(a: &DenseBoard, b: &DenseBoard) -> bool {
+    a == b && a.draw_state == b.draw_state
+}
@@ -0,0 +1,260 @@
+//! A threat map lists for every square the pieces that could unite with it
+//! during the next turn and the chains that get them there. This is what the
+//! editor needs to draw attack overlays and what explains why a king may not
+//! castle through a square.
+//!
+//! Like `determine_all_threats`, the map follows the rules for threats: Kings
+//! never threaten, as they can not take part in a union or a chain. A move to
+//! an empty square counts as a threat when the piece could unite there, so
+//! pawns threaten diagonally but not forward.
+
+use crate::{
+    BoardPosition, DenseBoard, EditorBoard, PacoAction, PacoBoard, PacoError, PieceType,
+    PlayerColor,
+};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use wasm_bindgen::prelude::*;
+
+/// Whether a threat needs a chain to reach its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum ThreatKind {
+    /// A piece is lifted and placed on the target.
+    Direct,
+    /// The piece reaching the target was picked up from a union on the way.
+    Chain,
+}
+
+/// One way for a player to reach a square.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Attack {
+    /// The square where the turn starts by lifting a piece.
+    pub origin: BoardPosition,
+    /// The piece that is placed on the target square. For chains, this is
+    /// the last piece of the chain.
+    pub piece: PieceType,
+    pub kind: ThreatKind,
+    /// All actions of the turn up to and including placing on the target.
+    pub actions: Vec<PacoAction>,
+}
+
+impl Attack {
+    /// The squares the turn visits, from the origin to the target.
+    pub fn path(&self) -> Vec<BoardPosition> {
+        self.actions
+            .iter()
+            .filter_map(PacoAction::position)
+            .collect()
+    }
+
+    /// The square that is threatened.
+    pub fn target(&self) -> BoardPosition {
+        // The last action is always a place action.
+        self.actions.last().and_then(PacoAction::position).unwrap()
+    }
+}
+
+/// All attacks of both players, sorted by target square.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ThreatMap {
+    white: Vec<Vec<Attack>>,
+    black: Vec<Vec<Attack>>,
+}
+
+impl ThreatMap {
+    /// All attacks of the given player on a square.
+    pub fn attacks(&self, color: PlayerColor, position: BoardPosition) -> &[Attack] {
+        &self.of_color(color)[position.0 as usize]
+    }
+
+    /// Can the given player reach the square during their next turn?
+    pub fn is_threatened(&self, color: PlayerColor, position: BoardPosition) -> bool {
+        !self.attacks(color, position).is_empty()
+    }
+
+    /// Can the given player reach the square without a chain?
+    pub fn is_threatened_directly(&self, color: PlayerColor, position: BoardPosition) -> bool {
+        self.attacks(color, position)
+            .iter()
+            .any(|a| a.kind == ThreatKind::Direct)
+    }
+
+    /// All squares the given player threatens.
+    pub fn threatened_squares(&self, color: PlayerColor) -> Vec<BoardPosition> {
+        (0..64)
+            .map(BoardPosition)
+            .filter(|&p| self.is_threatened(color, p))
+            .collect()
+    }
+
+    fn of_color(&self, color: PlayerColor) -> &Vec<Vec<Attack>> {
+        match color {
+            PlayerColor::White => &self.white,
+            PlayerColor::Black => &self.black,
+        }
+    }
+}
+
+/// Determines the threats of both players. The threats of the player that is
+/// not to move are determined as if it was their turn.
+pub fn threat_map(board: &DenseBoard) -> Result<ThreatMap, PacoError> {
+    let mut white = board.clone();
+    white.current_player = PlayerColor::White;
+    let mut black = board.clone();
+    black.current_player = PlayerColor::Black;
+
+    Ok(ThreatMap {
+        white: attacks_of_current_player(&white)?,
+        black: attacks_of_current_player(&black)?,
+    })
+}
+
+/// This follows the same search as `determine_all_threats`, but remembers the
+/// actions that lead to each state. Every state is only visited once, so only
+/// the shortest chain to each state is reported.
+fn attacks_of_current_player(board: &DenseBoard) -> Result<Vec<Vec<Attack>>, PacoError> {
+    let mut result = vec![Vec::new(); 64];
+
+    let mut todo_list: VecDeque<(DenseBoard, Vec<PacoAction>)> = VecDeque::new();
+    let mut seen: HashSet<DenseBoard> = HashSet::new();
+    for action in board.threat_actions() {
+        let mut b = board.clone();
+        b.execute_trusted(action)?;
+        todo_list.push_back((b, vec![action]));
+    }
+
+    while let Some((todo, trace)) = todo_list.pop_front() {
+        for action in todo.threat_actions() {
+            if let (PacoAction::Place(target), Some(piece)) = (action, todo.lifted_piece.piece()) {
+                let mut actions = trace.clone();
+                actions.push(action);
+                let origin = actions[0].position().unwrap();
+                let places = actions
+                    .iter()
+                    .filter(|a| matches!(a, PacoAction::Place(_)))
+                    .count();
+                let kind = if places == 1 {
+                    ThreatKind::Direct
+                } else {
+                    ThreatKind::Chain
+                };
+                result[target.0 as usize].push(Attack {
+                    origin,
+                    piece,
+                    kind,
+                    actions,
+                });
+            }
+        }
+
+        for action in todo.actions()? {
+            let follow = match action {
+                PacoAction::Place(target) => {
+                    let (white, black) = todo.get_at(target);
+                    (white.is_some() && black.is_some()) || todo.en_passant_capture_possible()
+                }
+                PacoAction::Promote(_) => true,
+                PacoAction::Lift(_) => false,
+            };
+            if !follow {
+                continue;
+            }
+            let mut b = todo.clone();
+            b.execute_trusted(action)?;
+            if !b.is_settled() && !seen.contains(&b) {
+                seen.insert(b.clone());
+                let mut actions = trace.clone();
+                actions.push(action);
+                todo_list.push_back((b, actions));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Entry point for the editor. Takes a JSON encoded `EditorBoard` and returns
+/// the JSON encoded `ThreatMap` or an error.
+#[wasm_bindgen]
+pub fn threat_map_json(board: &str) -> String {
+    let result = serde_json::from_str(board)
+        .map_err(|_| PacoError::InputJsonMalformed)
+        .and_then(|board: EditorBoard| {
+            let player = board.state().current_player.unwrap_or(PlayerColor::White);
+            threat_map(&board.with_active_player(player)?)
+        });
+
+    match result {
+        Ok(map) => serde_json::to_string(&map).unwrap(),
+        Err(error) => serde_json::to_string(&error).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::determine_all_threats;
+    use crate::fen::parse_fen;
+    use crate::testing::RandomBoard;
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    #[test]
+    fn initial_position() {
+        let map = threat_map(&DenseBoard::new()).unwrap();
+        // Squares with own pieces count as well, as the opponent may move
+        // there and then be united with.
+        assert_eq!(map.threatened_squares(PlayerColor::White).len(), 19);
+        assert_eq!(map.threatened_squares(PlayerColor::Black).len(), 19);
+        let attacks = map.attacks(PlayerColor::White, pos("c3"));
+        assert_eq!(attacks.len(), 3);
+        assert!(attacks.iter().all(|a| a.kind == ThreatKind::Direct));
+        assert!(!map.is_threatened(PlayerColor::White, pos("e4")));
+    }
+
+    #[test]
+    fn chain_threats() {
+        // The white rook can take over the union on d4 and then the white
+        // bishop from the union reaches f6.
+        let board = parse_fen("4k3/8/8/8/R2e4/8/8/4K3 w 0 ---- - - - - -").unwrap();
+        let map = threat_map(&board).unwrap();
+
+        let attacks = map.attacks(PlayerColor::White, pos("f6"));
+        assert_eq!(attacks.len(), 1);
+        assert_eq!(attacks[0].kind, ThreatKind::Chain);
+        assert_eq!(attacks[0].origin, pos("a4"));
+        assert_eq!(attacks[0].piece, PieceType::Bishop);
+        assert_eq!(attacks[0].path(), vec![pos("a4"), pos("d4"), pos("f6")]);
+        assert_eq!(attacks[0].target(), pos("f6"));
+
+        assert!(map.is_threatened_directly(PlayerColor::White, pos("d4")));
+        assert!(!map.is_threatened_directly(PlayerColor::White, pos("f6")));
+    }
+
+    #[test]
+    fn kings_do_not_threaten() {
+        let board = parse_fen("4k3/8/8/8/8/8/8/4K3 w 0 ---- - - - - -").unwrap();
+        let map = threat_map(&board).unwrap();
+        assert!(map.threatened_squares(PlayerColor::White).is_empty());
+        assert!(map.threatened_squares(PlayerColor::Black).is_empty());
+    }
+
+    /// The map marks the same squares as the internal threat search. Both
+    /// only make sense for a board where the turn is finished.
+    #[quickcheck]
+    fn map_agrees_with_threats(board: RandomBoard) -> bool {
+        if !board.0.is_settled() || board.0.promotion.is_some() {
+            return true;
+        }
+        let map = threat_map(&board.0).unwrap();
+        let mut attacker = board.0.clone();
+        attacker.current_player = PlayerColor::Black;
+        let threats = determine_all_threats(&attacker).unwrap();
+        (0..64).all(|i| {
+            map.is_threatened(PlayerColor::Black, BoardPosition(i)) == threats[i as usize].0
+        })
+    }
+}
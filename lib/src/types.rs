@@ -15,6 +15,35 @@ pub enum PieceType {
     King,
 }
 
+impl PieceType {
+    /// The upper case letter of the piece, as in chess notation.
+    pub fn letter(self) -> char {
+        use PieceType::*;
+        match self {
+            Pawn => 'P',
+            Rook => 'R',
+            Knight => 'N',
+            Bishop => 'B',
+            Queen => 'Q',
+            King => 'K',
+        }
+    }
+
+    /// Reads an upper case piece letter.
+    pub fn from_letter(letter: char) -> Option<Self> {
+        use PieceType::*;
+        match letter {
+            'P' => Some(Pawn),
+            'R' => Some(Rook),
+            'N' => Some(Knight),
+            'B' => Some(Bishop),
+            'Q' => Some(Queen),
+            'K' => Some(King),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PlayerColor {
     White,
@@ -0,0 +1,206 @@
+//! Make and unmake for the `DenseBoard`. Searches can execute an action, look
+//! at the result and undo it again instead of cloning the board each time.
+//!
+//! An action changes at most three squares: Castling moves the king and the
+//! rook, uniting en passant pulls back the opponent pawn. The king's starting
+//! square is already empty when it is placed. The undo record
+//! stores those squares together with all other state of the board.
+
+use crate::{
+    BoardPosition, Castling, DenseBoard, Hand, PacoAction, PieceType, PlayerColor, Progress,
+    VictoryState,
+};
+
+/// Contents of a square, (white piece, black piece).
+type SquareState = (BoardPosition, Option<PieceType>, Option<PieceType>);
+
+/// Returned by `execute_trusted` to restore the board with `undo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndoRecord {
+    squares: [Option<SquareState>; 3],
+    current_player: PlayerColor,
+    lifted_piece: Hand,
+    en_passant: Option<(BoardPosition, PlayerColor)>,
+    promotion: Option<BoardPosition>,
+    castling: Castling,
+    victory_state: VictoryState,
+    no_progress_half_moves: u16,
+    turn_progress: Progress,
+    history_length: usize,
+    /// The position history, if the action cleared it.
+    cleared_history: Option<Vec<u64>>,
+}
+
+impl UndoRecord {
+    /// Remembers everything `action` may change on the board.
+    pub(crate) fn new(board: &DenseBoard, action: PacoAction) -> Self {
+        let mut squares = [None; 3];
+        for (slot, position) in squares
+            .iter_mut()
+            .zip(touched_squares(board, action).iter())
+        {
+            *slot = position.map(|p| {
+                let (white, black) = (board.white[p.0 as usize], board.black[p.0 as usize]);
+                (p, white, black)
+            });
+        }
+
+        UndoRecord {
+            squares,
+            current_player: board.current_player,
+            lifted_piece: board.lifted_piece.clone(),
+            en_passant: board.en_passant,
+            promotion: board.promotion,
+            castling: board.castling,
+            victory_state: board.victory_state,
+            no_progress_half_moves: board.draw_state.no_progress_half_moves,
+            turn_progress: board.draw_state.turn_progress,
+            history_length: board.draw_state.history.len(),
+            cleared_history: None,
+        }
+    }
+
+    /// Must be called with the history that `finish_turn` removed.
+    pub(crate) fn set_cleared_history(&mut self, history: Option<Vec<u64>>) {
+        self.cleared_history = history;
+    }
+
+    /// Puts the board back into the state before the action.
+    pub(crate) fn restore(self, board: &mut DenseBoard) {
+        for &(position, white, black) in self.squares.iter().flatten() {
+            board.white[position.0 as usize] = white;
+            board.black[position.0 as usize] = black;
+        }
+        board.current_player = self.current_player;
+        board.lifted_piece = self.lifted_piece;
+        board.en_passant = self.en_passant;
+        board.promotion = self.promotion;
+        board.castling = self.castling;
+        board.victory_state = self.victory_state;
+
+        let draw_state = &mut board.draw_state;
+        draw_state.no_progress_half_moves = self.no_progress_half_moves;
+        draw_state.turn_progress = self.turn_progress;
+        match self.cleared_history {
+            Some(history) => draw_state.history = history,
+            None => draw_state.history.truncate(self.history_length),
+        }
+    }
+}
+
+/// The squares that executing `action` on `board` may change.
+fn touched_squares(board: &DenseBoard, action: PacoAction) -> [Option<BoardPosition>; 3] {
+    match action {
+        PacoAction::Lift(position) => [Some(position), None, None],
+        PacoAction::Promote(_) => [board.promotion, None, None],
+        PacoAction::Place(target) => {
+            // Uniting en passant pulls the opponent pawn onto the target.
+            if let Some((square, _)) = board.en_passant {
+                if square == target {
+                    let source = target.advance_pawn(board.current_player.other());
+                    return [Some(target), source, None];
+                }
+            }
+            // Castling moves the rook as well.
+            if let Hand::Single {
+                piece: PieceType::King,
+                position,
+            } = board.lifted_piece
+            {
+                let player = board.current_player;
+                if let Some(side) = board.castling_side(position, target) {
+                    return [
+                        Some(Castling::king_destination(player, side)),
+                        Some(board.castling.rook_square(player, side)),
+                        Some(Castling::rook_destination(player, side)),
+                    ];
+                }
+            }
+            [Some(target), None, None]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::testing::identical;
+    use crate::PacoBoard;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    /// Executes every action sequence up to the given depth in place and
+    /// checks that undo restores the same board as the clone before.
+    fn assert_undo_restores(board: &mut DenseBoard, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        for action in board.actions().unwrap() {
+            let before = board.clone();
+            let record = board.execute_trusted(action).unwrap();
+            assert_undo_restores(board, depth - 1);
+            board.undo(record);
+            assert!(identical(board, &before), "Undo of {:?} failed.", action);
+        }
+    }
+
+    #[test]
+    fn start_position() {
+        assert_undo_restores(&mut DenseBoard::new(), 4);
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w 0 ahah - - - - -";
+        assert_undo_restores(&mut parse_fen(fen).unwrap(), 4);
+    }
+
+    #[test]
+    fn fischer_random_castling() {
+        let fen = "4k3/8/8/8/8/8/8/RK5R w 0 ah-- - - - - -";
+        assert_undo_restores(&mut parse_fen(fen).unwrap(), 4);
+    }
+
+    #[test]
+    fn en_passant_chain() {
+        let fen = "7k/8/8/8/2pD4/8/8/4K3 b 0 ahah d3 - - - -";
+        assert_undo_restores(&mut parse_fen(fen).unwrap(), 4);
+    }
+
+    #[test]
+    fn promotion_chain() {
+        let fen = "4e3/3P1e2/3k4/8/8/8/8/K7 w 0 ahah - - - - -";
+        assert_undo_restores(&mut parse_fen(fen).unwrap(), 4);
+    }
+
+    /// Plays a random game, then takes back all actions one by one.
+    #[quickcheck]
+    fn undo_random_game(seed: u64) -> bool {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut board: DenseBoard = if rng.gen_bool(0.5) {
+            DenseBoard::new()
+        } else {
+            rng.gen()
+        };
+
+        let mut history = Vec::new();
+        for _ in 0..300 {
+            let actions = board.actions().unwrap();
+            let action = match actions.choose(&mut rng) {
+                Some(action) => *action,
+                None => break,
+            };
+            let before = board.clone();
+            let record = board.execute_trusted(action).unwrap();
+            history.push((before, record));
+        }
+
+        while let Some((before, record)) = history.pop() {
+            board.undo(record);
+            if !identical(&board, &before) {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,283 @@
+//! Checks if a board can occur in a game. Boards from the editor, from fen or
+//! from the exchange notation may contain anything, but move generation and
+//! the analysis assume that each player has exactly one king and so on.
+
+use crate::{
+    BoardPosition, DenseBoard, EditorBoard, Hand, PacoError, PieceType, PlayerColor, VictoryState,
+};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum PositionProblem {
+    #[error("{0:?} has no king.")]
+    MissingKing(PlayerColor),
+    #[error("{0:?} has {1} kings.")]
+    DuplicateKing(PlayerColor, usize),
+    #[error("The {0:?} king on {1} is in a union.")]
+    KingInUnion(PlayerColor, BoardPosition),
+    #[error("The single {0:?} pawn on {1} can never have reached its own home row.")]
+    PawnOnHomeRow(PlayerColor, BoardPosition),
+    #[error("The {0:?} pawn on {1} should have been promoted.")]
+    UnpromotedPawn(PlayerColor, BoardPosition),
+    #[error("{0:?} may castle, but the king or the rook left its square.")]
+    CastlingRights(PlayerColor),
+    #[error("{0} is not a possible en passant square.")]
+    EnPassant(BoardPosition),
+    #[error("{0:?} is not to move, but already in ŝako.")]
+    OpponentInSako(PlayerColor),
+    #[error("There are pieces in the hand, but no player to move who holds them.")]
+    HandWithoutPlayer,
+}
+
+impl DenseBoard {
+    /// Lists all reasons why this board can not occur in a game. An empty
+    /// list means that the board can be played and analysed.
+    pub fn validate(&self) -> Vec<PositionProblem> {
+        let mut problems = vec![];
+        for &color in &[PlayerColor::White, PlayerColor::Black] {
+            self.validate_king(color, &mut problems);
+            self.validate_pawns(color, &mut problems);
+            if self.castling_rights_broken(color) {
+                problems.push(PositionProblem::CastlingRights(color));
+            }
+        }
+        if let Some((square, player)) = self.en_passant {
+            let pawn = square.advance_pawn(player);
+            let row = match player {
+                PlayerColor::White => 2,
+                PlayerColor::Black => 5,
+            };
+            if square.y() != row
+                || pawn.map(|p| self.pieces_of_color(player)[p.0 as usize])
+                    != Some(Some(PieceType::Pawn))
+            {
+                problems.push(PositionProblem::EnPassant(square));
+            }
+        }
+
+        // The search for ŝako needs sound kings and a finished turn.
+        let opponent = self.current_player.other();
+        if problems.is_empty()
+            && self.victory_state == VictoryState::Running
+            && self.lifted_piece == Hand::Empty
+            && self.promotion.is_none()
+            && self
+                .sako_threats(opponent)
+                .is_ok_and(|threats| !threats.is_empty())
+        {
+            problems.push(PositionProblem::OpponentInSako(opponent));
+        }
+        problems
+    }
+
+    fn validate_king(&self, color: PlayerColor, problems: &mut Vec<PositionProblem>) {
+        let kings: Vec<BoardPosition> = (0..64)
+            .filter(|&i| self.pieces_of_color(color)[i] == Some(PieceType::King))
+            .map(|i| BoardPosition(i as u8))
+            .collect();
+        match kings.len() {
+            0 => problems.push(PositionProblem::MissingKing(color)),
+            1 => {}
+            n => problems.push(PositionProblem::DuplicateKing(color, n)),
+        }
+
+        // The king of the loser is in a union once the game is won.
+        if self.victory_state == VictoryState::PacoVictory(color.other()) {
+            return;
+        }
+        for king in kings {
+            if self.pieces_of_color(color.other())[king.0 as usize].is_some() {
+                problems.push(PositionProblem::KingInUnion(color, king));
+            }
+        }
+    }
+
+    fn validate_pawns(&self, color: PlayerColor, problems: &mut Vec<PositionProblem>) {
+        for i in 0..64 {
+            if self.pieces_of_color(color)[i] != Some(PieceType::Pawn) {
+                continue;
+            }
+            let position = BoardPosition(i as u8);
+            match position.home_row() {
+                // A pawn only gets back to its home row when it is carried
+                // there in a union.
+                Some(row) if row == color && self.pieces_of_color(color.other())[i].is_none() => {
+                    problems.push(PositionProblem::PawnOnHomeRow(color, position));
+                }
+                Some(row) if row != color && self.promotion != Some(position) => {
+                    problems.push(PositionProblem::UnpromotedPawn(color, position));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn castling_rights_broken(&self, color: PlayerColor) -> bool {
+        use crate::CastlingSide::*;
+        [Queen, King].iter().any(|&side| {
+            self.castling.has_right(color, side) && !self.castling_possible(color, side)
+        })
+    }
+}
+
+impl EditorBoard {
+    /// Validates the board of the editor. When the editor does not say who
+    /// is to move, the analysis looks for ŝako in both directions, so a player
+    /// in ŝako is not a problem then.
+    pub fn validate(&self) -> Result<Vec<PositionProblem>, PacoError> {
+        let board =
+            self.with_active_player(self.state().current_player.unwrap_or(PlayerColor::White))?;
+        let either_player = self.state().current_player.is_none();
+        Ok(board
+            .validate()
+            .into_iter()
+            .filter(|problem| {
+                !(either_player && matches!(problem, PositionProblem::OpponentInSako(_)))
+            })
+            .collect())
+    }
+}
+
+/// Entry point for the editor. Takes a JSON encoded `EditorBoard` and returns
+/// the list of problems.
+#[wasm_bindgen]
+pub fn validate_json(board: &str) -> String {
+    let result = serde_json::from_str(board)
+        .map_err(|_| PacoError::InputJsonMalformed)
+        .and_then(|board: EditorBoard| board.validate());
+
+    match result {
+        Ok(problems) => serde_json::to_string(&problems).unwrap(),
+        Err(error) => serde_json::to_string(&error).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::{find_sako_sequences, EditorState, PacoAction, PacoBoard};
+    use std::convert::TryFrom;
+
+    fn pos(identifier: &str) -> BoardPosition {
+        BoardPosition::try_from(identifier).unwrap()
+    }
+
+    fn problems(fen: &str) -> Vec<PositionProblem> {
+        parse_fen(fen).unwrap().validate()
+    }
+
+    #[test]
+    fn start_position_is_valid() {
+        assert_eq!(DenseBoard::new().validate(), vec![]);
+    }
+
+    #[test]
+    fn kings() {
+        use PositionProblem::*;
+        assert_eq!(
+            problems("8/8/8/8/8/8/8/4K3 w 0 ---- - - - - -"),
+            vec![MissingKing(PlayerColor::Black)]
+        );
+        assert_eq!(
+            problems("k6k/8/8/8/8/8/8/K1K1K3 w 0 ---- - - - - -"),
+            vec![
+                DuplicateKing(PlayerColor::White, 3),
+                DuplicateKing(PlayerColor::Black, 2)
+            ]
+        );
+        // A black king united with a white rook.
+        assert_eq!(
+            problems("4M3/8/8/8/8/8/8/4K3 w 0 ---- - - - - -"),
+            vec![KingInUnion(PlayerColor::Black, pos("e8"))]
+        );
+    }
+
+    #[test]
+    fn pawns() {
+        use PositionProblem::*;
+        assert_eq!(
+            problems("3Pk3/8/8/8/8/8/8/3PKp2 w 0 ---- - - - - -"),
+            vec![
+                PawnOnHomeRow(PlayerColor::White, pos("d1")),
+                UnpromotedPawn(PlayerColor::White, pos("d8")),
+                UnpromotedPawn(PlayerColor::Black, pos("f1"))
+            ]
+        );
+        // Pawns in a union may be carried back, a pending promotion is fine.
+        assert_eq!(
+            problems("3Pk3/8/8/8/8/8/8/2D1K3 b 0 ---- - - - d8 -"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn castling_and_en_passant() {
+        use PositionProblem::*;
+        assert_eq!(
+            problems("r3k3/8/8/8/8/8/8/R4RK1 w 0 ahah - - - - -"),
+            vec![
+                CastlingRights(PlayerColor::White),
+                CastlingRights(PlayerColor::Black)
+            ]
+        );
+        assert_eq!(
+            problems("4k3/8/8/8/3P4/8/8/4K3 b 0 ---- d3 - - - -"),
+            vec![]
+        );
+        assert_eq!(
+            problems("4k3/8/8/8/8/8/8/4K3 b 0 ---- d3 - - - -"),
+            vec![EnPassant(pos("d3"))]
+        );
+    }
+
+    #[test]
+    fn opponent_in_sako() {
+        assert_eq!(
+            problems("4k3/8/8/8/8/8/8/4RK2 w 0 ---- - - - - -"),
+            vec![PositionProblem::OpponentInSako(PlayerColor::Black)]
+        );
+        assert_eq!(problems("4k3/8/8/8/8/8/8/4RK2 b 0 ---- - - - - -"), vec![]);
+    }
+
+    #[test]
+    fn won_game_is_valid() {
+        let mut board = parse_fen("4k3/8/8/8/8/8/8/4RK2 w 0 ---- - - - - -").unwrap();
+        board.execute(PacoAction::Lift(pos("e1"))).unwrap();
+        board.execute(PacoAction::Place(pos("e8"))).unwrap();
+        assert_eq!(board.validate(), vec![]);
+    }
+
+    #[test]
+    fn editor_rejects_invalid_boards() {
+        let board: EditorBoard =
+            (&parse_fen("8/8/8/8/8/8/8/4K3 w 0 ---- - - - - -").unwrap()).into();
+        // Used to panic without a king.
+        assert!(!board
+            .with_active_player(PlayerColor::White)
+            .unwrap()
+            .king_in_union(PlayerColor::Black));
+        assert_eq!(
+            find_sako_sequences(&board).err(),
+            Some(PacoError::InvalidPosition(vec![
+                PositionProblem::MissingKing(PlayerColor::Black)
+            ]))
+        );
+    }
+
+    #[test]
+    fn editor_without_player_to_move() {
+        let board: EditorBoard =
+            (&parse_fen("4k3/8/8/8/8/8/8/4RK2 w 0 ---- - - - - -").unwrap()).into();
+        assert_eq!(
+            board.validate(),
+            Ok(vec![PositionProblem::OpponentInSako(PlayerColor::Black)])
+        );
+
+        // The editor shows ŝako for both players.
+        let board = board.with_state(EditorState::default());
+        assert_eq!(board.validate(), Ok(vec![]));
+        assert_eq!(find_sako_sequences(&board).unwrap().white.len(), 1);
+    }
+}
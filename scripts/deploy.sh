@@ -8,6 +8,11 @@ cd backend
 cargo build --release
 cd ..
 
+# Build the rules library to webassembly for the AI worker.
+cd lib
+wasm-pack build --target no-modules --out-dir ../target --out-name pacosako
+cd ..
+
 # Build typescript from the frontend. Elm was already build previously.
 tsc
 